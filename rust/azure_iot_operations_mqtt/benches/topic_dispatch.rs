@@ -0,0 +1,86 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Benchmarks for finding the topic filters that match an incoming topic name, comparing a linear
+//! scan of all topic filters against the [`TopicTree`] index used to dispatch incoming publishes.
+
+use std::hint::black_box;
+use std::str::FromStr;
+
+use azure_iot_operations_mqtt::topic::{TopicFilter, TopicName, TopicTree};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+
+/// Topic filters as registered by an application with a receiver per device, along with a few
+/// wildcard receivers shared by all devices.
+fn create_topic_filters(num_devices: usize) -> Vec<TopicFilter> {
+    let mut topic_filters: Vec<TopicFilter> = (0..num_devices)
+        .flat_map(|i| {
+            [
+                format!("factory/line{}/device{i}/telemetry", i % 10),
+                format!("factory/line{}/device{i}/command/+", i % 10),
+            ]
+        })
+        .map(|topic_filter| TopicFilter::from_string(topic_filter).unwrap())
+        .collect();
+    for topic_filter in [
+        "factory/+/+/telemetry",
+        "factory/line0/#",
+        "$share/group1/factory/+/+/telemetry",
+    ] {
+        topic_filters.push(TopicFilter::from_str(topic_filter).unwrap());
+    }
+    topic_filters
+}
+
+fn topic_dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("topic_dispatch");
+    for num_devices in [10, 100, 1_000, 10_000] {
+        let topic_filters = create_topic_filters(num_devices);
+        let mut topic_tree = TopicTree::new();
+        for (i, topic_filter) in topic_filters.iter().enumerate() {
+            topic_tree.insert(topic_filter.clone(), i);
+        }
+        let device = num_devices / 2;
+        let topic_name = TopicName::from_string(format!(
+            "factory/line{}/device{device}/telemetry",
+            device % 10
+        ))
+        .unwrap();
+
+        // Both approaches must agree on the matching topic filters
+        assert_eq!(
+            topic_filters
+                .iter()
+                .filter(|topic_filter| topic_filter.matches_topic_name(&topic_name))
+                .count(),
+            topic_tree.matches(&topic_name).len()
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("linear", num_devices),
+            &topic_name,
+            |b, topic_name| {
+                b.iter(|| {
+                    topic_filters
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, topic_filter)| {
+                            topic_filter.matches_topic_name(black_box(topic_name))
+                        })
+                        .collect::<Vec<_>>()
+                });
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("topic_tree", num_devices),
+            &topic_name,
+            |b, topic_name| {
+                b.iter(|| topic_tree.matches(black_box(topic_name)));
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, topic_dispatch);
+criterion_main!(benches);
@@ -1,23 +1,105 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-//! Internal module for handling authentication in the Azure IoT Operations MQTT library.
+//! Enhanced authentication for the MQTT client.
+//!
+//! Implement [`AuthProvider`] to supply the authentication method and data used when connecting,
+//! respond to authentication challenges from the MQTT broker, and decide when the client should
+//! re-authenticate. [`SatFileAuthProvider`] is provided for Kubernetes Service Account Token (SAT)
+//! authentication with the Azure IoT Operations MQTT broker.
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::{path::Path, time::Duration};
 
+use async_trait::async_trait;
+use bytes::Bytes;
 use notify::RecommendedWatcher;
 use notify_debouncer_full::{RecommendedCache, new_debouncer};
-use rumqttc::v5::mqttbytes::v5::{AuthProperties, AuthReasonCode};
+use rumqttc::v5::mqttbytes::v5::{Auth, AuthReasonCode};
 use thiserror::Error;
-use tokio::sync::Notify;
+use tokio::sync::watch;
 
+use crate::control_packet::AuthProperties;
 use crate::error::ReauthError;
 use crate::interface::MqttClient;
 
 /// Used as the authentication method for the MQTT client when using SAT.
 pub const SAT_AUTHENTICATION_METHOD: &str = "K8S-SAT";
 
+/// Provider of enhanced authentication (MQTT v5 AUTH) data for a [`Session`](crate::session::Session).
+///
+/// The provider is consulted for authentication data each time the client connects, and again
+/// each time the client re-authenticates on an existing connection.
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// Return the authentication method (e.g. `K8S-SAT`)
+    fn authentication_method(&self) -> String;
+
+    /// Return the authentication data used to begin an authentication exchange, either in the
+    /// CONNECT packet, or in the AUTH packet beginning a re-authentication.
+    ///
+    /// # Errors
+    /// Returns an [`AuthProviderError`] if the authentication data cannot be provided.
+    async fn initial_authentication_data(&self) -> Result<Option<Bytes>, AuthProviderError>;
+
+    /// Respond to an authentication challenge (an AUTH packet with reason code
+    /// `ContinueAuthentication`) from the broker, returning the authentication data for the
+    /// response. The response is sent with reason code `ContinueAuthentication`.
+    ///
+    /// Challenges are responded to by the MQTT event loop, both while connecting and while
+    /// re-authenticating, so this must not block.
+    ///
+    /// The default implementation does not support multi-step authentication exchanges.
+    ///
+    /// # Errors
+    /// Returns an [`AuthProviderError`] if the challenge cannot be responded to.
+    fn continue_authentication(
+        &self,
+        _challenge_data: Option<Bytes>,
+    ) -> Result<Option<Bytes>, AuthProviderError> {
+        Err(AuthProviderError::new(
+            "multi-step authentication is not supported by this provider",
+        ))
+    }
+
+    /// Wait until the client should re-authenticate with new authentication data.
+    ///
+    /// The default implementation never requests re-authentication.
+    async fn reauthentication_needed(&self) {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Error supplying authentication data from an [`AuthProvider`]
+#[derive(Debug, Error)]
+#[error("{msg}")]
+pub struct AuthProviderError {
+    msg: String,
+    #[source]
+    source: Option<Box<dyn std::error::Error + Send + Sync>>,
+}
+
+impl AuthProviderError {
+    /// Create a new [`AuthProviderError`] with the provided message
+    #[must_use]
+    pub fn new(msg: &str) -> Self {
+        Self {
+            msg: msg.to_string(),
+            source: None,
+        }
+    }
+
+    /// Create a new [`AuthProviderError`] with the provided message and underlying cause
+    #[must_use]
+    pub fn with_source(msg: &str, source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Self {
+            msg: msg.to_string(),
+            source: Some(Box::new(source)),
+        }
+    }
+}
+
 /// Error type for initializing the SAT auth context. The type of error is specified by the value of [`SatAuthContextInitError`].
 #[derive(Debug, Error)]
 pub enum SatAuthContextInitError {
@@ -32,47 +114,27 @@ pub enum SatAuthContextInitError {
     NoSatFile,
 }
 
-/// Error type for reauthenticating the client using the SAT token. The type of error is specified by the value of [`SatReauthError`].
-#[derive(Debug, Error)]
-pub enum SatReauthError {
-    /// I/O error occurred while reading the SAT token file.
-    #[error("{0}")]
-    IoError(#[from] std::io::Error),
-    /// Reauth timed out.
-    #[error("Reauth timed out")]
-    Timeout,
-    /// Reauth was not successful.
-    #[error("Reauth failed with reason: {0:?}")]
-    ReauthUnsuccessful(AuthReasonCode),
-    /// Error occurred while reauthenticating the client.
-    #[error("{0}")]
-    ClientReauthError(#[from] ReauthError),
-    /// Reauth channel closed.
-    #[error("Auth watcher channel closed")]
-    AuthWatcherClosed,
-}
-
-/// Context for maintaining SAT token authentication.
-pub struct SatAuthContext {
+/// [`AuthProvider`] for Kubernetes Service Account Token (SAT) authentication.
+///
+/// The SAT is read from a file, and the client re-authenticates whenever the file changes.
+pub struct SatFileAuthProvider {
     /// File path to the SAT token
     file_location: String,
     /// SAT file's directory watcher, held to keep the watcher alive
     #[allow(dead_code)]
     watcher: Option<notify_debouncer_full::Debouncer<RecommendedWatcher, RecommendedCache>>,
-    /// Notifier for changes in the SAT file's directory
-    pub directory_watcher_notify: Arc<Notify>,
-    /// Channel for receiving auth change notifications
-    auth_watcher_rx: tokio::sync::mpsc::UnboundedReceiver<AuthReasonCode>,
+    /// Generation of the SAT file's directory, incremented on each change to it
+    generation: Arc<watch::Sender<u64>>,
+    /// Generation of the SAT file's directory when the SAT was last read
+    read_generation: AtomicU64,
 }
 
-impl SatAuthContext {
-    /// Create a new SAT auth context.
+impl SatFileAuthProvider {
+    /// Create a new [`SatFileAuthProvider`] for the SAT file at the provided location.
     ///
-    /// Returns a [`SatAuthContext`] instance. If an error occurs, a [`SatAuthContextInitError`] is returned.
-    pub fn new(
-        file_location: String,
-        auth_watcher_rx: tokio::sync::mpsc::UnboundedReceiver<AuthReasonCode>,
-    ) -> Result<Self, SatAuthContextInitError> {
+    /// # Errors
+    /// Returns a [`SatAuthContextInitError`] if the SAT file does not exist or cannot be watched.
+    pub fn new(file_location: String) -> Result<Self, SatAuthContextInitError> {
         let file_location_path = Path::new(&file_location);
 
         // Check that the specified SAT token file exists
@@ -85,9 +147,9 @@ impl SatAuthContext {
             return Err(SatAuthContextInitError::NoSatFile);
         };
 
-        // Create a watcher notifier
-        let directory_watcher_notify = Arc::new(Notify::new());
-        let directory_watcher_notify_clone = directory_watcher_notify.clone();
+        // Track changes to the SAT file's directory
+        let generation = Arc::new(watch::Sender::new(0));
+        let generation_clone = generation.clone();
 
         // Create a SAT directory watcher
         let watcher = match new_debouncer(
@@ -103,7 +165,7 @@ impl SatAuthContext {
                                 notify::EventKind::Access(notify::event::AccessKind::Open(_))
                             )
                         }) {
-                            directory_watcher_notify_clone.notify_one();
+                            generation_clone.send_modify(|generation| *generation += 1);
                         }
                     }
                     Err(err) => {
@@ -128,53 +190,344 @@ impl SatAuthContext {
         Ok(Self {
             file_location,
             watcher,
-            directory_watcher_notify,
-            auth_watcher_rx,
+            generation,
+            read_generation: AtomicU64::new(0),
         })
     }
+}
+
+#[async_trait]
+impl AuthProvider for SatFileAuthProvider {
+    fn authentication_method(&self) -> String {
+        SAT_AUTHENTICATION_METHOD.to_string()
+    }
+
+    async fn initial_authentication_data(&self) -> Result<Option<Bytes>, AuthProviderError> {
+        // Record the changes to the SAT file included in the token read below, so we don't re-auth
+        // again for them. The generation is taken before reading, so a change made during the
+        // read is not missed.
+        let generation = *self.generation.borrow();
+        let sat_token = std::fs::read(&self.file_location).map_err(|e| {
+            AuthProviderError::with_source(
+                &format!("cannot read SAT file: {}", self.file_location),
+                e,
+            )
+        })?;
+        self.read_generation.fetch_max(generation, Ordering::SeqCst);
+        Ok(Some(sat_token.into()))
+    }
+
+    async fn reauthentication_needed(&self) {
+        // Wait for a change to the SAT file that is not included in the last token read
+        let mut generation_rx = self.generation.subscribe();
+        // NOTE: The sender is owned by self, so it cannot be dropped while waiting
+        let _ = generation_rx
+            .wait_for(|generation| *generation > self.read_generation.load(Ordering::SeqCst))
+            .await;
+    }
+}
+
+/// Error type for reauthenticating the client. The type of error is specified by the value of [`AuthExchangeError`].
+#[derive(Debug, Error)]
+pub(crate) enum AuthExchangeError {
+    /// Error occurred while getting authentication data from the provider.
+    #[error("{0}")]
+    ProviderError(#[from] AuthProviderError),
+    /// Reauth timed out.
+    #[error("Reauth timed out")]
+    Timeout,
+    /// Reauth was not successful.
+    #[error("Reauth failed with reason: {0:?}")]
+    ReauthUnsuccessful(AuthReasonCode),
+    /// Error occurred while reauthenticating the client.
+    #[error("{0}")]
+    ClientReauthError(#[from] ReauthError),
+    /// Reauth channel closed.
+    #[error("Auth watcher channel closed")]
+    AuthWatcherClosed,
+}
+
+/// Adapter for responding to authentication challenges from the MQTT broker with an
+/// [`AuthProvider`] from within the MQTT event loop.
+pub(crate) struct ContinueAuthentication(pub Arc<dyn AuthProvider>);
+
+impl ContinueAuthentication {
+    /// Return the authentication data responding to the provided challenge.
+    ///
+    /// Returns an error string if the authentication method of the challenge does not match the
+    /// provider, or if the provider cannot respond to the challenge.
+    pub fn respond(
+        &self,
+        authentication_method: Option<&str>,
+        challenge_data: Option<Bytes>,
+    ) -> Result<Option<Bytes>, String> {
+        let expected_method = self.0.authentication_method();
+        if authentication_method != Some(expected_method.as_str()) {
+            return Err(format!(
+                "authentication method of challenge {authentication_method:?} does not match {expected_method}"
+            ));
+        }
+        self.0
+            .continue_authentication(challenge_data)
+            .map_err(|e| e.to_string())
+    }
+}
 
-    /// Wait for changes in SAT file's directory.
-    pub async fn notified(&self) {
-        self.directory_watcher_notify.notified().await;
+impl std::fmt::Debug for ContinueAuthentication {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ContinueAuthentication")
+            .field(&self.0.authentication_method())
+            .finish()
     }
-    /// Drain the SAT file's directory watcher notification.
-    pub async fn drain_notify(&self) {
-        self.directory_watcher_notify.notify_one();
-        self.directory_watcher_notify.notified().await;
+}
+
+/// Context for maintaining enhanced authentication on a connection using an [`AuthProvider`].
+pub(crate) struct AuthContext {
+    /// Provider of the authentication data
+    provider: Arc<dyn AuthProvider>,
+    /// Channel for receiving incoming AUTH packets
+    auth_watcher_rx: tokio::sync::mpsc::UnboundedReceiver<Auth>,
+}
+
+impl AuthContext {
+    /// Create a new auth context.
+    pub fn new(
+        provider: Arc<dyn AuthProvider>,
+        auth_watcher_rx: tokio::sync::mpsc::UnboundedReceiver<Auth>,
+    ) -> Self {
+        Self {
+            provider,
+            auth_watcher_rx,
+        }
     }
 
-    /// Re-authenticate the client using the SAT token.
+    /// Wait until the provider requests re-authentication.
+    pub async fn reauthentication_needed(&self) {
+        self.provider.reauthentication_needed().await;
+    }
+
+    /// Re-authenticate the client, waiting for any multi-step exchange requested by the broker
+    /// to complete.
     ///
-    /// Returns `Ok(())` if re-authentication is successful. If an error occurs or reauthentication was unsuccessful, a [`SatReauthError`] is returned.
+    /// NOTE: Challenges from the broker are responded to by the MQTT event loop using
+    /// [`ContinueAuthentication`], so only the AUTH packet beginning the re-authentication is
+    /// sent here.
+    ///
+    /// Returns `Ok(())` if re-authentication is successful. If an error occurs or reauthentication was unsuccessful, an [`AuthExchangeError`] is returned.
     pub async fn reauth(
         &mut self,
         timeout: Duration,
         client: &impl MqttClient,
-    ) -> Result<(), SatReauthError> {
-        // Get SAT token
-        let sat_token =
-            std::fs::read_to_string(&self.file_location).map_err(SatReauthError::from)?;
+    ) -> Result<(), AuthExchangeError> {
+        // Discard AUTH packets from previous exchanges (e.g. a timed out re-authentication), so
+        // that they are not mistaken for the result of this one
+        while self.auth_watcher_rx.try_recv().is_ok() {}
 
         let props = AuthProperties {
-            method: Some(SAT_AUTHENTICATION_METHOD.to_string()),
-            data: Some(sat_token.into()),
+            method: Some(self.provider.authentication_method()),
+            data: self.provider.initial_authentication_data().await?,
             reason: None,
             user_properties: Vec::new(),
         };
 
-        // Re-authenticate the client
-        client.reauth(props).await.map_err(SatReauthError::from)?;
+        // Send the authentication data
+        client.reauth(props).await?;
 
-        // Wait for next auth change
-        tokio::select! {
-            auth = self.auth_watcher_rx.recv() => {
-                match auth {
-                    Some(AuthReasonCode::Success) => Ok(()),
-                    Some(rc) => Err(SatReauthError::ReauthUnsuccessful(rc)),
-                    None => Err(SatReauthError::AuthWatcherClosed),
+        // Wait for the exchange to complete
+        let exchange = async {
+            loop {
+                let auth = self
+                    .auth_watcher_rx
+                    .recv()
+                    .await
+                    .ok_or(AuthExchangeError::AuthWatcherClosed)?;
+                match auth.code {
+                    AuthReasonCode::Success => return Ok(()),
+                    // Challenge already responded to by the MQTT event loop
+                    AuthReasonCode::Continue => {}
+                    rc => return Err(AuthExchangeError::ReauthUnsuccessful(rc)),
                 }
             }
-            () = tokio::time::sleep(timeout) => Err(SatReauthError::Timeout),
+        };
+        tokio::select! {
+            result = exchange => result,
+            () = tokio::time::sleep(timeout) => Err(AuthExchangeError::Timeout),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface_mocks::MockClient;
+
+    #[test]
+    fn sat_file_does_not_exist() {
+        assert!(matches!(
+            SatFileAuthProvider::new("/nonexistent/path/to/sat".to_string()),
+            Err(SatAuthContextInitError::NoSatFile)
+        ));
+    }
+
+    #[tokio::test]
+    async fn sat_file_authentication_data() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let sat_file = dir.path().join("token");
+        std::fs::write(&sat_file, "sat_token").unwrap();
+
+        let provider = SatFileAuthProvider::new(sat_file.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(provider.authentication_method(), SAT_AUTHENTICATION_METHOD);
+        assert_eq!(
+            provider.initial_authentication_data().await.unwrap(),
+            Some(Bytes::from("sat_token"))
+        );
+        // Multi-step exchanges are not supported for SAT
+        assert!(provider.continue_authentication(None).is_err());
+    }
+
+    /// Provider responding to a challenge with the challenge data reversed
+    struct ChallengeAuthProvider;
+
+    #[async_trait]
+    impl AuthProvider for ChallengeAuthProvider {
+        fn authentication_method(&self) -> String {
+            "TEST-METHOD".to_string()
+        }
+
+        async fn initial_authentication_data(&self) -> Result<Option<Bytes>, AuthProviderError> {
+            Ok(None)
+        }
+
+        fn continue_authentication(
+            &self,
+            challenge_data: Option<Bytes>,
+        ) -> Result<Option<Bytes>, AuthProviderError> {
+            let mut data = challenge_data.unwrap_or_default().to_vec();
+            data.reverse();
+            Ok(Some(data.into()))
+        }
+    }
+
+    #[test]
+    fn continue_authentication_respond() {
+        let continue_auth = ContinueAuthentication(Arc::new(ChallengeAuthProvider));
+        assert_eq!(
+            continue_auth
+                .respond(Some("TEST-METHOD"), Some(Bytes::from("abc")))
+                .unwrap(),
+            Some(Bytes::from("cba"))
+        );
+        // Challenges for a different authentication method are not responded to
+        assert!(
+            continue_auth
+                .respond(Some("OTHER-METHOD"), Some(Bytes::from("abc")))
+                .is_err()
+        );
+        assert!(continue_auth.respond(None, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn reauth_ignores_stale_and_challenge_auth() {
+        let (auth_tx, auth_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut auth_context = AuthContext::new(Arc::new(ChallengeAuthProvider), auth_rx);
+
+        // AUTH left over from a previous exchange is discarded
+        auth_tx
+            .send(Auth::new(AuthReasonCode::ReAuthenticate, None))
+            .unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            // Challenge is responded to by the event loop, so the exchange continues
+            auth_tx
+                .send(Auth::new(AuthReasonCode::Continue, None))
+                .unwrap();
+            auth_tx
+                .send(Auth::new(AuthReasonCode::Success, None))
+                .unwrap();
+        });
+        assert!(
+            auth_context
+                .reauth(Duration::from_secs(5), &MockClient::new())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn sat_file_change_drained_on_read() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let sat_file = dir.path().join("token");
+        std::fs::write(&sat_file, "sat_token").unwrap();
+        let provider = SatFileAuthProvider::new(sat_file.to_str().unwrap().to_string()).unwrap();
+
+        // A change made before the token is read is included in the read, so does not request
+        // re-authentication again
+        provider
+            .generation
+            .send_modify(|generation| *generation += 1);
+        provider.initial_authentication_data().await.unwrap();
+        assert!(
+            tokio::time::timeout(
+                Duration::from_millis(100),
+                provider.reauthentication_needed()
+            )
+            .await
+            .is_err()
+        );
+
+        // A change made after the token is read requests re-authentication
+        provider
+            .generation
+            .send_modify(|generation| *generation += 1);
+        assert!(
+            tokio::time::timeout(
+                Duration::from_millis(100),
+                provider.reauthentication_needed()
+            )
+            .await
+            .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn sat_file_read_while_waiting_for_reauthentication() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let sat_file = dir.path().join("token");
+        std::fs::write(&sat_file, "sat_token").unwrap();
+        let provider =
+            Arc::new(SatFileAuthProvider::new(sat_file.to_str().unwrap().to_string()).unwrap());
+
+        // Wait for re-authentication, as the session does in the background
+        let waiter = tokio::spawn({
+            let provider = provider.clone();
+            async move { provider.reauthentication_needed().await }
+        });
+        tokio::task::yield_now().await;
+
+        // Reading the token (e.g. on reconnect) returns promptly, without waiting for the SAT
+        // file to change
+        assert_eq!(
+            tokio::time::timeout(
+                Duration::from_millis(100),
+                provider.initial_authentication_data()
+            )
+            .await
+            .unwrap()
+            .unwrap(),
+            Some(Bytes::from("sat_token"))
+        );
+        // And does not wake the waiter to re-authenticate
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!waiter.is_finished());
+
+        // The waiter is woken by a change to the SAT file
+        provider
+            .generation
+            .send_modify(|generation| *generation += 1);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), waiter)
+                .await
+                .is_ok()
+        );
+    }
+}
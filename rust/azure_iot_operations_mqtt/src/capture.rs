@@ -0,0 +1,601 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Capture and replay of the MQTT traffic of a [`Session`](crate::session::Session).
+//!
+//! When [`SessionOptions::capture`](crate::session::SessionOptions::capture) is set, every event
+//! polled from the MQTT event loop is appended to a capture file as a line of JSON with the
+//! following keys:
+//!
+//! | Key | Present for | Description |
+//! | --- | --- | --- |
+//! | `timestamp_ms` | all | Milliseconds since the Unix epoch |
+//! | `direction` | all | `incoming`, `outgoing` or `error` |
+//! | `type` | incoming, outgoing | Packet type (e.g. `Publish`, `ConnAck`, `PubAck`) |
+//! | `pkid` | packets with a packet identifier | Packet identifier |
+//! | `topic`, `qos`, `retain`, `dup` | incoming `Publish` | Publish fields |
+//! | `payload_len` | incoming `Publish` | Length of the payload received, in bytes |
+//! | `payload` / `payload_hex` | incoming `Publish` | Captured payload, as text if it is valid UTF-8, otherwise hex encoded |
+//! | `payload_truncated`, `payload_redacted` | incoming `Publish` | Set if the captured payload was truncated or redacted |
+//! | `properties` | incoming packets with properties | Properties of the packet, for reading |
+//! | `packet` | incoming | Hex encoded MQTT packet, used for replay |
+//! | `error` | error | Description of the connection error |
+//!
+//! A capture can be fed back through a [`Session`](crate::session::Session) with a
+//! [`ReplayEventLoop`] to reproduce an issue deterministically.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use rumqttc::v5::mqttbytes::v5::{Disconnect, Packet};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::control_packet::DisconnectReasonCode;
+use crate::error::ConnectionError;
+use crate::interface::{Event, Incoming, MqttEventLoop, Outgoing};
+
+/// Options for capturing the MQTT traffic of a [`Session`](crate::session::Session)
+#[derive(Builder, Clone, Debug)]
+#[builder(pattern = "owned", setter(into))]
+pub struct CaptureOptions {
+    /// Path of the capture file. Captured events are appended if the file already exists.
+    pub(crate) path: PathBuf,
+    /// Maximum number of payload bytes captured for each publish. Longer payloads are truncated.
+    /// If not provided, payloads are captured in full.
+    #[builder(default = "None", setter(strip_option))]
+    pub(crate) max_payload_len: Option<usize>,
+    /// Indicates if payloads should be omitted from the capture. Only the payload length is
+    /// captured.
+    #[builder(default = "false")]
+    pub(crate) redact_payloads: bool,
+}
+
+/// Error reading a capture file
+#[derive(Error, Debug)]
+pub enum CaptureError {
+    /// The capture file could not be read
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// A line of the capture file is malformed
+    #[error("malformed capture on line {line}: {reason}")]
+    Malformed {
+        /// Line number (starting at 1)
+        line: usize,
+        /// Description of the problem
+        reason: String,
+    },
+}
+
+/// Writes events polled from an MQTT event loop to a capture file.
+pub(crate) struct CaptureWriter {
+    writer: BufWriter<File>,
+    max_payload_len: Option<usize>,
+    redact_payloads: bool,
+}
+
+impl CaptureWriter {
+    /// Open the capture file described by the provided [`CaptureOptions`] for appending.
+    pub(crate) fn open(options: CaptureOptions) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&options.path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            max_payload_len: options.max_payload_len,
+            redact_payloads: options.redact_payloads,
+        })
+    }
+
+    /// Record an event or error polled from the MQTT event loop.
+    ///
+    /// Each record is flushed immediately so that the capture is complete even if the process
+    /// ends abruptly.
+    pub(crate) fn record(&mut self, next: &Result<Event, ConnectionError>) -> io::Result<()> {
+        let mut line = Map::new();
+        line.insert("timestamp_ms".into(), timestamp_ms().into());
+        match next {
+            Ok(Event::Incoming(packet)) => {
+                line.insert("direction".into(), "incoming".into());
+                self.describe_incoming(packet, &mut line)?;
+            }
+            Ok(Event::Outgoing(outgoing)) => {
+                line.insert("direction".into(), "outgoing".into());
+                let (kind, pkid) = describe_outgoing(outgoing);
+                line.insert("type".into(), kind.into());
+                if let Some(pkid) = pkid {
+                    line.insert("pkid".into(), pkid.into());
+                }
+            }
+            Err(e) => {
+                line.insert("direction".into(), "error".into());
+                line.insert("error".into(), e.to_string().into());
+            }
+        }
+        serde_json::to_writer(&mut self.writer, &Value::Object(line))?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+
+    /// Describe an incoming packet, truncating or redacting the payload of a publish as
+    /// configured.
+    fn describe_incoming(
+        &self,
+        packet: &Incoming,
+        line: &mut Map<String, Value>,
+    ) -> io::Result<()> {
+        line.insert("type".into(), packet_type(packet).into());
+        let packet = if let Incoming::Publish(publish) = packet {
+            let mut publish = publish.clone();
+            line.insert(
+                "topic".into(),
+                String::from_utf8_lossy(&publish.topic).into(),
+            );
+            line.insert("qos".into(), (publish.qos as u8).into());
+            line.insert("retain".into(), publish.retain.into());
+            line.insert("dup".into(), publish.dup.into());
+            line.insert("payload_len".into(), publish.payload.len().into());
+            if self.redact_payloads {
+                publish.payload = Bytes::new();
+                line.insert("payload_redacted".into(), true.into());
+            } else {
+                if let Some(max_payload_len) = self.max_payload_len {
+                    if publish.payload.len() > max_payload_len {
+                        publish.payload.truncate(max_payload_len);
+                        line.insert("payload_truncated".into(), true.into());
+                    }
+                }
+                match std::str::from_utf8(&publish.payload) {
+                    Ok(payload) => line.insert("payload".into(), payload.into()),
+                    Err(_) => {
+                        line.insert("payload_hex".into(), encode_hex(&publish.payload).into())
+                    }
+                };
+            }
+            Incoming::Publish(publish)
+        } else {
+            packet.clone()
+        };
+        if let Some(pkid) = packet_pkid(&packet) {
+            line.insert("pkid".into(), pkid.into());
+        }
+        if let Some(properties) = packet_properties(&packet) {
+            line.insert("properties".into(), properties.into());
+        }
+        let mut bytes = BytesMut::new();
+        packet
+            .write(&mut bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{e:?}")))?;
+        line.insert("packet".into(), encode_hex(&bytes).into());
+        Ok(())
+    }
+}
+
+/// MQTT event loop that replays the events of a capture file, in order.
+///
+/// Incoming packets are replayed exactly as captured, except for payloads that were truncated or
+/// redacted during capture. Errors are replayed as I/O errors with the captured description.
+/// Once all events have been replayed, polling waits indefinitely, so the
+/// [`Session`](crate::session::Session) must be ended with its exit handle.
+///
+/// Changes to the connection configuration (e.g. the broker endpoint) have no effect.
+pub struct ReplayEventLoop {
+    events: VecDeque<Result<Event, String>>,
+}
+
+impl ReplayEventLoop {
+    /// Load the events of the capture file at the provided path.
+    ///
+    /// # Errors
+    /// Returns a [`CaptureError`] if the file cannot be read or is malformed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CaptureError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut events = VecDeque::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = parse_line(&line).map_err(|reason| CaptureError::Malformed {
+                line: i + 1,
+                reason,
+            })?;
+            events.push_back(event);
+        }
+        Ok(Self { events })
+    }
+
+    /// Return the number of events that have not yet been replayed
+    #[must_use]
+    pub fn remaining(&self) -> usize {
+        self.events.len()
+    }
+}
+
+#[async_trait]
+impl MqttEventLoop for ReplayEventLoop {
+    async fn poll(&mut self) -> Result<Event, ConnectionError> {
+        match self.events.pop_front() {
+            Some(Ok(event)) => Ok(event),
+            Some(Err(error)) => Err(ConnectionError::Io(io::Error::other(error))),
+            None => std::future::pending().await,
+        }
+    }
+
+    fn set_clean_start(&mut self, _clean_start: bool) {}
+
+    fn set_authentication_method(&mut self, _authentication_method: Option<String>) {}
+
+    fn set_authentication_data(&mut self, _authentication_data: Option<Bytes>) {}
+}
+
+/// Parse a line of a capture file into the event (or error) it describes.
+fn parse_line(line: &str) -> Result<Result<Event, String>, String> {
+    let value: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let str_field = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_str)
+            .ok_or(format!("missing or invalid `{key}`"))
+    };
+    match str_field("direction")? {
+        "incoming" => {
+            let bytes = decode_hex(str_field("packet")?)?;
+            Ok(Ok(Event::Incoming(read_packet(&bytes)?)))
+        }
+        "outgoing" => {
+            let pkid = match value.get("pkid") {
+                Some(pkid) => Some(
+                    pkid.as_u64()
+                        .and_then(|pkid| u16::try_from(pkid).ok())
+                        .ok_or("invalid `pkid`")?,
+                ),
+                None => None,
+            };
+            let kind = str_field("type")?;
+            let outgoing =
+                parse_outgoing(kind, pkid).ok_or(format!("invalid outgoing event `{kind}`"))?;
+            Ok(Ok(Event::Outgoing(outgoing)))
+        }
+        "error" => Ok(Err(str_field("error")?.to_string())),
+        direction => Err(format!("unknown direction `{direction}`")),
+    }
+}
+
+/// Decode an MQTT packet
+fn read_packet(bytes: &[u8]) -> Result<Packet, String> {
+    // NOTE: A normal DISCONNECT without properties has no variable header, which the packet
+    // decoder rejects.
+    if bytes == [0xE0, 0x00] {
+        return Ok(Packet::Disconnect(Disconnect::new(
+            DisconnectReasonCode::NormalDisconnection,
+        )));
+    }
+    let mut bytes = BytesMut::from(bytes);
+    let packet = Packet::read(&mut bytes, None).map_err(|e| format!("{e:?}"))?;
+    if !bytes.is_empty() {
+        return Err("trailing bytes after packet".to_string());
+    }
+    Ok(packet)
+}
+
+/// Return the name of the type of an incoming packet
+fn packet_type(packet: &Incoming) -> &'static str {
+    match packet {
+        Incoming::Connect(..) => "Connect",
+        Incoming::ConnAck(_) => "ConnAck",
+        Incoming::Publish(_) => "Publish",
+        Incoming::PubAck(_) => "PubAck",
+        Incoming::PingReq(_) => "PingReq",
+        Incoming::PingResp(_) => "PingResp",
+        Incoming::Subscribe(_) => "Subscribe",
+        Incoming::SubAck(_) => "SubAck",
+        Incoming::PubRec(_) => "PubRec",
+        Incoming::PubRel(_) => "PubRel",
+        Incoming::PubComp(_) => "PubComp",
+        Incoming::Unsubscribe(_) => "Unsubscribe",
+        Incoming::UnsubAck(_) => "UnsubAck",
+        Incoming::Disconnect(_) => "Disconnect",
+        Incoming::Auth(_) => "Auth",
+    }
+}
+
+/// Return the packet identifier of an incoming packet, if it has one
+fn packet_pkid(packet: &Incoming) -> Option<u16> {
+    match packet {
+        Incoming::Publish(publish) => Some(publish.pkid),
+        Incoming::PubAck(puback) => Some(puback.pkid),
+        Incoming::SubAck(suback) => Some(suback.pkid),
+        Incoming::PubRec(pubrec) => Some(pubrec.pkid),
+        Incoming::PubRel(pubrel) => Some(pubrel.pkid),
+        Incoming::PubComp(pubcomp) => Some(pubcomp.pkid),
+        Incoming::UnsubAck(unsuback) => Some(unsuback.pkid),
+        _ => None,
+    }
+}
+
+/// Return a description of the properties of an incoming packet, if it has any
+fn packet_properties(packet: &Incoming) -> Option<String> {
+    fn describe(properties: Option<&impl std::fmt::Debug>) -> Option<String> {
+        properties.map(|properties| format!("{properties:?}"))
+    }
+    match packet {
+        Incoming::ConnAck(connack) => describe(connack.properties.as_ref()),
+        Incoming::Publish(publish) => describe(publish.properties.as_ref()),
+        Incoming::PubAck(puback) => describe(puback.properties.as_ref()),
+        Incoming::SubAck(suback) => describe(suback.properties.as_ref()),
+        Incoming::PubRec(pubrec) => describe(pubrec.properties.as_ref()),
+        Incoming::PubRel(pubrel) => describe(pubrel.properties.as_ref()),
+        Incoming::PubComp(pubcomp) => describe(pubcomp.properties.as_ref()),
+        Incoming::UnsubAck(unsuback) => describe(unsuback.properties.as_ref()),
+        Incoming::Disconnect(disconnect) => describe(disconnect.properties.as_ref()),
+        Incoming::Auth(auth) => describe(auth.properties.as_ref()),
+        _ => None,
+    }
+}
+
+/// Return the name and packet identifier (if any) of an outgoing event
+fn describe_outgoing(outgoing: &Outgoing) -> (&'static str, Option<u16>) {
+    match outgoing {
+        Outgoing::Publish(pkid) => ("Publish", Some(*pkid)),
+        Outgoing::Subscribe(pkid) => ("Subscribe", Some(*pkid)),
+        Outgoing::Unsubscribe(pkid) => ("Unsubscribe", Some(*pkid)),
+        Outgoing::PubAck(pkid) => ("PubAck", Some(*pkid)),
+        Outgoing::PubRec(pkid) => ("PubRec", Some(*pkid)),
+        Outgoing::PubRel(pkid) => ("PubRel", Some(*pkid)),
+        Outgoing::PubComp(pkid) => ("PubComp", Some(*pkid)),
+        Outgoing::PingReq => ("PingReq", None),
+        Outgoing::PingResp => ("PingResp", None),
+        Outgoing::Disconnect => ("Disconnect", None),
+        Outgoing::AwaitAck(pkid) => ("AwaitAck", Some(*pkid)),
+    }
+}
+
+/// Reconstruct an outgoing event from its name and packet identifier
+fn parse_outgoing(kind: &str, pkid: Option<u16>) -> Option<Outgoing> {
+    let outgoing = match (kind, pkid) {
+        ("Publish", Some(pkid)) => Outgoing::Publish(pkid),
+        ("Subscribe", Some(pkid)) => Outgoing::Subscribe(pkid),
+        ("Unsubscribe", Some(pkid)) => Outgoing::Unsubscribe(pkid),
+        ("PubAck", Some(pkid)) => Outgoing::PubAck(pkid),
+        ("PubRec", Some(pkid)) => Outgoing::PubRec(pkid),
+        ("PubRel", Some(pkid)) => Outgoing::PubRel(pkid),
+        ("PubComp", Some(pkid)) => Outgoing::PubComp(pkid),
+        ("PingReq", None) => Outgoing::PingReq,
+        ("PingResp", None) => Outgoing::PingResp,
+        ("Disconnect", None) => Outgoing::Disconnect,
+        ("AwaitAck", Some(pkid)) => Outgoing::AwaitAck(pkid),
+        _ => return None,
+    };
+    Some(outgoing)
+}
+
+/// Return the current time in milliseconds since the Unix epoch
+fn timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        .try_into()
+        .unwrap_or(u64::MAX)
+}
+
+/// Encode bytes as lowercase hex
+fn encode_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write as _;
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut hex, b| {
+            let _ = write!(hex, "{b:02x}");
+            hex
+        })
+}
+
+/// Decode hex encoded bytes
+fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if hex.len() % 2 != 0 {
+        return Err("odd number of hex digits".to_string());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or(format!("invalid hex at offset {i}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+    use crate::control_packet::{ConnectReturnCode, PublishProperties, QoS};
+    use rumqttc::v5::mqttbytes::v5::{ConnAck, PubAck, PubAckReason, Publish};
+
+    fn publish(payload: &'static [u8]) -> Publish {
+        let mut publish = Publish::new(
+            "test/topic",
+            QoS::AtLeastOnce,
+            Bytes::from_static(payload),
+            Some(PublishProperties {
+                content_type: Some("text/plain".to_string()),
+                user_properties: vec![("key".to_string(), "value".to_string())],
+                ..Default::default()
+            }),
+        );
+        publish.pkid = 7;
+        publish
+    }
+
+    fn capture(options: CaptureOptions, events: &[Result<Event, ConnectionError>]) {
+        let mut writer = CaptureWriter::open(options).unwrap();
+        for event in events {
+            writer.record(event).unwrap();
+        }
+    }
+
+    fn read_lines(path: &Path) -> Vec<Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn capture_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capture.jsonl");
+        let connack = Incoming::ConnAck(ConnAck {
+            session_present: false,
+            code: ConnectReturnCode::Success,
+            properties: None,
+        });
+        let publish = Incoming::Publish(publish(b"hello"));
+        let puback = Incoming::PubAck(PubAck {
+            pkid: 3,
+            reason: PubAckReason::Success,
+            properties: None,
+        });
+        let disconnect =
+            Incoming::Disconnect(Disconnect::new(DisconnectReasonCode::NormalDisconnection));
+        capture(
+            CaptureOptionsBuilder::default()
+                .path(path.clone())
+                .build()
+                .unwrap(),
+            &[
+                Ok(Event::Incoming(connack.clone())),
+                Ok(Event::Outgoing(Outgoing::Publish(3))),
+                Ok(Event::Incoming(puback.clone())),
+                Ok(Event::Incoming(publish.clone())),
+                Ok(Event::Outgoing(Outgoing::PubAck(7))),
+                Ok(Event::Outgoing(Outgoing::PingReq)),
+                Ok(Event::Incoming(disconnect.clone())),
+                Err(ConnectionError::RequestsDone),
+            ],
+        );
+
+        let lines = read_lines(&path);
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[3]["direction"], "incoming");
+        assert_eq!(lines[3]["type"], "Publish");
+        assert_eq!(lines[3]["topic"], "test/topic");
+        assert_eq!(lines[3]["pkid"], 7);
+        assert_eq!(lines[3]["payload"], "hello");
+        assert!(
+            lines[3]["properties"]
+                .as_str()
+                .unwrap()
+                .contains("text/plain")
+        );
+        assert_eq!(lines[4]["direction"], "outgoing");
+        assert_eq!(lines[4]["type"], "PubAck");
+        assert_eq!(lines[7]["direction"], "error");
+        assert_eq!(lines[7]["error"], "Requests done");
+
+        let mut replay = ReplayEventLoop::open(&path).unwrap();
+        assert_eq!(replay.remaining(), 8);
+        assert_eq!(replay.poll().await.unwrap(), Event::Incoming(connack));
+        assert_eq!(
+            replay.poll().await.unwrap(),
+            Event::Outgoing(Outgoing::Publish(3))
+        );
+        assert_eq!(replay.poll().await.unwrap(), Event::Incoming(puback));
+        assert_eq!(replay.poll().await.unwrap(), Event::Incoming(publish));
+        assert_eq!(
+            replay.poll().await.unwrap(),
+            Event::Outgoing(Outgoing::PubAck(7))
+        );
+        assert_eq!(
+            replay.poll().await.unwrap(),
+            Event::Outgoing(Outgoing::PingReq)
+        );
+        assert_eq!(replay.poll().await.unwrap(), Event::Incoming(disconnect));
+        match replay.poll().await {
+            Err(ConnectionError::Io(e)) => assert_eq!(e.to_string(), "Requests done"),
+            other => panic!("Expected replayed error, got {other:?}"),
+        }
+        assert_eq!(replay.remaining(), 0);
+
+        // Once exhausted, polling does not complete
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(10), replay.poll())
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capture.jsonl");
+        capture(
+            CaptureOptionsBuilder::default()
+                .path(path.clone())
+                .max_payload_len(4_usize)
+                .build()
+                .unwrap(),
+            &[
+                Ok(Event::Incoming(Incoming::Publish(publish(b"hello world")))),
+                Ok(Event::Incoming(Incoming::Publish(publish(&[0xff, 0x00])))),
+            ],
+        );
+        let lines = read_lines(&path);
+        assert_eq!(lines[0]["payload"], "hell");
+        assert_eq!(lines[0]["payload_len"], 11);
+        assert_eq!(lines[0]["payload_truncated"], true);
+        assert_eq!(lines[1]["payload_hex"], "ff00");
+        assert!(lines[1].get("payload_truncated").is_none());
+    }
+
+    #[test]
+    fn redacted_payload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capture.jsonl");
+        capture(
+            CaptureOptionsBuilder::default()
+                .path(path.clone())
+                .redact_payloads(true)
+                .build()
+                .unwrap(),
+            &[Ok(Event::Incoming(Incoming::Publish(publish(b"secret"))))],
+        );
+        let lines = read_lines(&path);
+        assert_eq!(lines[0]["payload_len"], 6);
+        assert_eq!(lines[0]["payload_redacted"], true);
+        assert!(lines[0].get("payload").is_none());
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("secret"));
+
+        // The redacted publish is replayed without its payload
+        let mut replay = ReplayEventLoop::open(&path).unwrap();
+        assert_eq!(
+            tokio_test::block_on(replay.poll()).unwrap(),
+            Event::Incoming(Incoming::Publish(publish(b"")))
+        );
+    }
+
+    #[test_case("not json"; "not json")]
+    #[test_case(r#"{"direction":"sideways"}"#; "unknown direction")]
+    #[test_case(r#"{"direction":"incoming","packet":"zz"}"#; "invalid hex")]
+    #[test_case(r#"{"direction":"incoming","packet":"30"}"#; "truncated packet")]
+    #[test_case(r#"{"direction":"outgoing","type":"PubAck"}"#; "missing pkid")]
+    #[test_case(r#"{"direction":"outgoing","type":"Connect"}"#; "unknown outgoing")]
+    fn malformed_capture(line: &str) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "{line}").unwrap();
+        match ReplayEventLoop::open(file.path()) {
+            Err(CaptureError::Malformed { line, .. }) => assert_eq!(line, 1),
+            Err(e) => panic!("Expected malformed capture error, got {e:?}"),
+            Ok(_) => panic!("Expected malformed capture error"),
+        }
+    }
+}
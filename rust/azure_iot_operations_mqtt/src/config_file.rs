@@ -0,0 +1,571 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Internal module for reading [`MqttConnectionSettings`](crate::MqttConnectionSettings) and
+//! [`SessionOptions`](crate::session::SessionOptions) from a TOML, YAML or JSON config file.
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use bytes::Bytes;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::MqttConnectionSettingsBuilder;
+use crate::control_packet::{LastWillProperties, QoS};
+use crate::session::SessionOptionsBuilder;
+
+/// Error reading settings from a config file
+#[derive(Debug, Error)]
+pub enum ConfigFileError {
+    /// The config file could not be read
+    #[error("cannot read config file: {0}")]
+    Io(#[from] std::io::Error),
+    /// The config file extension does not indicate a supported format
+    #[error("unsupported config file format: {0}. Expected .toml, .yaml, .yml or .json")]
+    UnsupportedFormat(String),
+    /// The config file could not be parsed
+    #[error("malformed config file: {0}")]
+    Malformed(String),
+    /// The config file contains an unknown key
+    #[error("unknown key in config file: {0}")]
+    UnknownKey(String),
+    /// The config file contains an invalid value for a key
+    #[error("invalid value for {key} in config file: {msg}")]
+    InvalidValue {
+        /// Full path of the key (e.g. `connection_settings.tcp_port`)
+        key: String,
+        /// Description of the problem with the value
+        msg: String,
+    },
+    /// An environment variable layered over the config file contains invalid data
+    #[error("invalid environment variable: {0}")]
+    Environment(String),
+}
+
+/// Contents of a config file, split into its tables
+#[derive(Default)]
+pub struct ConfigFile {
+    /// Values of the `connection_settings` table
+    connection_settings: Map<String, Value>,
+    /// Values of the `session_options` table
+    session_options: Map<String, Value>,
+}
+
+impl ConfigFile {
+    /// Read a config file, using its extension to determine the format.
+    ///
+    /// # Errors
+    /// Returns a [`ConfigFileError`] if the file cannot be read or parsed, or contains an unknown
+    /// table
+    pub fn read(path: &Path) -> Result<Self, ConfigFileError> {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let contents = fs::read_to_string(path)?;
+        let value = match extension.as_str() {
+            "toml" => toml::from_str(&contents).map_err(|e| e.to_string()),
+            "yaml" | "yml" => serde_yaml::from_str(&contents).map_err(|e| e.to_string()),
+            "json" => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+            _ => {
+                return Err(ConfigFileError::UnsupportedFormat(
+                    path.display().to_string(),
+                ));
+            }
+        }
+        .map_err(ConfigFileError::Malformed)?;
+        Self::from_value(value)
+    }
+
+    /// Split the parsed contents of a config file into its tables
+    fn from_value(value: Value) -> Result<Self, ConfigFileError> {
+        let root = match value {
+            Value::Object(root) => root,
+            // An empty YAML file
+            Value::Null => Map::new(),
+            _ => {
+                return Err(ConfigFileError::Malformed(
+                    "expected a table at the top level".to_string(),
+                ));
+            }
+        };
+        let mut config_file = Self::default();
+        for (name, value) in root {
+            match name.as_str() {
+                "connection_settings" => config_file.connection_settings = table(&name, value)?,
+                "session_options" => config_file.session_options = table(&name, value)?,
+                _ => return Err(ConfigFileError::UnknownKey(name)),
+            }
+        }
+        Ok(config_file)
+    }
+
+    /// Return a [`MqttConnectionSettingsBuilder`] with the values of the `connection_settings`
+    /// table set.
+    ///
+    /// # Errors
+    /// Returns a [`ConfigFileError`] naming the key if the table contains an unknown key or an
+    /// invalid value
+    pub fn connection_settings_builder(
+        &self,
+    ) -> Result<MqttConnectionSettingsBuilder, ConfigFileError> {
+        let mut builder = MqttConnectionSettingsBuilder::default();
+        for (name, value) in &self.connection_settings {
+            let key = format!("connection_settings.{name}");
+            let value = value.clone();
+            builder = match name.as_str() {
+                "client_id" => builder.client_id(string(&key, value)?),
+                "hostname" => builder.hostname(string(&key, value)?),
+                "tcp_port" => builder.tcp_port(integer::<u16>(&key, &value)?),
+                "failover_endpoints" => builder.failover_endpoints(endpoints(&key, value)?),
+                "keep_alive" => builder.keep_alive(seconds(&key, &value)?),
+                "receive_max" => builder.receive_max(integer::<u16>(&key, &value)?),
+                "receive_packet_size_max" => {
+                    builder.receive_packet_size_max(integer::<u32>(&key, &value)?)
+                }
+                "session_expiry" => builder.session_expiry(seconds(&key, &value)?),
+                "connection_timeout" => builder.connection_timeout(seconds(&key, &value)?),
+                "clean_start" => builder.clean_start(boolean(&key, &value)?),
+                "username" => builder.username(string(&key, value)?),
+                "password" => builder.password(string(&key, value)?),
+                "password_file" => builder.password_file(string(&key, value)?),
+                "use_tls" => builder.use_tls(boolean(&key, &value)?),
+                "ca_file" => builder.ca_file(string(&key, value)?),
+                "cert_file" => builder.cert_file(string(&key, value)?),
+                "key_file" => builder.key_file(string(&key, value)?),
+                "key_password_file" => builder.key_password_file(string(&key, value)?),
+                "sat_file" => builder.sat_file(string(&key, value)?),
+                "use_websocket" => builder.use_websocket(boolean(&key, &value)?),
+                "websocket_path" => builder.websocket_path(string(&key, value)?),
+                "websocket_headers" => builder.websocket_headers(string_pairs(&key, value)?),
+                "unix_socket_path" => builder.unix_socket_path(string(&key, value)?),
+                "proxy_url" => builder.proxy_url(string(&key, value)?),
+                "proxy_username" => builder.proxy_username(string(&key, value)?),
+                "proxy_password" => builder.proxy_password(string(&key, value)?),
+                "no_proxy" => builder.no_proxy(
+                    array(&key, value)?
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, entry)| string(&format!("{key}[{i}]"), entry))
+                        .collect::<Result<Vec<_>, _>>()?,
+                ),
+                "will_topic" => builder.will_topic(string(&key, value)?),
+                "will_payload" => builder.will_payload(Bytes::from(string(&key, value)?)),
+                "will_qos" => builder.will_qos(qos(&key, &value)?),
+                "will_retain" => builder.will_retain(boolean(&key, &value)?),
+                "will_delay_interval" => builder.will_delay_interval(seconds(&key, &value)?),
+                "will_properties" => builder.will_properties(will_properties(&key, value)?),
+                _ => return Err(ConfigFileError::UnknownKey(key)),
+            };
+        }
+        Ok(builder)
+    }
+
+    /// Return a [`SessionOptionsBuilder`] with the values of the `session_options` table set.
+    ///
+    /// # Errors
+    /// Returns a [`ConfigFileError`] naming the key if the table contains an unknown key or an
+    /// invalid value
+    pub fn session_options_builder(&self) -> Result<SessionOptionsBuilder, ConfigFileError> {
+        let mut builder = SessionOptionsBuilder::default();
+        for (name, value) in &self.session_options {
+            let key = format!("session_options.{name}");
+            let value = value.clone();
+            builder = match name.as_str() {
+                "outgoing_max" => builder.outgoing_max(integer::<usize>(&key, &value)?),
+                "aio_broker_features" => builder.aio_broker_features(boolean(&key, &value)?),
+                "offline_queue_dir" => builder.offline_queue_dir(string(&key, value)?),
+                "offline_queue_max" => builder.offline_queue_max(integer::<usize>(&key, &value)?),
+                "tls_reload_debounce" => builder.tls_reload_debounce(seconds(&key, &value)?),
+                "recover_lost_session" => builder.recover_lost_session(boolean(&key, &value)?),
+                "ordered_acks" => builder.ordered_acks(boolean(&key, &value)?),
+                "held_ack_warning_threshold" => {
+                    builder.held_ack_warning_threshold(seconds(&key, &value)?)
+                }
+                _ => return Err(ConfigFileError::UnknownKey(key)),
+            };
+        }
+        Ok(builder)
+    }
+}
+
+/// Helper function to create an [`ConfigFileError::InvalidValue`] for a key
+fn invalid(key: &str, msg: impl Into<String>) -> ConfigFileError {
+    ConfigFileError::InvalidValue {
+        key: key.to_string(),
+        msg: msg.into(),
+    }
+}
+
+/// Helper function to get a value as a table
+fn table(key: &str, value: Value) -> Result<Map<String, Value>, ConfigFileError> {
+    match value {
+        Value::Object(table) => Ok(table),
+        _ => Err(invalid(key, "expected a table")),
+    }
+}
+
+/// Helper function to get a value as an array
+fn array(key: &str, value: Value) -> Result<Vec<Value>, ConfigFileError> {
+    match value {
+        Value::Array(array) => Ok(array),
+        _ => Err(invalid(key, "expected an array")),
+    }
+}
+
+/// Helper function to get a value as a string
+fn string(key: &str, value: Value) -> Result<String, ConfigFileError> {
+    match value {
+        Value::String(string) => Ok(string),
+        _ => Err(invalid(key, "expected a string")),
+    }
+}
+
+/// Helper function to get a value as a boolean
+fn boolean(key: &str, value: &Value) -> Result<bool, ConfigFileError> {
+    value
+        .as_bool()
+        .ok_or_else(|| invalid(key, "expected a boolean"))
+}
+
+/// Helper function to get a value as a non-negative integer of the provided type
+fn integer<T: TryFrom<u64>>(key: &str, value: &Value) -> Result<T, ConfigFileError> {
+    let integer = value
+        .as_u64()
+        .ok_or_else(|| invalid(key, "expected a non-negative integer"))?;
+    T::try_from(integer).map_err(|_| invalid(key, format!("{integer} is out of range")))
+}
+
+/// Helper function to get a value as a [`Duration`] from a number of seconds
+fn seconds(key: &str, value: &Value) -> Result<Duration, ConfigFileError> {
+    integer::<u64>(key, value).map(Duration::from_secs)
+}
+
+/// Helper function to get a value as a [`QoS`] from its numeric value
+fn qos(key: &str, value: &Value) -> Result<QoS, ConfigFileError> {
+    match integer::<u8>(key, value) {
+        Ok(0) => Ok(QoS::AtMostOnce),
+        Ok(1) => Ok(QoS::AtLeastOnce),
+        Ok(2) => Ok(QoS::ExactlyOnce),
+        _ => Err(invalid(key, "expected 0, 1 or 2")),
+    }
+}
+
+/// Helper function to get a table of string values as a list of name/value pairs
+fn string_pairs(key: &str, value: Value) -> Result<Vec<(String, String)>, ConfigFileError> {
+    table(key, value)?
+        .into_iter()
+        .map(|(name, value)| {
+            let value = string(&format!("{key}.{name}"), value)?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Helper function to get an array of tables with `hostname` and `tcp_port` keys as a list of
+/// endpoints
+fn endpoints(key: &str, value: Value) -> Result<Vec<(String, u16)>, ConfigFileError> {
+    array(key, value)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, endpoint)| {
+            let key = format!("{key}[{i}]");
+            let (mut hostname, mut tcp_port) = (None, None);
+            for (name, value) in table(&key, endpoint)? {
+                let key = format!("{key}.{name}");
+                match name.as_str() {
+                    "hostname" => hostname = Some(string(&key, value)?),
+                    "tcp_port" => tcp_port = Some(integer::<u16>(&key, &value)?),
+                    _ => return Err(ConfigFileError::UnknownKey(key)),
+                }
+            }
+            let hostname = hostname.ok_or_else(|| invalid(&key, "missing hostname"))?;
+            let tcp_port = tcp_port.ok_or_else(|| invalid(&key, "missing tcp_port"))?;
+            Ok((hostname, tcp_port))
+        })
+        .collect()
+}
+
+/// Helper function to get a table as [`LastWillProperties`]
+fn will_properties(key: &str, value: Value) -> Result<LastWillProperties, ConfigFileError> {
+    let mut properties = LastWillProperties {
+        delay_interval: None,
+        payload_format_indicator: None,
+        message_expiry_interval: None,
+        content_type: None,
+        response_topic: None,
+        correlation_data: None,
+        user_properties: Vec::new(),
+    };
+    for (name, value) in table(key, value)? {
+        let key = format!("{key}.{name}");
+        match name.as_str() {
+            "delay_interval" => properties.delay_interval = Some(integer(&key, &value)?),
+            "payload_format_indicator" => {
+                properties.payload_format_indicator = Some(integer(&key, &value)?);
+            }
+            "message_expiry_interval" => {
+                properties.message_expiry_interval = Some(integer(&key, &value)?);
+            }
+            "content_type" => properties.content_type = Some(string(&key, value)?),
+            "response_topic" => properties.response_topic = Some(string(&key, value)?),
+            "correlation_data" => {
+                properties.correlation_data = Some(Bytes::from(string(&key, value)?));
+            }
+            "user_properties" => properties.user_properties = string_pairs(&key, value)?,
+            _ => return Err(ConfigFileError::UnknownKey(key)),
+        }
+    }
+    Ok(properties)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    const TOML: &str = r#"
+[connection_settings]
+client_id = "test-client-id"
+hostname = "test.hostname.com"
+tcp_port = 1883
+failover_endpoints = [{ hostname = "test2.hostname.com", tcp_port = 1884 }]
+keep_alive = 30
+session_expiry = 600
+use_tls = false
+no_proxy = ["internal.net"]
+will_topic = "test/will"
+will_qos = 1
+
+[connection_settings.will_properties]
+content_type = "text/plain"
+
+[session_options]
+outgoing_max = 50
+tls_reload_debounce = 5
+recover_lost_session = true
+ordered_acks = false
+held_ack_warning_threshold = 30
+"#;
+
+    const YAML: &str = r#"
+connection_settings:
+  client_id: test-client-id
+  hostname: test.hostname.com
+  tcp_port: 1883
+  failover_endpoints:
+    - hostname: test2.hostname.com
+      tcp_port: 1884
+  keep_alive: 30
+  session_expiry: 600
+  use_tls: false
+  no_proxy: [internal.net]
+  will_topic: test/will
+  will_qos: 1
+  will_properties:
+    content_type: text/plain
+session_options:
+  outgoing_max: 50
+  tls_reload_debounce: 5
+  recover_lost_session: true
+  ordered_acks: false
+  held_ack_warning_threshold: 30
+"#;
+
+    const JSON: &str = r#"{
+    "connection_settings": {
+        "client_id": "test-client-id",
+        "hostname": "test.hostname.com",
+        "tcp_port": 1883,
+        "failover_endpoints": [{ "hostname": "test2.hostname.com", "tcp_port": 1884 }],
+        "keep_alive": 30,
+        "session_expiry": 600,
+        "use_tls": false,
+        "no_proxy": ["internal.net"],
+        "will_topic": "test/will",
+        "will_qos": 1,
+        "will_properties": { "content_type": "text/plain" }
+    },
+    "session_options": {
+        "outgoing_max": 50,
+        "tls_reload_debounce": 5,
+        "recover_lost_session": true,
+        "ordered_acks": false,
+        "held_ack_warning_threshold": 30
+    }
+}"#;
+
+    /// Write a config file with the provided name and contents to a temporary directory
+    fn write_config_file(name: &str, contents: &str) -> (tempfile::TempDir, std::path::PathBuf) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        (dir, path)
+    }
+
+    #[test_case("config.toml", TOML; "toml")]
+    #[test_case("config.yaml", YAML; "yaml")]
+    #[test_case("config.yml", YAML; "yml")]
+    #[test_case("config.json", JSON; "json")]
+    fn read(name: &str, contents: &str) {
+        let (_dir, path) = write_config_file(name, contents);
+        let config_file = ConfigFile::read(&path).unwrap();
+
+        let connection_settings = config_file
+            .connection_settings_builder()
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(connection_settings.client_id, "test-client-id");
+        assert_eq!(connection_settings.hostname, "test.hostname.com");
+        assert_eq!(connection_settings.tcp_port, 1883);
+        assert_eq!(
+            connection_settings.failover_endpoints,
+            vec![("test2.hostname.com".to_string(), 1884)]
+        );
+        assert_eq!(connection_settings.keep_alive, Duration::from_secs(30));
+        assert_eq!(connection_settings.session_expiry, Duration::from_secs(600));
+        assert!(!connection_settings.use_tls);
+        assert_eq!(
+            connection_settings.no_proxy,
+            vec!["internal.net".to_string()]
+        );
+        assert_eq!(
+            connection_settings.will_topic,
+            Some("test/will".to_string())
+        );
+        assert_eq!(connection_settings.will_qos, QoS::AtLeastOnce);
+        assert_eq!(
+            connection_settings.will_properties.unwrap().content_type,
+            Some("text/plain".to_string())
+        );
+
+        let session_options = config_file
+            .session_options_builder()
+            .unwrap()
+            .connection_settings(connection_settings)
+            .build()
+            .unwrap();
+        assert_eq!(session_options.outgoing_max, 50);
+        assert_eq!(session_options.tls_reload_debounce, Duration::from_secs(5));
+        assert!(session_options.recover_lost_session);
+        assert!(!session_options.ordered_acks);
+        assert_eq!(
+            session_options.held_ack_warning_threshold,
+            Some(Duration::from_secs(30))
+        );
+        // Values not in the file are defaulted
+        assert!(session_options.aio_broker_features);
+    }
+
+    #[test_case("config.toml", "[connection_settings]\ntcp_port = \"1883\"", "connection_settings.tcp_port"; "wrong type")]
+    #[test_case("config.toml", "[connection_settings]\ntcp_port = 70000", "connection_settings.tcp_port"; "out of range")]
+    #[test_case("config.yaml", "connection_settings:\n  will_qos: 3", "connection_settings.will_qos"; "invalid qos")]
+    #[test_case("config.json", r#"{"connection_settings": {"failover_endpoints": [{"hostname": "a"}]}}"#, "connection_settings.failover_endpoints[0]"; "missing endpoint port")]
+    #[test_case("config.json", r#"{"session_options": {"outgoing_max": -1}}"#, "session_options.outgoing_max"; "negative")]
+    fn invalid_value(name: &str, contents: &str, expected_key: &str) {
+        let (_dir, path) = write_config_file(name, contents);
+        let config_file = ConfigFile::read(&path).unwrap();
+        let err = config_file
+            .connection_settings_builder()
+            .and_then(|_| config_file.session_options_builder())
+            .err()
+            .unwrap();
+        match err {
+            ConfigFileError::InvalidValue { key, .. } => assert_eq!(key, expected_key),
+            _ => panic!("Unexpected error: {err:?}"),
+        }
+    }
+
+    #[test_case("[connection_settings]\nhost_name = \"a\"", "connection_settings.host_name"; "connection settings")]
+    #[test_case("[session_options]\nreconnect_policy = \"a\"", "session_options.reconnect_policy"; "session options")]
+    #[test_case("[connection_settings.will_properties]\ndelay = 1", "connection_settings.will_properties.delay"; "nested")]
+    fn unknown_key(contents: &str, expected_key: &str) {
+        let (_dir, path) = write_config_file("config.toml", contents);
+        let config_file = ConfigFile::read(&path).unwrap();
+        let err = config_file
+            .connection_settings_builder()
+            .and_then(|_| config_file.session_options_builder())
+            .err()
+            .unwrap();
+        assert!(matches!(err, ConfigFileError::UnknownKey(key) if key == expected_key));
+    }
+
+    #[test]
+    fn unknown_table() {
+        let (_dir, path) = write_config_file("config.toml", "[settings]\nhostname = \"a\"");
+        assert!(matches!(
+            ConfigFile::read(&path),
+            Err(ConfigFileError::UnknownKey(key)) if key == "settings"
+        ));
+    }
+
+    #[test]
+    fn unsupported_format() {
+        let (_dir, path) = write_config_file("config.ini", "hostname = a");
+        assert!(matches!(
+            ConfigFile::read(&path),
+            Err(ConfigFileError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn malformed() {
+        let (_dir, path) = write_config_file("config.json", "{ not json");
+        assert!(matches!(
+            ConfigFile::read(&path),
+            Err(ConfigFileError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn empty_yaml() {
+        let (_dir, path) = write_config_file("config.yaml", "");
+        let config_file = ConfigFile::read(&path).unwrap();
+        assert!(config_file.connection_settings_builder().is_ok());
+        assert!(config_file.session_options_builder().is_ok());
+    }
+
+    #[test]
+    fn layered_precedence() {
+        let (_dir, path) = write_config_file(
+            "config.toml",
+            "[connection_settings]\nclient_id = \"file-client-id\"\nhostname = \"file.hostname.com\"\ntcp_port = 1883\nuse_tls = false",
+        );
+        temp_env::with_vars(
+            [
+                ("AIO_MQTT_CLIENT_ID", None),
+                ("AIO_BROKER_HOSTNAME", Some("env.hostname.com")),
+                ("AIO_BROKER_TCP_PORT", Some("1884")),
+            ],
+            || {
+                let connection_settings = MqttConnectionSettingsBuilder::from_config_file(&path)
+                    .unwrap()
+                    .tcp_port(1885u16)
+                    .build()
+                    .unwrap();
+                // Values only in the file are used
+                assert_eq!(connection_settings.client_id, "file-client-id");
+                assert!(!connection_settings.use_tls);
+                // Environment variables take precedence over the file
+                assert_eq!(connection_settings.hostname, "env.hostname.com");
+                // Values set on the builder take precedence over both
+                assert_eq!(connection_settings.tcp_port, 1885);
+            },
+        );
+    }
+
+    #[test]
+    fn layered_invalid_environment() {
+        let (_dir, path) = write_config_file("config.toml", "");
+        temp_env::with_var("AIO_BROKER_TCP_PORT", Some("not numeric"), || {
+            assert!(matches!(
+                MqttConnectionSettingsBuilder::from_config_file(&path),
+                Err(ConfigFileError::Environment(_))
+            ));
+        });
+    }
+}
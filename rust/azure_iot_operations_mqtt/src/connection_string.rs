@@ -0,0 +1,144 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Internal module for parsing and formatting connection strings of the form
+//! `Key1=value1;Key2=value2`.
+//!
+//! Values containing `;`, beginning with `"` or with leading or trailing whitespace are enclosed in
+//! double quotes, with any double quotes within them doubled (e.g. `Key="a;""b"""` for `a;"b"`).
+
+/// Parse a connection string into its key/value pairs, in order.
+///
+/// # Errors
+/// Returns a `String` describing the error if the connection string is malformed
+pub fn parse(connection_string: &str) -> Result<Vec<(String, String)>, String> {
+    let mut pairs = Vec::new();
+    let mut rest = connection_string;
+    loop {
+        rest = rest.trim_start();
+        // Skip empty segments (e.g. a trailing `;`)
+        if let Some(remaining) = rest.strip_prefix(';') {
+            rest = remaining;
+            continue;
+        }
+        if rest.is_empty() {
+            break;
+        }
+        let (key, remaining) = rest
+            .split_once('=')
+            .ok_or(format!("Expected format <key>=<value>. Found: {rest}"))?;
+        let key = key.trim();
+        if key.is_empty() || key.contains(';') {
+            return Err(format!("Malformed key: {key}"));
+        }
+        let remaining = remaining.trim_start();
+        let (value, remaining) = if let Some(quoted) = remaining.strip_prefix('"') {
+            let (value, remaining) =
+                parse_quoted(quoted).ok_or(format!("Unterminated quoted value for {key}"))?;
+            let remaining = remaining.trim_start();
+            if !remaining.is_empty() && !remaining.starts_with(';') {
+                return Err(format!(
+                    "Unexpected characters after quoted value for {key}"
+                ));
+            }
+            (value, remaining)
+        } else {
+            let (value, remaining) = remaining.split_once(';').unwrap_or((remaining, ""));
+            (value.trim_end().to_string(), remaining)
+        };
+        pairs.push((key.to_string(), value));
+        rest = remaining;
+    }
+    Ok(pairs)
+}
+
+/// Parse the remainder of a quoted value (following the opening quote), returning the unescaped
+/// value and the rest of the string following the closing quote.
+fn parse_quoted(quoted: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = quoted.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == '"' {
+            if let Some((_, '"')) = chars.peek() {
+                // Escaped quote
+                chars.next();
+            } else {
+                return Some((value, &quoted[i + 1..]));
+            }
+        }
+        value.push(c);
+    }
+    None
+}
+
+/// Format key/value pairs as a connection string, quoting values as necessary.
+pub fn format(pairs: &[(&str, String)]) -> String {
+    pairs
+        .iter()
+        .map(|(key, value)| {
+            let needs_quotes =
+                value.contains(';') || value.starts_with('"') || value.trim() != value.as_str();
+            if needs_quotes {
+                format!("{key}=\"{}\"", value.replace('"', "\"\""))
+            } else {
+                format!("{key}={value}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("HostName=localhost;TcpPort=1883", &[("HostName", "localhost"), ("TcpPort", "1883")]; "simple")]
+    #[test_case(" HostName = localhost ; TcpPort=1883; ", &[("HostName", "localhost"), ("TcpPort", "1883")]; "whitespace and trailing separator")]
+    #[test_case("Password=a=b", &[("Password", "a=b")]; "equals in value")]
+    #[test_case("Password=", &[("Password", "")]; "empty value")]
+    #[test_case("Headers=\"a:1;b:2\";TcpPort=1883", &[("Headers", "a:1;b:2"), ("TcpPort", "1883")]; "quoted")]
+    #[test_case("Password=\"a\"\"b\"", &[("Password", "a\"b")]; "escaped quote")]
+    #[test_case("", &[]; "empty")]
+    fn parse_valid(connection_string: &str, expected: &[(&str, &str)]) {
+        let pairs = parse(connection_string).unwrap();
+        let expected = expected
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(pairs, expected);
+    }
+
+    #[test_case("HostName"; "no equals")]
+    #[test_case("=localhost"; "no key")]
+    #[test_case("Password=\"abc"; "unterminated quote")]
+    #[test_case("Password=\"abc\"def"; "characters after quote")]
+    fn parse_invalid(connection_string: &str) {
+        assert!(parse(connection_string).is_err());
+    }
+
+    #[test]
+    fn format_round_trip() {
+        let pairs = [
+            ("HostName", "localhost".to_string()),
+            ("Headers", "a:1;b:2".to_string()),
+            ("Password", "\"quoted\"".to_string()),
+            ("Padded", " padded ".to_string()),
+            ("Empty", String::new()),
+        ];
+        let connection_string = format(&pairs);
+        assert_eq!(
+            connection_string,
+            "HostName=localhost;Headers=\"a:1;b:2\";Password=\"\"\"quoted\"\"\";Padded=\" padded \";Empty="
+        );
+        let parsed = parse(&connection_string).unwrap();
+        assert_eq!(
+            parsed,
+            pairs
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), v.clone()))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -11,8 +11,15 @@ pub type QoS = rumqttc::v5::mqttbytes::QoS;
 /// PUBLISH packet
 pub type Publish = rumqttc::v5::mqttbytes::v5::Publish;
 
+/// Reason code of a CONNACK packet
+pub type ConnectReturnCode = rumqttc::v5::mqttbytes::v5::ConnectReturnCode;
+/// Reason code of a DISCONNECT packet
+pub type DisconnectReasonCode = rumqttc::v5::mqttbytes::v5::DisconnectReasonCode;
+
 /// Properties for a CONNECT packet
 pub type ConnectProperties = rumqttc::v5::mqttbytes::v5::ConnectProperties;
+/// Properties for a CONNACK packet
+pub type ConnAckProperties = rumqttc::v5::mqttbytes::v5::ConnAckProperties;
 /// Properties for a PUBLISH packet
 pub type PublishProperties = rumqttc::v5::mqttbytes::v5::PublishProperties;
 /// Properties for a SUBSCRIBE packet
@@ -21,3 +28,193 @@ pub type SubscribeProperties = rumqttc::v5::mqttbytes::v5::SubscribeProperties;
 pub type UnsubscribeProperties = rumqttc::v5::mqttbytes::v5::UnsubscribeProperties;
 /// Properties for an AUTH packet
 pub type AuthProperties = rumqttc::v5::mqttbytes::v5::AuthProperties;
+/// Properties for the will message of a CONNECT packet
+pub type LastWillProperties = rumqttc::v5::mqttbytes::v5::LastWillProperties;
+
+/// Reason code of a PUBACK packet (or PUBREC packet, for Quality of Service 2) sent to
+/// acknowledge an incoming PUBLISH. See: MQTT 5.0 spec, 3.4.2.1
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[repr(u8)]
+pub enum PubAckReasonCode {
+    /// The message is accepted (0x00)
+    #[default]
+    Success = 0x00,
+    /// The message is accepted, but there are no subscribers for it (0x10)
+    NoMatchingSubscribers = 0x10,
+    /// The receiver does not accept the message, and does not wish to reveal the reason (0x80)
+    UnspecifiedError = 0x80,
+    /// The message is valid, but is not accepted by the receiver (0x83)
+    ImplementationSpecificError = 0x83,
+    /// The message is not authorized (0x87)
+    NotAuthorized = 0x87,
+    /// The topic name is not malformed, but is not accepted by the receiver (0x90)
+    TopicNameInvalid = 0x90,
+    /// The packet identifier is already in use (0x91)
+    PacketIdentifierInUse = 0x91,
+    /// An implementation or administrative imposed limit has been exceeded (0x97)
+    QuotaExceeded = 0x97,
+    /// The payload format does not match the payload format indicator (0x99)
+    PayloadFormatInvalid = 0x99,
+}
+
+impl PubAckReasonCode {
+    /// Returns true if the reason code indicates the message was not accepted (0x80 or greater)
+    #[must_use]
+    pub fn is_failure(self) -> bool {
+        self as u8 >= 0x80
+    }
+}
+
+/// Maximum value of a subscription identifier. See: MQTT 5.0 spec, 3.8.2.1.2
+const MAX_SUBSCRIPTION_IDENTIFIER: usize = 268_435_455;
+
+/// Retain Handling option of a subscription, indicating whether retained messages are sent when
+/// the subscription is established. See: MQTT 5.0 spec, 3.8.3.1
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RetainHandling {
+    /// Send retained messages at the time of the subscribe
+    #[default]
+    SendOnSubscribe,
+    /// Send retained messages at the time of the subscribe, only if the subscription does not
+    /// currently exist
+    SendOnNewSubscribe,
+    /// Do not send retained messages at the time of the subscribe
+    DoNotSend,
+}
+
+/// Options for a subscription in a SUBSCRIBE packet
+#[derive(Builder, Clone, Debug, Eq, PartialEq)]
+#[builder(pattern = "owned", setter(into), build_fn(validate = "Self::validate"))]
+pub struct SubscribeOptions {
+    /// Maximum Quality of Service of messages delivered on the subscription
+    #[builder(default = "QoS::AtLeastOnce")]
+    pub(crate) qos: QoS,
+    /// Indicates that messages published by this client are not delivered back to it
+    #[builder(default = "false")]
+    pub(crate) no_local: bool,
+    /// Indicates that messages delivered on the subscription keep the retain flag they were
+    /// published with
+    #[builder(default = "false")]
+    pub(crate) retain_as_published: bool,
+    /// Whether retained messages are sent when the subscription is established
+    #[builder(default)]
+    pub(crate) retain_handling: RetainHandling,
+    /// Subscription identifier, included by the broker in messages delivered on the subscription.
+    /// Must be between 1 and 268,435,455.
+    #[builder(default = "None", setter(strip_option))]
+    pub(crate) subscription_identifier: Option<usize>,
+    /// User properties of the SUBSCRIBE packet
+    #[builder(default = "Vec::new()")]
+    pub(crate) user_properties: Vec<(String, String)>,
+}
+
+impl SubscribeOptions {
+    /// Create [`SubscribeOptions`] equivalent to subscribing with the provided QoS and
+    /// (optional) [`SubscribeProperties`].
+    #[must_use]
+    pub fn from_properties(qos: QoS, properties: Option<SubscribeProperties>) -> Self {
+        let (subscription_identifier, user_properties) = match properties {
+            Some(properties) => (properties.id, properties.user_properties),
+            None => (None, Vec::new()),
+        };
+        Self {
+            qos,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: RetainHandling::default(),
+            subscription_identifier,
+            user_properties,
+        }
+    }
+
+    /// Maximum Quality of Service of messages delivered on the subscription
+    #[must_use]
+    pub fn qos(&self) -> QoS {
+        self.qos
+    }
+
+    /// Subscription identifier, if any
+    #[must_use]
+    pub fn subscription_identifier(&self) -> Option<usize> {
+        self.subscription_identifier
+    }
+
+    /// Return the [`SubscribeProperties`] of the SUBSCRIBE packet
+    #[must_use]
+    pub fn properties(&self) -> SubscribeProperties {
+        SubscribeProperties {
+            id: self.subscription_identifier,
+            user_properties: self.user_properties.clone(),
+        }
+    }
+}
+
+impl SubscribeOptionsBuilder {
+    /// Validate the [`SubscribeOptions`].
+    ///
+    /// # Errors
+    /// Returns a `String` describing the error if the subscription identifier is out of range.
+    fn validate(&self) -> Result<(), String> {
+        match self.subscription_identifier {
+            Some(Some(subscription_identifier))
+                if !(1..=MAX_SUBSCRIPTION_IDENTIFIER).contains(&subscription_identifier) =>
+            {
+                Err(format!(
+                    "subscription_identifier must be between 1 and {MAX_SUBSCRIPTION_IDENTIFIER}"
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(PubAckReasonCode::Success, false; "success")]
+    #[test_case(PubAckReasonCode::NoMatchingSubscribers, false; "no matching subscribers")]
+    #[test_case(PubAckReasonCode::UnspecifiedError, true; "unspecified error")]
+    #[test_case(PubAckReasonCode::ImplementationSpecificError, true; "implementation specific error")]
+    #[test_case(PubAckReasonCode::QuotaExceeded, true; "quota exceeded")]
+    fn pub_ack_reason_code_is_failure(reason_code: PubAckReasonCode, is_failure: bool) {
+        assert_eq!(reason_code.is_failure(), is_failure);
+    }
+
+    #[test]
+    fn subscribe_options_defaults() {
+        let options = SubscribeOptionsBuilder::default().build().unwrap();
+        assert_eq!(
+            options,
+            SubscribeOptions::from_properties(QoS::AtLeastOnce, None)
+        );
+        assert_eq!(options.retain_handling, RetainHandling::SendOnSubscribe);
+        assert!(!options.no_local);
+        assert!(!options.retain_as_published);
+    }
+
+    #[test_case(1; "min")]
+    #[test_case(268_435_455; "max")]
+    fn subscription_identifier_valid(subscription_identifier: usize) {
+        let options = SubscribeOptionsBuilder::default()
+            .subscription_identifier(subscription_identifier)
+            .user_properties(vec![("key".to_string(), "value".to_string())])
+            .build()
+            .unwrap();
+        let properties = options.properties();
+        assert_eq!(properties.id, Some(subscription_identifier));
+        assert_eq!(properties.user_properties.len(), 1);
+    }
+
+    #[test_case(0; "zero")]
+    #[test_case(268_435_456; "too large")]
+    fn subscription_identifier_invalid(subscription_identifier: usize) {
+        assert!(
+            SubscribeOptionsBuilder::default()
+                .subscription_identifier(subscription_identifier)
+                .build()
+                .is_err()
+        );
+    }
+}
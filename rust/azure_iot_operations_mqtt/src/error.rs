@@ -46,6 +46,10 @@ pub enum PublishErrorKind {
     DetachedClient,
     /// Invalid topic name provided
     InvalidTopicName,
+    /// The offline publish queue is at capacity
+    OfflineQueueFull,
+    /// The publish could not be written to the offline publish queue
+    OfflineQueueWrite,
 }
 
 impl fmt::Display for PublishErrorKind {
@@ -55,6 +59,10 @@ impl fmt::Display for PublishErrorKind {
                 write!(f, "client is detached from connection/event loop")
             }
             PublishErrorKind::InvalidTopicName => write!(f, "invalid topic name"),
+            PublishErrorKind::OfflineQueueFull => write!(f, "offline publish queue is full"),
+            PublishErrorKind::OfflineQueueWrite => {
+                write!(f, "cannot write to offline publish queue")
+            }
         }
     }
 }
@@ -87,6 +95,8 @@ pub enum SubscribeErrorKind {
     DetachedClient,
     /// Invalid topic filter provided
     InvalidTopicFilter,
+    /// The subscribe options (e.g. a subscription identifier) are not supported by the client
+    OptionsNotSupported,
 }
 
 impl fmt::Display for SubscribeErrorKind {
@@ -96,6 +106,9 @@ impl fmt::Display for SubscribeErrorKind {
                 write!(f, "client is detached from connection/event loop")
             }
             SubscribeErrorKind::InvalidTopicFilter => write!(f, "invalid topic filter"),
+            SubscribeErrorKind::OptionsNotSupported => {
+                write!(f, "subscribe options not supported by the client")
+            }
         }
     }
 }
@@ -169,6 +182,8 @@ pub enum AckErrorKind {
     DetachedClient,
     /// The publish has already been sufficiently acknowledged
     AlreadyAcked,
+    /// The MQTT session the publish was received on has been lost, so it cannot be acknowledged
+    SessionLost,
 }
 
 impl fmt::Display for AckErrorKind {
@@ -178,6 +193,9 @@ impl fmt::Display for AckErrorKind {
                 write!(f, "client is detached from connection/event loop")
             }
             AckErrorKind::AlreadyAcked => write!(f, "publish already acknowledged"),
+            AckErrorKind::SessionLost => {
+                write!(f, "MQTT session the publish was received on was lost")
+            }
         }
     }
 }
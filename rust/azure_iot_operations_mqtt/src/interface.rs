@@ -3,17 +3,24 @@
 
 //! Traits and types for defining sets and subsets of MQTT client functionality.
 
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use bytes::Bytes;
 
+use crate::auth::AuthProvider;
 use crate::control_packet::{
-    AuthProperties, Publish, PublishProperties, QoS, SubscribeProperties, UnsubscribeProperties,
+    AuthProperties, PubAckReasonCode, Publish, PublishProperties, QoS, SubscribeOptions,
+    SubscribeProperties, UnsubscribeProperties,
 };
 use crate::error::{
     AckError, CompletionError, ConnectionError, DisconnectError, PublishError, ReauthError,
-    SubscribeError, UnsubscribeError,
+    SubscribeError, SubscribeErrorKind, UnsubscribeError,
 };
 pub use crate::session::receiver::AckToken; // TODO: remove this pub re-export after concretized receivers / managed clients
+pub use crate::session::receiver::OverflowPolicy;
+pub use crate::session::receiver::PublishFilter;
 use crate::topic::TopicParseError;
 
 // ---------- Concrete Types ----------
@@ -56,6 +63,8 @@ pub type Event = rumqttc::v5::Event;
 pub type Incoming = rumqttc::v5::Incoming;
 /// Outgoing data on the event loop
 pub type Outgoing = rumqttc::Outgoing;
+/// Network transport used by the event loop
+pub type Transport = rumqttc::Transport;
 
 // ---------- Lower level MQTT abstractions ----------
 
@@ -108,6 +117,31 @@ pub trait MqttPubSub {
         properties: SubscribeProperties,
     ) -> Result<CompletionToken, SubscribeError>;
 
+    /// MQTT Subscribe with typed [`SubscribeOptions`], such as No Local, Retain As Published,
+    /// Retain Handling or a subscription identifier.
+    ///
+    /// If connection is unavailable, subscribe will be queued and delivered when connection is re-established.
+    /// Blocks if at capacity for queueing.
+    ///
+    /// By default, only options that can be expressed as a QoS and [`SubscribeProperties`] are
+    /// supported, and the subscribe is made with [`MqttPubSub::subscribe_with_properties`].
+    ///
+    /// # Errors
+    /// Returns a [`SubscribeError`] of kind [`SubscribeErrorKind::OptionsNotSupported`] if the
+    /// options are not supported.
+    async fn subscribe_with_options(
+        &self,
+        topic: impl Into<String> + Send,
+        options: SubscribeOptions,
+    ) -> Result<CompletionToken, SubscribeError> {
+        let properties = options.properties();
+        if options != SubscribeOptions::from_properties(options.qos(), Some(properties.clone())) {
+            return Err(SubscribeError::new(SubscribeErrorKind::OptionsNotSupported));
+        }
+        self.subscribe_with_properties(topic, options.qos(), properties)
+            .await
+    }
+
     /// MQTT Unsubscribe
     ///
     /// If connection is unavailable, unsubscribe will be queued and delivered when connection is re-established.
@@ -133,6 +167,20 @@ pub trait MqttPubSub {
 pub trait MqttAck {
     /// Acknowledge a received Publish.
     async fn ack(&self, publish: &Publish) -> Result<CompletionToken, AckError>;
+
+    /// Acknowledge a received Publish with the provided reason code and (optional) reason
+    /// string, e.g. to indicate that the Publish was rejected.
+    ///
+    /// By default, the reason code and reason string are dropped, and the Publish is
+    /// acknowledged with [`MqttAck::ack`].
+    async fn ack_with_reason(
+        &self,
+        publish: &Publish,
+        _reason_code: PubAckReasonCode,
+        _reason_string: Option<String>,
+    ) -> Result<CompletionToken, AckError> {
+        self.ack(publish).await
+    }
 }
 
 // TODO: consider scoping this to also include a `connect`. Not currently needed, but would be more flexible,
@@ -166,6 +214,23 @@ pub trait MqttEventLoop {
 
     /// Set the authentication data
     fn set_authentication_data(&mut self, authentication_data: Option<Bytes>);
+
+    /// Respond to authentication challenges (AUTH packets with reason code
+    /// `ContinueAuthentication`) from the MQTT broker using the provided [`AuthProvider`], both
+    /// while connecting and while re-authenticating.
+    ///
+    /// By default, challenges are not responded to, and this does nothing.
+    fn set_auth_provider(&mut self, _auth_provider: Arc<dyn AuthProvider>) {}
+
+    /// Set the network transport for subsequent MQTT connection attempts.
+    ///
+    /// By default, the transport cannot be changed, and this does nothing.
+    fn set_transport(&mut self, _transport: Transport) {}
+
+    /// Set the MQTT broker endpoint (hostname, TCP port) for subsequent MQTT connection attempts.
+    ///
+    /// By default, the endpoint cannot be changed, and this does nothing.
+    fn set_broker_endpoint(&mut self, _hostname: &str, _port: u16) {}
 }
 
 // ---------- Higher level MQTT abstractions ----------
@@ -188,6 +253,66 @@ pub trait ManagedClient: MqttPubSub {
         topic_filter: &str,
     ) -> Result<Self::PubReceiver, TopicParseError>;
 
+    /// Creates a new [`PubReceiver`] that receives messages on a specific topic, holding at most
+    /// `capacity` messages that have not yet been received. When full, incoming messages are
+    /// handled according to the provided [`OverflowPolicy`].
+    ///
+    /// Any message dropped due to overflow is acknowledged.
+    ///
+    /// By default, capacity is not supported, and this creates an unbounded receiver with
+    /// [`ManagedClient::create_filtered_pub_receiver`].
+    ///
+    /// # Errors
+    /// Returns a [`TopicParseError`] if the pub receiver cannot be registered.
+    fn create_bounded_filtered_pub_receiver(
+        &self,
+        topic_filter: &str,
+        _capacity: NonZeroUsize,
+        _overflow_policy: OverflowPolicy,
+    ) -> Result<Self::PubReceiver, TopicParseError> {
+        self.create_filtered_pub_receiver(topic_filter)
+    }
+
+    /// Creates a new [`PubReceiver`] that receives messages on a specific topic that also match
+    /// the provided [`PublishFilter`] (e.g. on content type or user properties).
+    ///
+    /// Messages on the topic that do not match the [`PublishFilter`] are not delivered to this
+    /// receiver. If no other receiver matches them either, they are acknowledged.
+    ///
+    /// By default, content filtering is not supported, and this returns
+    /// [`TopicParseError::ContentFilterNotSupported`].
+    ///
+    /// # Errors
+    /// Returns a [`TopicParseError`] if the pub receiver cannot be registered.
+    fn create_content_filtered_pub_receiver(
+        &self,
+        topic_filter: &str,
+        _publish_filter: PublishFilter,
+    ) -> Result<Self::PubReceiver, TopicParseError> {
+        Err(TopicParseError::ContentFilterNotSupported(
+            topic_filter.to_string(),
+        ))
+    }
+
+    /// Creates a new [`PubReceiver`] that receives messages delivered on the subscription(s) made
+    /// with the provided subscription identifier (see [`SubscribeOptions`]).
+    ///
+    /// Messages are routed to this receiver by the subscription identifiers the broker includes
+    /// with them, rather than by topic, so that a message matching several overlapping
+    /// subscriptions is only delivered to the receivers of the subscriptions it was sent for.
+    ///
+    /// By default, subscription identifiers are not supported, and this returns a
+    /// [`SubscribeError`] of kind [`SubscribeErrorKind::OptionsNotSupported`].
+    ///
+    /// # Errors
+    /// Returns a [`SubscribeError`] if subscription identifiers are not supported.
+    fn create_identified_pub_receiver(
+        &self,
+        _subscription_identifier: usize,
+    ) -> Result<Self::PubReceiver, SubscribeError> {
+        Err(SubscribeError::new(SubscribeErrorKind::OptionsNotSupported))
+    }
+
     /// Creates a new [`PubReceiver`] that receives all messages not sent to other
     /// filtered receivers.
     fn create_unfiltered_pub_receiver(&self) -> Self::PubReceiver;
@@ -10,7 +10,8 @@ use bytes::Bytes;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, error::SendError, unbounded_channel};
 
 use crate::control_packet::{
-    AuthProperties, Publish, PublishProperties, QoS, SubscribeProperties, UnsubscribeProperties,
+    AuthProperties, PubAckReasonCode, Publish, PublishProperties, QoS, SubscribeOptions,
+    SubscribeProperties, UnsubscribeProperties,
 };
 use crate::error::{
     AckError, CompletionError, ConnectionError, DisconnectError, PublishError, ReauthError,
@@ -60,6 +61,7 @@ pub struct SubscribeCall {
     pub topic: String,
     pub qos: QoS,
     pub properties: Option<SubscribeProperties>,
+    pub options: Option<SubscribeOptions>,
 }
 
 #[derive(Clone)]
@@ -73,6 +75,8 @@ pub struct UnsubscribeCall {
 #[allow(missing_docs)]
 pub struct AckCall {
     pub publish: Publish,
+    pub reason_code: PubAckReasonCode,
+    pub reason_string: Option<String>,
 }
 
 /// Call data for [`MockClient`]
@@ -246,6 +250,7 @@ impl MqttPubSub for MockClient {
             topic: topic.into(),
             qos,
             properties: None,
+            options: None,
         };
         self.shared_tracker
             .lock()
@@ -265,6 +270,26 @@ impl MqttPubSub for MockClient {
             topic: topic.into(),
             qos,
             properties: Some(properties),
+            options: None,
+        };
+        self.shared_tracker
+            .lock()
+            .unwrap()
+            .call_sequence
+            .push(MockClientCall::Subscribe(call));
+        Ok(CompletionToken(Box::new(CompletedAckFuture {})))
+    }
+
+    async fn subscribe_with_options(
+        &self,
+        topic: impl Into<String> + Send,
+        options: SubscribeOptions,
+    ) -> Result<CompletionToken, SubscribeError> {
+        let call = SubscribeCall {
+            topic: topic.into(),
+            qos: options.qos(),
+            properties: Some(options.properties()),
+            options: Some(options),
         };
         self.shared_tracker
             .lock()
@@ -311,8 +336,20 @@ impl MqttPubSub for MockClient {
 #[async_trait]
 impl MqttAck for MockClient {
     async fn ack(&self, publish: &Publish) -> Result<CompletionToken, AckError> {
+        self.ack_with_reason(publish, PubAckReasonCode::Success, None)
+            .await
+    }
+
+    async fn ack_with_reason(
+        &self,
+        publish: &Publish,
+        reason_code: PubAckReasonCode,
+        reason_string: Option<String>,
+    ) -> Result<CompletionToken, AckError> {
         let call = AckCall {
             publish: publish.clone(),
+            reason_code,
+            reason_string,
         };
         self.shared_tracker
             .lock()
@@ -8,23 +8,40 @@
 //! Use the components of the [`session`] module to communicate over MQTT with
 //! an automatically managed connection across a single MQTT session.
 
+#[cfg(feature = "config-file")]
+pub use crate::config_file::ConfigFileError;
 pub use crate::connection_settings::{
     MqttConnectionSettings, MqttConnectionSettingsBuilder, MqttConnectionSettingsBuilderError,
 };
+pub use crate::credentials::{SecretString, TlsData};
 
-mod auth;
+pub mod auth;
+#[cfg(feature = "capture")]
+pub mod capture;
+#[cfg(feature = "config-file")]
+mod config_file;
 mod connection_settings;
+mod connection_string;
 pub mod control_packet;
+mod credentials;
 pub mod error;
 pub mod interface;
+pub mod metrics;
+mod proxy;
 pub mod session;
+mod tls_watcher;
 pub mod topic;
 
 // TODO: put behind `use-rumqttc` feature flag
 mod rumqttc_adapter;
 
+#[cfg(not(any(feature = "use-native-tls", feature = "use-rustls")))]
+compile_error!("Either the `use-native-tls` or `use-rustls` feature must be enabled");
+
 #[cfg(feature = "test-utils")]
 pub mod interface_mocks;
+#[cfg(feature = "test-utils")]
+pub mod test_broker;
 
 #[macro_use]
 extern crate derive_builder;
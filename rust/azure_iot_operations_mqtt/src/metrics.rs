@@ -0,0 +1,334 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Metrics recorded by a [`Session`](crate::session::Session) and its managed clients.
+//!
+//! Metrics are recorded through a [`Meter`], which can be provided with
+//! [`SessionOptions::meter`](crate::session::SessionOptions::meter). With the `metrics` feature,
+//! [`OpenTelemetryMeter`] records them as OpenTelemetry instruments.
+//!
+//! The following metrics are recorded:
+//!
+//! | Name | Kind | Unit | Attributes |
+//! | --- | --- | --- | --- |
+//! | [`PUBLISHES_SENT`] | counter | publishes | `qos` |
+//! | [`PUBLISHES_ACKED`] | counter | publishes | `qos` |
+//! | [`PUBLISH_ACK_DURATION`] | histogram | seconds | `qos` |
+//! | [`OUTGOING_WAIT_DURATION`] | histogram | seconds | |
+//! | [`SUBSCRIBES_SENT`] | counter | subscribes | |
+//! | [`MESSAGES_RECEIVED`] | counter | messages | `receiver` |
+//! | [`ACK_QUEUE_DEPTH`] | gauge | publishes | |
+//! | [`ACK_DURATION`] | histogram | seconds | |
+//! | [`RECONNECTS`] | counter | reconnects | |
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::control_packet::QoS;
+
+/// Number of publishes sent. Publishes queued while disconnected are counted when queued.
+pub const PUBLISHES_SENT: &str = "mqtt.client.publishes.sent";
+/// Number of QoS 1 and 2 publishes acknowledged by the MQTT broker
+pub const PUBLISHES_ACKED: &str = "mqtt.client.publishes.acked";
+/// Time between sending a QoS 1 or 2 publish and its acknowledgement by the MQTT broker
+pub const PUBLISH_ACK_DURATION: &str = "mqtt.client.publish.ack.duration";
+/// Time spent waiting for the outgoing queue to accept a publish. Consistently high values
+/// indicate the outgoing queue is saturated.
+pub const OUTGOING_WAIT_DURATION: &str = "mqtt.client.outgoing.wait.duration";
+/// Number of subscribes sent
+pub const SUBSCRIBES_SENT: &str = "mqtt.client.subscribes.sent";
+/// Number of received publishes dispatched to each receiver. The `receiver` attribute is the
+/// topic filter of the receiver, or `unfiltered`.
+pub const MESSAGES_RECEIVED: &str = "mqtt.client.messages.received";
+/// Number of received QoS 1 and 2 publishes waiting to be acknowledged, in order
+pub const ACK_QUEUE_DEPTH: &str = "mqtt.client.ack_queue.depth";
+/// Time between dispatching a received QoS 1 or 2 publish and acknowledging it
+pub const ACK_DURATION: &str = "mqtt.client.ack.duration";
+/// Number of reconnect attempts scheduled
+pub const RECONNECTS: &str = "mqtt.client.reconnects";
+
+/// Interface for recording metrics to a metrics backend.
+///
+/// Each metric is identified by its name (see the constants in this module), and may have
+/// attributes that further identify it.
+pub trait Meter: Send + Sync {
+    /// Add to a monotonically increasing counter
+    fn add(&self, name: &'static str, value: u64, attributes: &[(&'static str, String)]);
+    /// Record a value in a histogram
+    fn record(&self, name: &'static str, value: f64, attributes: &[(&'static str, String)]);
+    /// Record the current value of a gauge
+    fn gauge(&self, name: &'static str, value: u64, attributes: &[(&'static str, String)]);
+}
+
+/// Internal helper for recording metrics to an optional [`Meter`]
+#[derive(Clone, Default)]
+pub(crate) struct Metrics(Option<Arc<dyn Meter>>);
+
+impl Metrics {
+    /// Create a new [`Metrics`] recording to the provided [`Meter`]
+    pub fn new(meter: Arc<dyn Meter>) -> Self {
+        Self(Some(meter))
+    }
+
+    /// Record that a publish was sent
+    pub fn publish_sent(&self, qos: QoS) {
+        if let Some(meter) = &self.0 {
+            meter.add(PUBLISHES_SENT, 1, &[("qos", qos_attribute(qos))]);
+        }
+    }
+
+    /// Record that a publish was acknowledged by the MQTT broker after the provided duration
+    pub fn publish_acked(&self, qos: QoS, duration: Duration) {
+        if let Some(meter) = &self.0 {
+            let attributes = [("qos", qos_attribute(qos))];
+            meter.add(PUBLISHES_ACKED, 1, &attributes);
+            meter.record(PUBLISH_ACK_DURATION, duration.as_secs_f64(), &attributes);
+        }
+    }
+
+    /// Record the time spent waiting for the outgoing queue to accept a publish
+    pub fn outgoing_wait(&self, duration: Duration) {
+        if let Some(meter) = &self.0 {
+            meter.record(OUTGOING_WAIT_DURATION, duration.as_secs_f64(), &[]);
+        }
+    }
+
+    /// Record that a subscribe was sent
+    pub fn subscribe_sent(&self) {
+        if let Some(meter) = &self.0 {
+            meter.add(SUBSCRIBES_SENT, 1, &[]);
+        }
+    }
+
+    /// Record that a received publish was dispatched to a receiver with the provided topic
+    /// filter, or to an unfiltered receiver if `None`
+    pub fn message_received(&self, receiver: Option<&str>) {
+        if let Some(meter) = &self.0 {
+            meter.add(
+                MESSAGES_RECEIVED,
+                1,
+                &[("receiver", receiver.unwrap_or("unfiltered").to_string())],
+            );
+        }
+    }
+
+    /// Record the number of received publishes waiting to be acknowledged
+    pub fn ack_queue_depth(&self, depth: usize) {
+        if let Some(meter) = &self.0 {
+            meter.gauge(ACK_QUEUE_DEPTH, depth as u64, &[]);
+        }
+    }
+
+    /// Record that a received publish was acknowledged after the provided duration
+    pub fn acked(&self, duration: Duration) {
+        if let Some(meter) = &self.0 {
+            meter.record(ACK_DURATION, duration.as_secs_f64(), &[]);
+        }
+    }
+
+    /// Record that a reconnect attempt was scheduled
+    pub fn reconnect(&self) {
+        if let Some(meter) = &self.0 {
+            meter.add(RECONNECTS, 1, &[]);
+        }
+    }
+}
+
+/// Helper function to format a [`QoS`] as a metric attribute
+fn qos_attribute(qos: QoS) -> String {
+    match qos {
+        QoS::AtMostOnce => "0",
+        QoS::AtLeastOnce => "1",
+        QoS::ExactlyOnce => "2",
+    }
+    .to_string()
+}
+
+#[cfg(feature = "metrics")]
+pub use opentelemetry_meter::OpenTelemetryMeter;
+
+#[cfg(feature = "metrics")]
+mod opentelemetry_meter {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use opentelemetry::KeyValue;
+    use opentelemetry::metrics::{Counter, Gauge, Histogram};
+
+    use super::{
+        ACK_DURATION, ACK_QUEUE_DEPTH, MESSAGES_RECEIVED, Meter, OUTGOING_WAIT_DURATION,
+        PUBLISH_ACK_DURATION, PUBLISHES_ACKED, PUBLISHES_SENT, RECONNECTS, SUBSCRIBES_SENT,
+    };
+
+    /// [`Meter`] that records metrics as OpenTelemetry instruments created from an
+    /// [`opentelemetry::metrics::Meter`].
+    pub struct OpenTelemetryMeter {
+        /// OpenTelemetry meter used to create instruments
+        meter: opentelemetry::metrics::Meter,
+        /// Counters, created on first use
+        counters: Mutex<HashMap<&'static str, Counter<u64>>>,
+        /// Histograms, created on first use
+        histograms: Mutex<HashMap<&'static str, Histogram<f64>>>,
+        /// Gauges, created on first use
+        gauges: Mutex<HashMap<&'static str, Gauge<u64>>>,
+    }
+
+    impl OpenTelemetryMeter {
+        /// Create a new [`OpenTelemetryMeter`] creating instruments from the provided
+        /// [`opentelemetry::metrics::Meter`]
+        #[must_use]
+        pub fn new(meter: opentelemetry::metrics::Meter) -> Self {
+            Self {
+                meter,
+                counters: Mutex::new(HashMap::new()),
+                histograms: Mutex::new(HashMap::new()),
+                gauges: Mutex::new(HashMap::new()),
+            }
+        }
+    }
+
+    impl Meter for OpenTelemetryMeter {
+        fn add(&self, name: &'static str, value: u64, attributes: &[(&'static str, String)]) {
+            self.counters
+                .lock()
+                .unwrap()
+                .entry(name)
+                .or_insert_with(|| {
+                    self.meter
+                        .u64_counter(name)
+                        .with_description(description(name))
+                        .build()
+                })
+                .add(value, &key_values(attributes));
+        }
+
+        fn record(&self, name: &'static str, value: f64, attributes: &[(&'static str, String)]) {
+            self.histograms
+                .lock()
+                .unwrap()
+                .entry(name)
+                .or_insert_with(|| {
+                    self.meter
+                        .f64_histogram(name)
+                        .with_description(description(name))
+                        .with_unit("s")
+                        .build()
+                })
+                .record(value, &key_values(attributes));
+        }
+
+        fn gauge(&self, name: &'static str, value: u64, attributes: &[(&'static str, String)]) {
+            self.gauges
+                .lock()
+                .unwrap()
+                .entry(name)
+                .or_insert_with(|| {
+                    self.meter
+                        .u64_gauge(name)
+                        .with_description(description(name))
+                        .build()
+                })
+                .record(value, &key_values(attributes));
+        }
+    }
+
+    /// Helper function to convert attributes to OpenTelemetry [`KeyValue`]s
+    fn key_values(attributes: &[(&'static str, String)]) -> Vec<KeyValue> {
+        attributes
+            .iter()
+            .map(|(key, value)| KeyValue::new(*key, value.clone()))
+            .collect()
+    }
+
+    /// Helper function to get the description of a metric
+    fn description(name: &'static str) -> &'static str {
+        match name {
+            PUBLISHES_SENT => "Number of publishes sent",
+            PUBLISHES_ACKED => "Number of publishes acknowledged by the MQTT broker",
+            PUBLISH_ACK_DURATION => "Time until a publish is acknowledged by the MQTT broker",
+            OUTGOING_WAIT_DURATION => "Time spent waiting for the outgoing queue",
+            SUBSCRIBES_SENT => "Number of subscribes sent",
+            MESSAGES_RECEIVED => "Number of received publishes dispatched to each receiver",
+            ACK_QUEUE_DEPTH => "Number of received publishes waiting to be acknowledged",
+            ACK_DURATION => "Time until a received publish is acknowledged",
+            RECONNECTS => "Number of reconnect attempts",
+            _ => "",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// [`Meter`] that records all calls
+    #[derive(Default)]
+    struct RecordingMeter(Mutex<Vec<(&'static str, f64, Vec<(&'static str, String)>)>>);
+
+    impl Meter for RecordingMeter {
+        #[allow(clippy::cast_precision_loss)]
+        fn add(&self, name: &'static str, value: u64, attributes: &[(&'static str, String)]) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((name, value as f64, attributes.to_vec()));
+        }
+
+        fn record(&self, name: &'static str, value: f64, attributes: &[(&'static str, String)]) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((name, value, attributes.to_vec()));
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        fn gauge(&self, name: &'static str, value: u64, attributes: &[(&'static str, String)]) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((name, value as f64, attributes.to_vec()));
+        }
+    }
+
+    #[test]
+    fn no_meter() {
+        // Recording without a meter is a no-op
+        let metrics = Metrics::default();
+        metrics.publish_sent(QoS::AtLeastOnce);
+        metrics.reconnect();
+    }
+
+    #[test]
+    fn records_to_meter() {
+        let meter = Arc::new(RecordingMeter::default());
+        let metrics = Metrics::new(meter.clone());
+        metrics.publish_sent(QoS::AtLeastOnce);
+        metrics.publish_acked(QoS::AtLeastOnce, Duration::from_millis(500));
+        metrics.message_received(Some("sensors/+"));
+        metrics.message_received(None);
+        metrics.ack_queue_depth(3);
+
+        let recorded = meter.0.lock().unwrap();
+        assert_eq!(
+            *recorded,
+            vec![
+                (PUBLISHES_SENT, 1.0, vec![("qos", "1".to_string())]),
+                (PUBLISHES_ACKED, 1.0, vec![("qos", "1".to_string())]),
+                (PUBLISH_ACK_DURATION, 0.5, vec![("qos", "1".to_string())]),
+                (
+                    MESSAGES_RECEIVED,
+                    1.0,
+                    vec![("receiver", "sensors/+".to_string())]
+                ),
+                (
+                    MESSAGES_RECEIVED,
+                    1.0,
+                    vec![("receiver", "unfiltered".to_string())]
+                ),
+                (ACK_QUEUE_DEPTH, 3.0, vec![]),
+            ]
+        );
+    }
+}
@@ -8,7 +8,10 @@
 //! * [`SessionManagedClient`] - Sends MQTT messages to the broker
 //! * [`SessionPubReceiver`] - Receives MQTT messages from the broker
 //! * [`SessionConnectionMonitor`] - Provides information about MQTT connection state
+//! * [`SessionEventStream`] - Provides [`SessionEvent`]s describing the lifecycle of the [`Session`]
 //! * [`SessionExitHandle`] - Allows the user to exit the session gracefully
+//! * [`NamespacedManagedClient`] - Confines a managed client to a topic namespace, isolating
+//!   multiple tenants sharing a [`Session`]
 //!
 //! # [`Session`] lifespan
 //! Each instance of [`Session`] is single use - after configuring a [`Session`], and creating any
@@ -18,7 +21,9 @@
 //! the broker.
 //!
 //! The MQTT session can be ended one of three ways:
-//! 1. The MQTT broker ends the MQTT session
+//! 1. The MQTT broker ends the MQTT session (unless the [`Session`] is configured to recover from
+//!    the loss of the MQTT session with
+//!    [`recover_lost_session`](crate::session::SessionOptions::recover_lost_session))
 //! 2. The [`ReconnectPolicy`](crate::session::reconnect_policy::ReconnectPolicy) configured on the
 //!    [`Session`] halts reconnection attempts, causing the [`Session`] to end the MQTT session.
 //! 3. The user uses the [`SessionExitHandle`] to end the MQTT session.
@@ -42,7 +47,11 @@
 //! discarded. Thus, in order to guarantee that messages will not be lost, you should create the
 //! [`SessionPubReceiver`] *before* subscribing to the topic filter.
 
+mod endpoints;
+mod events;
 pub mod managed_client; // TODO: This really ought be private, but we need it public for testing
+mod namespaced_client;
+mod offline_queue;
 pub(crate) mod receiver;
 pub mod reconnect_policy;
 #[doc(hidden)]
@@ -50,15 +59,18 @@ pub mod reconnect_policy;
 // This isn't ideal naming, but it'd be inconsistent otherwise.
 pub mod session; // TODO: Make this private and accessible via compile flags
 mod state;
+mod subscription_registry;
 mod wrapper;
 
 use std::fmt;
 
 use thiserror::Error;
 
-use crate::auth::SatAuthContextInitError;
+use crate::auth::{AuthProviderError, SatAuthContextInitError};
 use crate::error::{ConnectionError, DisconnectError};
 use crate::rumqttc_adapter as adapter;
+pub use events::{SessionEvent, SessionEventStream};
+pub use namespaced_client::{NamespacedManagedClient, NamespacedPubReceiver};
 pub use wrapper::*;
 
 /// Error describing why a [`Session`] ended prematurely
@@ -81,9 +93,9 @@ enum SessionErrorRepr {
     /// The [`Session`] was ended by a user-initiated force exit. The broker may still retain the MQTT session.
     #[error("session ended by force exit")]
     ForceExit,
-    /// The [`Session`] was ended by an IO error.
+    /// The [`Session`] was ended by an error getting authentication data.
     #[error("{0}")]
-    IoError(#[from] std::io::Error),
+    AuthProviderError(#[from] AuthProviderError),
     /// The [`Session`] was ended by an error in the SAT auth context.
     #[error("{0}")]
     SatAuthError(#[from] SatAuthContextInitError),
@@ -92,7 +104,28 @@ enum SessionErrorRepr {
 /// Error configuring a [`Session`].
 #[derive(Error, Debug)]
 #[error(transparent)]
-pub struct SessionConfigError(#[from] adapter::MqttAdapterError);
+pub struct SessionConfigError(#[from] SessionConfigErrorRepr);
+
+impl From<adapter::MqttAdapterError> for SessionConfigError {
+    fn from(e: adapter::MqttAdapterError) -> Self {
+        Self(SessionConfigErrorRepr::from(e))
+    }
+}
+
+/// Internal error for [`Session`] configuration.
+#[derive(Error, Debug)]
+enum SessionConfigErrorRepr {
+    /// Error using the MQTT connection settings
+    #[error(transparent)]
+    Adapter(#[from] adapter::MqttAdapterError),
+    /// Error opening the offline publish queue
+    #[error("cannot open offline publish queue: {0}")]
+    OfflineQueue(#[source] std::io::Error),
+    /// Error opening the packet capture file
+    #[cfg(feature = "capture")]
+    #[error("cannot open packet capture file: {0}")]
+    Capture(#[source] std::io::Error),
+}
 
 /// Error type for exiting a [`Session`] using the [`SessionExitHandle`].
 #[derive(Error, Debug)]
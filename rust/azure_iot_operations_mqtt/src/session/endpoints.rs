@@ -0,0 +1,201 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Internal tracking of the MQTT broker endpoints a [`Session`](super::Session) connects to,
+//! used to fail over between redundant brokers.
+
+/// Ordered list of MQTT broker endpoints (hostname, TCP port), rotated through on failed
+/// connection attempts.
+pub struct BrokerEndpoints {
+    /// Configured endpoints, in order of preference
+    endpoints: Vec<(String, u16)>,
+    /// Index of the configured endpoint currently in use
+    index: usize,
+    /// Endpoint the MQTT broker referred the client to, used for the next connection attempt
+    redirect: Option<(String, u16)>,
+    /// Indicates the MQTT broker asked the client to use another endpoint without referring it
+    /// to a specific one
+    use_another: bool,
+    /// Endpoint of the current (or most recent) connection attempt
+    current: (String, u16),
+}
+
+impl BrokerEndpoints {
+    /// Create a new [`BrokerEndpoints`] starting with the first of the provided endpoints.
+    ///
+    /// Returns `None` if no endpoints are provided.
+    pub fn new(endpoints: Vec<(String, u16)>) -> Option<Self> {
+        let current = endpoints.first()?.clone();
+        Some(Self {
+            endpoints,
+            index: 0,
+            redirect: None,
+            use_another: false,
+            current,
+        })
+    }
+
+    /// Return the endpoint of the current (or most recent) connection attempt
+    pub fn current(&self) -> &(String, u16) {
+        &self.current
+    }
+
+    /// Record that the MQTT broker asked the client to use another server (reason code 0x9C) or
+    /// that the server has moved (reason code 0x9D), with an optional Server Reference.
+    ///
+    /// The referenced server is used for the next connection attempt. If the server has moved, it
+    /// also permanently replaces the current endpoint. If there is no usable Server Reference, the
+    /// next configured endpoint is used instead.
+    pub fn redirect(&mut self, server_reference: Option<&str>, moved: bool) {
+        let default_port = self.current.1;
+        match server_reference.and_then(|r| parse_server_reference(r, default_port)) {
+            Some(endpoint) => {
+                log::info!(
+                    "MQTT broker referred client to {}:{}",
+                    endpoint.0,
+                    endpoint.1
+                );
+                if moved {
+                    self.endpoints[self.index] = endpoint.clone();
+                }
+                self.redirect = Some(endpoint);
+            }
+            None => {
+                log::info!("MQTT broker requested use of another server");
+                self.use_another = true;
+            }
+        }
+    }
+
+    /// Select the endpoint for the next connection attempt.
+    ///
+    /// If the previous connection attempt failed, or the MQTT broker asked the client to use
+    /// another server, the next configured endpoint is used, unless the MQTT broker referred the
+    /// client to a specific one.
+    ///
+    /// Returns the newly selected endpoint if it differs from the current one.
+    pub fn next(&mut self, attempt_failed: bool) -> Option<&(String, u16)> {
+        let next = if let Some(redirect) = self.redirect.take() {
+            redirect
+        } else {
+            if attempt_failed || self.use_another {
+                self.index = (self.index + 1) % self.endpoints.len();
+            }
+            self.endpoints[self.index].clone()
+        };
+        self.use_another = false;
+        if next == self.current {
+            None
+        } else {
+            self.current = next;
+            Some(&self.current)
+        }
+    }
+}
+
+/// Parse the first server of an MQTT Server Reference, which is a space-separated list of
+/// references of the form `<host>[:<port>]`.
+fn parse_server_reference(server_reference: &str, default_port: u16) -> Option<(String, u16)> {
+    let reference = server_reference.split_whitespace().next()?;
+    let (host, port) = if let Some(bracketed) = reference.strip_prefix('[') {
+        // IPv6 address
+        let (host, port) = bracketed.split_once(']')?;
+        (host, port.strip_prefix(':'))
+    } else {
+        match reference.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (reference, None),
+        }
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => default_port,
+    };
+    Some((host.to_string(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    fn endpoints() -> BrokerEndpoints {
+        BrokerEndpoints::new(vec![
+            ("broker1".to_string(), 1883),
+            ("broker2".to_string(), 1883),
+            ("broker3".to_string(), 8883),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn no_endpoints() {
+        assert!(BrokerEndpoints::new(vec![]).is_none());
+    }
+
+    #[test]
+    fn rotate_on_failed_attempt() {
+        let mut endpoints = endpoints();
+        assert_eq!(endpoints.current(), &("broker1".to_string(), 1883));
+        assert_eq!(endpoints.next(true), Some(&("broker2".to_string(), 1883)));
+        assert_eq!(endpoints.next(true), Some(&("broker3".to_string(), 8883)));
+        // Wraps around to the first endpoint
+        assert_eq!(endpoints.next(true), Some(&("broker1".to_string(), 1883)));
+        // A lost connection retries the same endpoint
+        assert_eq!(endpoints.next(false), None);
+        assert_eq!(endpoints.current(), &("broker1".to_string(), 1883));
+    }
+
+    #[test]
+    fn single_endpoint() {
+        let mut endpoints = BrokerEndpoints::new(vec![("broker1".to_string(), 1883)]).unwrap();
+        assert_eq!(endpoints.next(true), None);
+        assert_eq!(endpoints.current(), &("broker1".to_string(), 1883));
+    }
+
+    #[test]
+    fn use_another_server() {
+        let mut endpoints = endpoints();
+        endpoints.redirect(Some("other:1884"), false);
+        assert_eq!(endpoints.next(false), Some(&("other".to_string(), 1884)));
+        // The redirect is only used once
+        assert_eq!(endpoints.next(true), Some(&("broker2".to_string(), 1883)));
+    }
+
+    #[test]
+    fn server_moved() {
+        let mut endpoints = endpoints();
+        endpoints.redirect(Some("moved"), true);
+        assert_eq!(endpoints.next(false), Some(&("moved".to_string(), 1883)));
+        endpoints.next(true);
+        endpoints.next(true);
+        // The moved server replaced the original endpoint
+        assert_eq!(endpoints.next(true), Some(&("moved".to_string(), 1883)));
+    }
+
+    #[test]
+    fn use_another_server_without_reference() {
+        let mut endpoints = endpoints();
+        endpoints.redirect(None, false);
+        assert_eq!(endpoints.next(false), Some(&("broker2".to_string(), 1883)));
+        assert_eq!(endpoints.next(false), None);
+    }
+
+    #[test_case("broker", Some(("broker", 1883)); "host only")]
+    #[test_case("broker:1884", Some(("broker", 1884)); "host and port")]
+    #[test_case("broker:1884 other:1885", Some(("broker", 1884)); "multiple references")]
+    #[test_case("[::1]:1884", Some(("::1", 1884)); "ipv6")]
+    #[test_case("", None; "empty")]
+    #[test_case(":1884", None; "no host")]
+    #[test_case("broker:port", None; "malformed port")]
+    fn server_reference(reference: &str, expected: Option<(&str, u16)>) {
+        assert_eq!(
+            parse_server_reference(reference, 1883),
+            expected.map(|(host, port)| (host.to_string(), port))
+        );
+    }
+}
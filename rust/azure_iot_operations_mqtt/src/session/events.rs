@@ -0,0 +1,200 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Events describing the lifecycle of a [`Session`](super::Session).
+
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+use crate::control_packet::{ConnAckProperties, ConnectReturnCode, DisconnectReasonCode};
+
+/// Maximum number of events buffered for a [`SessionEventStream`] before the oldest are missed
+const EVENT_CAPACITY: usize = 100;
+
+/// An event in the lifecycle of a [`Session`](super::Session).
+#[derive(Clone, Debug)]
+pub enum SessionEvent {
+    /// A connection attempt is being made to the MQTT broker
+    ConnectAttempt {
+        /// Number of reconnect attempts made since the last successful connection.
+        /// 0 for the initial connection attempt.
+        reconnect_attempt: u32,
+    },
+    /// A CONNACK was received from the MQTT broker, and the connection was established
+    Connected {
+        /// Indicates if the MQTT broker had state for the MQTT session
+        session_present: bool,
+        /// Reason code of the CONNACK
+        reason_code: ConnectReturnCode,
+        /// Properties of the CONNACK
+        properties: Option<ConnAckProperties>,
+    },
+    /// The connection was refused by the MQTT broker
+    ConnectionRefused {
+        /// Reason code of the CONNACK
+        reason_code: ConnectReturnCode,
+    },
+    /// The MQTT session was lost while disconnected, and was recovered by restoring all active
+    /// subscriptions on the new MQTT session. Sent once the MQTT broker has responded to all
+    /// of the subscribes restoring them.
+    ///
+    /// Any messages sent to the client while the MQTT session was lost were not received, and
+    /// messages received before the loss can no longer be acknowledged.
+    SessionRecovered {
+        /// Number of subscriptions successfully restored
+        resubscribed: usize,
+    },
+    /// A DISCONNECT was received from the MQTT broker
+    ServerDisconnect {
+        /// Reason code of the DISCONNECT
+        reason_code: DisconnectReasonCode,
+        /// Human readable reason for the DISCONNECT, if provided by the MQTT broker
+        reason_string: Option<String>,
+    },
+    /// The connection to the MQTT broker was lost, or a connection attempt failed
+    ConnectionLost {
+        /// Description of the connection error
+        error: String,
+    },
+    /// A reconnect attempt was scheduled by the
+    /// [`ReconnectPolicy`](crate::session::reconnect_policy::ReconnectPolicy)
+    ReconnectScheduled {
+        /// Number of reconnect attempts made since the last successful connection
+        prev_attempts: u32,
+        /// Delay before the reconnect attempt
+        delay: Duration,
+    },
+    /// The client re-authenticated with the MQTT broker
+    Reauthenticated,
+    /// The client failed to re-authenticate with the MQTT broker
+    ReauthenticationFailed {
+        /// Description of the re-authentication error
+        error: String,
+    },
+    /// Rotated TLS files were reloaded. The updated TLS material is used from the next
+    /// connection attempt, and the current connection is not interrupted.
+    TlsReloaded,
+    /// Rotated TLS files could not be reloaded, and the previous TLS material remains in use
+    TlsReloadFailed {
+        /// Description of the reload error
+        error: String,
+    },
+    /// The [`Session`](super::Session) exited. This is always the final event.
+    Exited {
+        /// Description of the error that caused the exit, or `None` if the exit was requested
+        /// by the user
+        error: Option<String>,
+    },
+}
+
+/// Sender of [`SessionEvent`]s to all [`SessionEventStream`]s
+#[derive(Clone)]
+pub(crate) struct SessionEventSender(broadcast::Sender<SessionEvent>);
+
+impl SessionEventSender {
+    /// Create a new [`SessionEventSender`]
+    pub fn new() -> Self {
+        Self(broadcast::channel(EVENT_CAPACITY).0)
+    }
+
+    /// Send an event to all current [`SessionEventStream`]s
+    pub fn send(&self, event: SessionEvent) {
+        log::debug!("Session event: {event:?}");
+        // Ignore the error - it only indicates there are no streams
+        let _ = self.0.send(event);
+    }
+
+    /// Create a new [`SessionEventStream`] that will receive all subsequent events
+    pub fn subscribe(&self) -> SessionEventStream {
+        SessionEventStream {
+            rx: self.0.subscribe(),
+            exited: false,
+        }
+    }
+}
+
+/// Stream of [`SessionEvent`]s from a [`Session`](super::Session).
+///
+/// Only events that occur after the stream is created are received. If events are not received
+/// quickly enough, the oldest are missed.
+pub struct SessionEventStream {
+    /// Receiver for events
+    rx: broadcast::Receiver<SessionEvent>,
+    /// Indicates the final event has been received
+    exited: bool,
+}
+
+impl SessionEventStream {
+    /// Receive the next [`SessionEvent`].
+    ///
+    /// Returns `None` once the [`SessionEvent::Exited`] event has been received.
+    pub async fn recv(&mut self) -> Option<SessionEvent> {
+        if self.exited {
+            return None;
+        }
+        loop {
+            match self.rx.recv().await {
+                Ok(event) => {
+                    self.exited = matches!(event, SessionEvent::Exited { .. });
+                    return Some(event);
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    log::warn!("{missed} session event(s) missed due to slow receipt");
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stream_ends_after_exit() {
+        let sender = SessionEventSender::new();
+        let mut stream = sender.subscribe();
+        sender.send(SessionEvent::ConnectAttempt {
+            reconnect_attempt: 0,
+        });
+        sender.send(SessionEvent::Exited { error: None });
+        // Events sent after the exit are not received
+        sender.send(SessionEvent::Reauthenticated);
+
+        assert!(matches!(
+            stream.recv().await,
+            Some(SessionEvent::ConnectAttempt {
+                reconnect_attempt: 0
+            })
+        ));
+        assert!(matches!(
+            stream.recv().await,
+            Some(SessionEvent::Exited { error: None })
+        ));
+        assert!(stream.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn missed_events_skipped() {
+        let sender = SessionEventSender::new();
+        let mut stream = sender.subscribe();
+        for _ in 0..EVENT_CAPACITY {
+            sender.send(SessionEvent::Reauthenticated);
+        }
+        sender.send(SessionEvent::Exited { error: None });
+
+        // The oldest event was missed
+        for _ in 0..EVENT_CAPACITY - 1 {
+            assert!(matches!(
+                stream.recv().await,
+                Some(SessionEvent::Reauthenticated)
+            ));
+        }
+        assert!(matches!(
+            stream.recv().await,
+            Some(SessionEvent::Exited { .. })
+        ));
+    }
+}
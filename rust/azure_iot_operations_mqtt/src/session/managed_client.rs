@@ -3,19 +3,31 @@
 
 //! Internal implementation of [`SessionManagedClient`] and [`SessionPubReceiver`].
 
+use std::future::Future;
+use std::num::NonZeroUsize;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use async_trait::async_trait;
 use bytes::Bytes;
+use tokio::sync::oneshot;
 
 use crate::control_packet::{
-    Publish, PublishProperties, QoS, SubscribeProperties, UnsubscribeProperties,
+    Publish, PublishProperties, QoS, SubscribeOptions, SubscribeProperties, UnsubscribeProperties,
 };
-use crate::error::{PublishError, SubscribeError, UnsubscribeError};
-use crate::interface::{CompletionToken, ManagedClient, MqttPubSub, PubReceiver};
+use crate::error::{
+    CompletionError, PublishError, PublishErrorKind, SubscribeError, UnsubscribeError,
+};
+use crate::interface::{
+    CompletionToken, ManagedClient, MqttPubSub, OverflowPolicy, PubReceiver, PublishFilter,
+};
+use crate::metrics::Metrics;
+use crate::session::offline_queue::{EnqueueError, OfflinePublishQueue};
 use crate::session::receiver::{AckToken, PublishReceiverManager, PublishRx};
-use crate::topic::{TopicFilter, TopicParseError};
+use crate::session::state::SessionState;
+use crate::session::subscription_registry::SubscriptionRegistry;
+use crate::topic::{TopicFilter, TopicName, TopicParseError};
 
 /// An MQTT client that has it's connection state externally managed by a [`Session`](super::Session).
 /// Can be used to send messages and create receivers for incoming messages.
@@ -30,6 +42,150 @@ where
     pub(crate) pub_sub: PS,
     /// Manager for receivers
     pub(crate) receiver_manager: Arc<Mutex<PublishReceiverManager>>,
+    /// State of the `Session` that manages this client
+    pub(crate) state: Arc<SessionState>,
+    /// Disk-backed queue for QoS 1 publishes made while disconnected
+    pub(crate) offline_queue: Option<Arc<OfflinePublishQueue>>,
+    /// Registry of active subscriptions, used to restore them if the MQTT session is lost
+    pub(crate) subscriptions: Option<Arc<tokio::sync::Mutex<SubscriptionRegistry>>>,
+    /// Metrics recorder of the `Session` that manages this client
+    pub(crate) metrics: Metrics,
+}
+
+impl<PS> SessionManagedClient<PS>
+where
+    PS: MqttPubSub + Clone + Send + Sync,
+{
+    /// Return the number of publishes in the offline publish queue waiting to be sent,
+    /// or `None` if the offline publish queue is not enabled.
+    #[must_use]
+    pub fn offline_queue_depth(&self) -> Option<usize> {
+        self.offline_queue.as_ref().map(|q| q.depth())
+    }
+
+    /// Helper for queueing a publish in the offline publish queue, if necessary.
+    ///
+    /// Returns `None` if the publish should be sent directly.
+    async fn enqueue_offline(
+        &self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: &Bytes,
+        properties: Option<&PublishProperties>,
+    ) -> Result<Option<CompletionToken>, PublishError> {
+        let Some(offline_queue) = &self.offline_queue else {
+            return Ok(None);
+        };
+        if qos != QoS::AtLeastOnce {
+            return Ok(None);
+        }
+        if !TopicName::is_valid_topic_name(topic) {
+            return Err(PublishError::new(PublishErrorKind::InvalidTopicName));
+        }
+        match offline_queue
+            .enqueue(
+                self.state.is_connected(),
+                topic,
+                retain,
+                payload,
+                properties,
+            )
+            .await
+        {
+            Ok(Some(completion_rx)) => {
+                log::debug!("Publish to {topic} added to offline publish queue");
+                Ok(Some(CompletionToken(Box::new(async move {
+                    completion_rx.await.unwrap_or(Err(CompletionError::Recv))
+                }))))
+            }
+            Ok(None) => Ok(None),
+            Err(EnqueueError::Full) => Err(PublishError::new(PublishErrorKind::OfflineQueueFull)),
+            Err(EnqueueError::Io(e)) => {
+                log::error!("Cannot add publish to offline publish queue: {e:?}");
+                Err(PublishError::new(PublishErrorKind::OfflineQueueWrite))
+            }
+        }
+    }
+
+    /// Helper for sending a subscribe, and recording the subscription in the subscription
+    /// registry (if any) once the MQTT broker has acknowledged it.
+    async fn subscribe_recorded(
+        &self,
+        topic: String,
+        options: SubscribeOptions,
+        send: impl Future<Output = Result<CompletionToken, SubscribeError>> + Send,
+    ) -> Result<CompletionToken, SubscribeError> {
+        let Some(subscriptions) = &self.subscriptions else {
+            let ct = send.await?;
+            self.metrics.subscribe_sent();
+            return Ok(ct);
+        };
+        // NOTE: The registry is locked while sending, so that the subscribe is ordered relative
+        // to the subscribes restoring a lost MQTT session
+        let mut registry = subscriptions.lock().await;
+        let ct = send.await?;
+        self.metrics.subscribe_sent();
+        let seq = registry.subscribe_sent(&topic);
+        drop(registry);
+
+        // NOTE: The subscription is recorded in a separate task, so that it is recorded even if
+        // the returned CompletionToken is not awaited
+        let (completion_tx, completion_rx) = oneshot::channel();
+        tokio::spawn({
+            let subscriptions = subscriptions.clone();
+            async move {
+                let result = ct.await;
+                subscriptions.lock().await.subscribe_completed(
+                    seq,
+                    &topic,
+                    result.is_ok().then_some(options),
+                );
+                // Ignore the error - the receiver may no longer care about completion
+                let _ = completion_tx.send(result);
+            }
+        });
+        Ok(CompletionToken(Box::new(async move {
+            completion_rx.await.unwrap_or(Err(CompletionError::Recv))
+        })))
+    }
+
+    /// Helper for sending an unsubscribe, and removing the subscription from the subscription
+    /// registry (if any).
+    async fn unsubscribe_recorded(
+        &self,
+        topic: &str,
+        send: impl Future<Output = Result<CompletionToken, UnsubscribeError>> + Send,
+    ) -> Result<CompletionToken, UnsubscribeError> {
+        let Some(subscriptions) = &self.subscriptions else {
+            return send.await;
+        };
+        // NOTE: The registry is locked while sending, so that the unsubscribe is ordered relative
+        // to the subscribes restoring a lost MQTT session
+        let mut registry = subscriptions.lock().await;
+        let ct = send.await?;
+        registry.unsubscribe_sent(topic);
+        Ok(ct)
+    }
+
+    /// Helper for recording the metrics of a publish that was sent after waiting for the
+    /// outgoing queue since `start`, and wrapping its [`CompletionToken`] to record its
+    /// acknowledgement.
+    fn record_publish(&self, qos: QoS, start: Instant, ct: CompletionToken) -> CompletionToken {
+        self.metrics.outgoing_wait(start.elapsed());
+        self.metrics.publish_sent(qos);
+        if qos == QoS::AtMostOnce {
+            return ct;
+        }
+        let metrics = self.metrics.clone();
+        CompletionToken(Box::new(async move {
+            let result = ct.await;
+            if result.is_ok() {
+                metrics.publish_acked(qos, start.elapsed());
+            }
+            result
+        }))
+    }
 }
 
 impl<PS> ManagedClient for SessionManagedClient<PS>
@@ -55,6 +211,47 @@ where
         Ok(SessionPubReceiver { pub_rx })
     }
 
+    fn create_bounded_filtered_pub_receiver(
+        &self,
+        topic_filter: &str,
+        capacity: NonZeroUsize,
+        overflow_policy: OverflowPolicy,
+    ) -> Result<SessionPubReceiver, TopicParseError> {
+        let topic_filter = TopicFilter::from_str(topic_filter)?;
+        let pub_rx = self
+            .receiver_manager
+            .lock()
+            .unwrap()
+            .create_bounded_filtered_receiver(&topic_filter, capacity, overflow_policy);
+        Ok(SessionPubReceiver { pub_rx })
+    }
+
+    fn create_content_filtered_pub_receiver(
+        &self,
+        topic_filter: &str,
+        publish_filter: PublishFilter,
+    ) -> Result<SessionPubReceiver, TopicParseError> {
+        let topic_filter = TopicFilter::from_str(topic_filter)?;
+        let pub_rx = self
+            .receiver_manager
+            .lock()
+            .unwrap()
+            .create_content_filtered_receiver(&topic_filter, publish_filter);
+        Ok(SessionPubReceiver { pub_rx })
+    }
+
+    fn create_identified_pub_receiver(
+        &self,
+        subscription_identifier: usize,
+    ) -> Result<SessionPubReceiver, SubscribeError> {
+        let pub_rx = self
+            .receiver_manager
+            .lock()
+            .unwrap()
+            .create_identified_receiver(subscription_identifier);
+        Ok(SessionPubReceiver { pub_rx })
+    }
+
     fn create_unfiltered_pub_receiver(&self) -> SessionPubReceiver {
         let pub_rx = self
             .receiver_manager
@@ -77,7 +274,18 @@ where
         retain: bool,
         payload: impl Into<Bytes> + Send,
     ) -> Result<CompletionToken, PublishError> {
-        self.pub_sub.publish(topic, qos, retain, payload).await
+        let topic = topic.into();
+        let payload = payload.into();
+        // NOTE: Queued publishes are recorded as sent once they are sent from the queue
+        if let Some(ct) = self
+            .enqueue_offline(&topic, qos, retain, &payload, None)
+            .await?
+        {
+            return Ok(ct);
+        }
+        let start = Instant::now();
+        let ct = self.pub_sub.publish(topic, qos, retain, payload).await?;
+        Ok(self.record_publish(qos, start, ct))
     }
 
     async fn publish_with_properties(
@@ -88,9 +296,20 @@ where
         payload: impl Into<Bytes> + Send,
         properties: PublishProperties,
     ) -> Result<CompletionToken, PublishError> {
-        self.pub_sub
+        let topic = topic.into();
+        let payload = payload.into();
+        if let Some(ct) = self
+            .enqueue_offline(&topic, qos, retain, &payload, Some(&properties))
+            .await?
+        {
+            return Ok(ct);
+        }
+        let start = Instant::now();
+        let ct = self
+            .pub_sub
             .publish_with_properties(topic, qos, retain, payload, properties)
-            .await
+            .await?;
+        Ok(self.record_publish(qos, start, ct))
     }
 
     async fn subscribe(
@@ -98,7 +317,10 @@ where
         topic: impl Into<String> + Send,
         qos: QoS,
     ) -> Result<CompletionToken, SubscribeError> {
-        self.pub_sub.subscribe(topic, qos).await
+        let topic = topic.into();
+        let send = self.pub_sub.subscribe(topic.clone(), qos);
+        self.subscribe_recorded(topic, SubscribeOptions::from_properties(qos, None), send)
+            .await
     }
 
     async fn subscribe_with_properties(
@@ -107,16 +329,33 @@ where
         qos: QoS,
         properties: SubscribeProperties,
     ) -> Result<CompletionToken, SubscribeError> {
-        self.pub_sub
-            .subscribe_with_properties(topic, qos, properties)
-            .await
+        let topic = topic.into();
+        let options = SubscribeOptions::from_properties(qos, Some(properties.clone()));
+        let send = self
+            .pub_sub
+            .subscribe_with_properties(topic.clone(), qos, properties);
+        self.subscribe_recorded(topic, options, send).await
+    }
+
+    async fn subscribe_with_options(
+        &self,
+        topic: impl Into<String> + Send,
+        options: SubscribeOptions,
+    ) -> Result<CompletionToken, SubscribeError> {
+        let topic = topic.into();
+        let send = self
+            .pub_sub
+            .subscribe_with_options(topic.clone(), options.clone());
+        self.subscribe_recorded(topic, options, send).await
     }
 
     async fn unsubscribe(
         &self,
         topic: impl Into<String> + Send,
     ) -> Result<CompletionToken, UnsubscribeError> {
-        self.pub_sub.unsubscribe(topic).await
+        let topic = topic.into();
+        let send = self.pub_sub.unsubscribe(topic.clone());
+        self.unsubscribe_recorded(&topic, send).await
     }
 
     async fn unsubscribe_with_properties(
@@ -124,9 +363,11 @@ where
         topic: impl Into<String> + Send,
         properties: UnsubscribeProperties,
     ) -> Result<CompletionToken, UnsubscribeError> {
-        self.pub_sub
-            .unsubscribe_with_properties(topic, properties)
-            .await
+        let topic = topic.into();
+        let send = self
+            .pub_sub
+            .unsubscribe_with_properties(topic.clone(), properties);
+        self.unsubscribe_recorded(&topic, send).await
     }
 }
 
@@ -136,6 +377,14 @@ pub struct SessionPubReceiver {
     pub_rx: PublishRx,
 }
 
+impl SessionPubReceiver {
+    /// Return the number of incoming messages dropped because this receiver was full
+    #[must_use]
+    pub fn dropped_count(&self) -> u64 {
+        self.pub_rx.dropped_count()
+    }
+}
+
 #[async_trait]
 impl PubReceiver for SessionPubReceiver {
     async fn recv(&mut self) -> Option<Publish> {
@@ -0,0 +1,568 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! [`ManagedClient`] adapter isolating its users within a topic namespace.
+
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::control_packet::{
+    Publish, PublishProperties, QoS, SubscribeOptions, SubscribeProperties, UnsubscribeProperties,
+};
+use crate::error::{
+    PublishError, PublishErrorKind, SubscribeError, SubscribeErrorKind, UnsubscribeError,
+    UnsubscribeErrorKind,
+};
+use crate::interface::{
+    AckToken, CompletionToken, ManagedClient, MqttPubSub, OverflowPolicy, PubReceiver,
+    PublishFilter,
+};
+use crate::topic::{TopicFilter, TopicName, TopicParseError};
+
+/// Prefix of a shared subscription topic filter
+const SHARED_SUBSCRIPTION_PREFIX: &str = "$share/";
+
+/// A [`ManagedClient`] that confines all of its MQTT traffic to a topic namespace.
+///
+/// Every topic name and topic filter used with this client (including receiver filters and the
+/// response topic of outgoing publishes) is prefixed with `<namespace>/`, and the prefix is
+/// stripped from the topic and response topic of incoming publishes. Code written against
+/// [`ManagedClient`] can therefore run unchanged in isolation from other namespaces sharing the
+/// same [`Session`](crate::session::Session).
+///
+/// The namespace is inserted after the share name of shared subscription topic filters (e.g.
+/// `$share/group/<namespace>/topic`). Any other topic beginning with `$` is outside the namespace
+/// and rejected.
+#[derive(Clone)]
+pub struct NamespacedManagedClient<C>
+where
+    C: ManagedClient + Send + Sync,
+{
+    /// The underlying client
+    inner: C,
+    /// Namespace, including the trailing level separator
+    prefix: String,
+}
+
+impl<C> NamespacedManagedClient<C>
+where
+    C: ManagedClient + Send + Sync,
+{
+    /// Create a new [`NamespacedManagedClient`] confining the provided [`ManagedClient`] to
+    /// the provided namespace.
+    ///
+    /// # Errors
+    /// Returns a [`TopicParseError`] if the namespace is not a valid topic name, or begins
+    /// with `$`.
+    pub fn new(inner: C, namespace: &str) -> Result<Self, TopicParseError> {
+        let namespace = TopicName::from_str(namespace)?;
+        if namespace.as_str().starts_with('$') {
+            return Err(TopicParseError::OutsideNamespace(
+                namespace.as_str().to_string(),
+            ));
+        }
+        Ok(Self {
+            inner,
+            prefix: format!("{namespace}/"),
+        })
+    }
+
+    /// Return the namespace of this client
+    #[must_use]
+    pub fn namespace(&self) -> &str {
+        &self.prefix[..self.prefix.len() - 1]
+    }
+
+    /// Helper for prefixing a topic name with the namespace
+    fn topic_name(&self, topic_name: &str) -> Result<String, TopicParseError> {
+        TopicName::from_str(topic_name)?;
+        if topic_name.starts_with('$') {
+            return Err(TopicParseError::OutsideNamespace(topic_name.to_string()));
+        }
+        Ok(format!("{}{topic_name}", self.prefix))
+    }
+
+    /// Helper for prefixing a topic filter with the namespace
+    fn topic_filter(&self, topic_filter: &str) -> Result<String, TopicParseError> {
+        TopicFilter::from_str(topic_filter)?;
+        if let Some(shared) = topic_filter.strip_prefix(SHARED_SUBSCRIPTION_PREFIX) {
+            // NOTE: A valid shared subscription topic filter always has a share name followed
+            // by a level separator
+            if let Some((share_name, filter)) = shared.split_once('/') {
+                if !filter.starts_with('$') {
+                    return Ok(format!(
+                        "{SHARED_SUBSCRIPTION_PREFIX}{share_name}/{}{filter}",
+                        self.prefix
+                    ));
+                }
+            }
+        } else if !topic_filter.starts_with('$') {
+            return Ok(format!("{}{topic_filter}", self.prefix));
+        }
+        Err(TopicParseError::OutsideNamespace(topic_filter.to_string()))
+    }
+
+    /// Helper for prefixing the response topic of outgoing publish properties with the namespace
+    fn publish_properties(
+        &self,
+        mut properties: PublishProperties,
+    ) -> Result<PublishProperties, PublishError> {
+        if let Some(response_topic) = properties.response_topic {
+            properties.response_topic = Some(
+                self.topic_name(&response_topic)
+                    .map_err(|_| PublishError::new(PublishErrorKind::InvalidTopicName))?,
+            );
+        }
+        Ok(properties)
+    }
+}
+
+impl<C> ManagedClient for NamespacedManagedClient<C>
+where
+    C: ManagedClient + Send + Sync,
+    C::PubReceiver: Send,
+{
+    type PubReceiver = NamespacedPubReceiver<C::PubReceiver>;
+
+    fn client_id(&self) -> &str {
+        self.inner.client_id()
+    }
+
+    fn create_filtered_pub_receiver(
+        &self,
+        topic_filter: &str,
+    ) -> Result<Self::PubReceiver, TopicParseError> {
+        let topic_filter = self.topic_filter(topic_filter)?;
+        Ok(NamespacedPubReceiver {
+            inner: self.inner.create_filtered_pub_receiver(&topic_filter)?,
+            prefix: self.prefix.clone(),
+        })
+    }
+
+    fn create_bounded_filtered_pub_receiver(
+        &self,
+        topic_filter: &str,
+        capacity: NonZeroUsize,
+        overflow_policy: OverflowPolicy,
+    ) -> Result<Self::PubReceiver, TopicParseError> {
+        let topic_filter = self.topic_filter(topic_filter)?;
+        Ok(NamespacedPubReceiver {
+            inner: self.inner.create_bounded_filtered_pub_receiver(
+                &topic_filter,
+                capacity,
+                overflow_policy,
+            )?,
+            prefix: self.prefix.clone(),
+        })
+    }
+
+    /// Creates a new [`PubReceiver`] that receives messages on a specific topic within the
+    /// namespace that also match the provided [`PublishFilter`].
+    ///
+    /// Note that the [`PublishFilter`] is evaluated against messages as received from the broker,
+    /// i.e. before the namespace is removed from their topic.
+    fn create_content_filtered_pub_receiver(
+        &self,
+        topic_filter: &str,
+        publish_filter: PublishFilter,
+    ) -> Result<Self::PubReceiver, TopicParseError> {
+        let topic_filter = self.topic_filter(topic_filter)?;
+        Ok(NamespacedPubReceiver {
+            inner: self
+                .inner
+                .create_content_filtered_pub_receiver(&topic_filter, publish_filter)?,
+            prefix: self.prefix.clone(),
+        })
+    }
+
+    fn create_identified_pub_receiver(
+        &self,
+        subscription_identifier: usize,
+    ) -> Result<Self::PubReceiver, SubscribeError> {
+        Ok(NamespacedPubReceiver {
+            inner: self
+                .inner
+                .create_identified_pub_receiver(subscription_identifier)?,
+            prefix: self.prefix.clone(),
+        })
+    }
+
+    /// Creates a new [`PubReceiver`] that receives all messages within the namespace not sent to
+    /// other filtered receivers. Messages outside the namespace are acknowledged and discarded.
+    fn create_unfiltered_pub_receiver(&self) -> Self::PubReceiver {
+        NamespacedPubReceiver {
+            inner: self.inner.create_unfiltered_pub_receiver(),
+            prefix: self.prefix.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<C> MqttPubSub for NamespacedManagedClient<C>
+where
+    C: ManagedClient + Send + Sync,
+{
+    async fn publish(
+        &self,
+        topic: impl Into<String> + Send,
+        qos: QoS,
+        retain: bool,
+        payload: impl Into<Bytes> + Send,
+    ) -> Result<CompletionToken, PublishError> {
+        let topic = self
+            .topic_name(&topic.into())
+            .map_err(|_| PublishError::new(PublishErrorKind::InvalidTopicName))?;
+        self.inner.publish(topic, qos, retain, payload).await
+    }
+
+    async fn publish_with_properties(
+        &self,
+        topic: impl Into<String> + Send,
+        qos: QoS,
+        retain: bool,
+        payload: impl Into<Bytes> + Send,
+        properties: PublishProperties,
+    ) -> Result<CompletionToken, PublishError> {
+        let topic = self
+            .topic_name(&topic.into())
+            .map_err(|_| PublishError::new(PublishErrorKind::InvalidTopicName))?;
+        let properties = self.publish_properties(properties)?;
+        self.inner
+            .publish_with_properties(topic, qos, retain, payload, properties)
+            .await
+    }
+
+    async fn subscribe(
+        &self,
+        topic: impl Into<String> + Send,
+        qos: QoS,
+    ) -> Result<CompletionToken, SubscribeError> {
+        let topic = self
+            .topic_filter(&topic.into())
+            .map_err(|_| SubscribeError::new(SubscribeErrorKind::InvalidTopicFilter))?;
+        self.inner.subscribe(topic, qos).await
+    }
+
+    async fn subscribe_with_properties(
+        &self,
+        topic: impl Into<String> + Send,
+        qos: QoS,
+        properties: SubscribeProperties,
+    ) -> Result<CompletionToken, SubscribeError> {
+        let topic = self
+            .topic_filter(&topic.into())
+            .map_err(|_| SubscribeError::new(SubscribeErrorKind::InvalidTopicFilter))?;
+        self.inner
+            .subscribe_with_properties(topic, qos, properties)
+            .await
+    }
+
+    async fn subscribe_with_options(
+        &self,
+        topic: impl Into<String> + Send,
+        options: SubscribeOptions,
+    ) -> Result<CompletionToken, SubscribeError> {
+        let topic = self
+            .topic_filter(&topic.into())
+            .map_err(|_| SubscribeError::new(SubscribeErrorKind::InvalidTopicFilter))?;
+        self.inner.subscribe_with_options(topic, options).await
+    }
+
+    async fn unsubscribe(
+        &self,
+        topic: impl Into<String> + Send,
+    ) -> Result<CompletionToken, UnsubscribeError> {
+        let topic = self
+            .topic_filter(&topic.into())
+            .map_err(|_| UnsubscribeError::new(UnsubscribeErrorKind::InvalidTopicFilter))?;
+        self.inner.unsubscribe(topic).await
+    }
+
+    async fn unsubscribe_with_properties(
+        &self,
+        topic: impl Into<String> + Send,
+        properties: UnsubscribeProperties,
+    ) -> Result<CompletionToken, UnsubscribeError> {
+        let topic = self
+            .topic_filter(&topic.into())
+            .map_err(|_| UnsubscribeError::new(UnsubscribeErrorKind::InvalidTopicFilter))?;
+        self.inner
+            .unsubscribe_with_properties(topic, properties)
+            .await
+    }
+}
+
+/// Receive and acknowledge incoming MQTT messages within a namespace.
+///
+/// The namespace is stripped from the topic and response topic of received messages.
+pub struct NamespacedPubReceiver<R>
+where
+    R: PubReceiver + Send,
+{
+    /// The underlying receiver
+    inner: R,
+    /// Namespace, including the trailing level separator
+    prefix: String,
+}
+
+impl<R> NamespacedPubReceiver<R>
+where
+    R: PubReceiver + Send,
+{
+    /// Strip the namespace from an incoming publish.
+    ///
+    /// Returns `None` if the publish is outside of the namespace.
+    fn strip(&self, mut publish: Publish) -> Option<Publish> {
+        let topic = publish.topic.strip_prefix(self.prefix.as_bytes())?;
+        publish.topic = publish.topic.slice_ref(topic);
+        // NOTE: A response topic outside the namespace is left as is, and will be prefixed with
+        // the namespace if used to publish a response.
+        if let Some(properties) = &mut publish.properties {
+            if let Some(response_topic) = &properties.response_topic {
+                if let Some(stripped) = response_topic.strip_prefix(&self.prefix) {
+                    properties.response_topic = Some(stripped.to_string());
+                }
+            }
+        }
+        Some(publish)
+    }
+}
+
+#[async_trait]
+impl<R> PubReceiver for NamespacedPubReceiver<R>
+where
+    R: PubReceiver + Send,
+{
+    async fn recv(&mut self) -> Option<Publish> {
+        self.recv_manual_ack().await.map(|(publish, _)| publish)
+    }
+
+    async fn recv_manual_ack(&mut self) -> Option<(Publish, Option<AckToken>)> {
+        loop {
+            let (publish, ack_token) = self.inner.recv_manual_ack().await?;
+            let topic = publish.topic.clone();
+            match self.strip(publish) {
+                Some(publish) => return Some((publish, ack_token)),
+                None => {
+                    // NOTE: Dropping the ack token acknowledges the publish
+                    log::debug!(
+                        "Discarding publish outside of namespace: {}",
+                        String::from_utf8_lossy(&topic)
+                    );
+                }
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        self.inner.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+    use crate::interface::{Event, Incoming};
+    use crate::interface_mocks::{MockClient, MockClientCall, MockEventLoop};
+    use crate::session::reconnect_policy::ExponentialBackoffWithJitter;
+    use crate::session::session::Session;
+
+    fn session() -> (
+        Session<MockClient, MockEventLoop>,
+        crate::interface_mocks::EventInjector,
+    ) {
+        let (event_loop, injector) = MockEventLoop::new();
+        let session = Session::new_from_injection(
+            MockClient::new(),
+            event_loop,
+            Box::new(ExponentialBackoffWithJitter::default()),
+            "client_id".to_string(),
+            None,
+        );
+        (session, injector)
+    }
+
+    fn incoming(topic: &str, response_topic: Option<&str>) -> Event {
+        Event::Incoming(Incoming::Publish(Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: false,
+            topic: Bytes::copy_from_slice(topic.as_bytes()),
+            pkid: 0,
+            payload: Bytes::new(),
+            properties: response_topic.map(|response_topic| PublishProperties {
+                response_topic: Some(response_topic.to_string()),
+                ..Default::default()
+            }),
+        }))
+    }
+
+    #[test_case("tenant"; "single level")]
+    #[test_case("org/tenant"; "multiple levels")]
+    fn valid_namespace(namespace: &str) {
+        let (session, _) = session();
+        let client =
+            NamespacedManagedClient::new(session.create_managed_client(), namespace).unwrap();
+        assert_eq!(client.namespace(), namespace);
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("tenant/#"; "wildcard")]
+    #[test_case("$SYS"; "system topic")]
+    fn invalid_namespace(namespace: &str) {
+        let (session, _) = session();
+        assert!(NamespacedManagedClient::new(session.create_managed_client(), namespace).is_err());
+    }
+
+    #[test_case("sensor/temp", "tenant/sensor/temp"; "topic filter")]
+    #[test_case("sensor/+/temp", "tenant/sensor/+/temp"; "single level wildcard")]
+    #[test_case("#", "tenant/#"; "multi level wildcard")]
+    #[test_case("$share/group/sensor/#", "$share/group/tenant/sensor/#"; "shared subscription")]
+    fn valid_topic_filter(topic_filter: &str, expected: &str) {
+        let (session, _) = session();
+        let client =
+            NamespacedManagedClient::new(session.create_managed_client(), "tenant").unwrap();
+        assert_eq!(client.topic_filter(topic_filter).unwrap(), expected);
+    }
+
+    #[test_case("$SYS/#"; "system topic")]
+    #[test_case("$share/group/$SYS/#"; "shared system topic")]
+    #[test_case("sensor/#/temp"; "invalid filter")]
+    fn invalid_topic_filter(topic_filter: &str) {
+        let (session, _) = session();
+        let client =
+            NamespacedManagedClient::new(session.create_managed_client(), "tenant").unwrap();
+        assert!(client.topic_filter(topic_filter).is_err());
+        assert!(client.create_filtered_pub_receiver(topic_filter).is_err());
+    }
+
+    #[tokio::test]
+    async fn outgoing_topics_prefixed() {
+        let (session, _) = session();
+        let managed_client = session.create_managed_client();
+        let controller = managed_client.pub_sub.mock_controller();
+        let client = NamespacedManagedClient::new(managed_client, "tenant").unwrap();
+
+        client
+            .publish_with_properties(
+                "request/topic",
+                QoS::AtLeastOnce,
+                false,
+                "payload",
+                PublishProperties {
+                    response_topic: Some("response/topic".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        client
+            .subscribe("response/+", QoS::AtLeastOnce)
+            .await
+            .unwrap();
+        client.unsubscribe("response/+").await.unwrap();
+
+        let calls = controller.call_sequence();
+        let MockClientCall::Publish(publish) = &calls[0] else {
+            panic!("Expected publish");
+        };
+        assert_eq!(publish.topic, "tenant/request/topic");
+        assert_eq!(
+            publish
+                .properties
+                .as_ref()
+                .unwrap()
+                .response_topic
+                .as_deref(),
+            Some("tenant/response/topic")
+        );
+        let MockClientCall::Subscribe(subscribe) = &calls[1] else {
+            panic!("Expected subscribe");
+        };
+        assert_eq!(subscribe.topic, "tenant/response/+");
+        let MockClientCall::Unsubscribe(unsubscribe) = &calls[2] else {
+            panic!("Expected unsubscribe");
+        };
+        assert_eq!(unsubscribe.topic, "tenant/response/+");
+    }
+
+    #[tokio::test]
+    async fn escaping_topics_rejected() {
+        let (session, _) = session();
+        let managed_client = session.create_managed_client();
+        let controller = managed_client.pub_sub.mock_controller();
+        let client = NamespacedManagedClient::new(managed_client, "tenant").unwrap();
+
+        let Err(e) = client
+            .publish("$SYS/topic", QoS::AtMostOnce, false, "payload")
+            .await
+        else {
+            panic!("Expected publish to be rejected");
+        };
+        assert_eq!(e.kind(), &PublishErrorKind::InvalidTopicName);
+        let Err(e) = client
+            .publish_with_properties(
+                "topic",
+                QoS::AtMostOnce,
+                false,
+                "payload",
+                PublishProperties {
+                    response_topic: Some("$SYS/response".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+        else {
+            panic!("Expected publish to be rejected");
+        };
+        assert_eq!(e.kind(), &PublishErrorKind::InvalidTopicName);
+        let Err(e) = client.subscribe("$SYS/#", QoS::AtMostOnce).await else {
+            panic!("Expected subscribe to be rejected");
+        };
+        assert_eq!(e.kind(), &SubscribeErrorKind::InvalidTopicFilter);
+        let Err(e) = client.unsubscribe("$SYS/#").await else {
+            panic!("Expected unsubscribe to be rejected");
+        };
+        assert_eq!(e.kind(), &UnsubscribeErrorKind::InvalidTopicFilter);
+        assert_eq!(controller.call_sequence().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn incoming_topics_stripped() {
+        let (session, injector) = session();
+        let managed_client = session.create_managed_client();
+        let client = NamespacedManagedClient::new(managed_client.clone(), "tenant").unwrap();
+        let mut filtered_receiver = client.create_filtered_pub_receiver("request/+").unwrap();
+        let mut unfiltered_receiver = client.create_unfiltered_pub_receiver();
+
+        let test = async {
+            injector
+                .inject(incoming("tenant/request/1", Some("tenant/response/1")))
+                .unwrap();
+            let publish = filtered_receiver.recv().await.unwrap();
+            assert_eq!(publish.topic, "request/1");
+            assert_eq!(
+                publish.properties.unwrap().response_topic.as_deref(),
+                Some("response/1")
+            );
+
+            // Messages outside the namespace are not received by the unfiltered receiver
+            injector.inject(incoming("other/topic", None)).unwrap();
+            injector
+                .inject(incoming("tenant/other/topic", None))
+                .unwrap();
+            let publish = unfiltered_receiver.recv().await.unwrap();
+            assert_eq!(publish.topic, "other/topic");
+        };
+
+        tokio::select! {
+            () = test => {}
+            _ = session.run() => panic!("Session ended unexpectedly"),
+        }
+    }
+}
@@ -0,0 +1,511 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Internal implementation of a disk-backed queue for QoS 1 publishes made while the
+//! [`Session`](super::Session) is disconnected.
+//!
+//! Each queued publish is stored as its own file in the queue directory, named by a sequence
+//! number so that publishes are replayed in the order they were queued, even across restarts.
+//! A file is only removed once delivery of its publish has completed.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use rumqttc::v5::mqttbytes::v5::Packet;
+use tokio::sync::{Notify, oneshot};
+
+use crate::control_packet::{Publish, PublishProperties, QoS};
+use crate::error::CompletionError;
+
+/// File extension of a queued publish
+const ENTRY_EXTENSION: &str = "pub";
+/// File extension of a queued publish that has not been completely written
+const TMP_EXTENSION: &str = "tmp";
+
+/// Error queueing a publish in an [`OfflinePublishQueue`]
+#[derive(Debug)]
+pub enum EnqueueError {
+    /// The queue is at capacity
+    Full,
+    /// The publish could not be written to disk
+    Io(io::Error),
+}
+
+/// A queued publish ready to be sent
+#[derive(Debug)]
+pub struct QueuedPublish {
+    /// Sequence number of the queued publish
+    pub seq: u64,
+    /// Topic of the publish
+    pub topic: String,
+    /// Retain flag of the publish
+    pub retain: bool,
+    /// Payload of the publish
+    pub payload: Bytes,
+    /// Properties of the publish, with the message expiry interval reduced by the time spent
+    /// in the queue
+    pub properties: PublishProperties,
+}
+
+/// Disk-backed queue of QoS 1 publishes awaiting a connection.
+pub struct OfflinePublishQueue {
+    /// Directory the queued publishes are stored in
+    dir: PathBuf,
+    /// Maximum number of publishes stored at once
+    max_entries: usize,
+    /// Queue state locked for concurrency protection
+    inner: Mutex<InnerQueue>,
+    /// Lock held while queueing a publish, so that publishes are written to disk and queued in
+    /// the order they were made
+    enqueue_lock: tokio::sync::Mutex<()>,
+    /// Notifier indicating a publish was added to the queue
+    entry_added: Notify,
+}
+
+/// The inner state of the queue
+struct InnerQueue {
+    /// Sequence numbers of queued publishes not yet sent, in order
+    pending: VecDeque<u64>,
+    /// Number of queued publishes stored on disk, including those sent but not yet completed
+    stored: usize,
+    /// Sequence number for the next queued publish
+    next_seq: u64,
+    /// Indicates a queued publish is currently being sent
+    send_in_progress: bool,
+    /// Notifiers for the completion of publishes queued by this process
+    completions: HashMap<u64, oneshot::Sender<Result<(), CompletionError>>>,
+}
+
+impl OfflinePublishQueue {
+    /// Open the queue stored in the provided directory, creating the directory if necessary.
+    /// Publishes already stored in the directory will be sent before any newly queued ones.
+    ///
+    /// # Errors
+    /// Returns an [`io::Error`] if the directory cannot be created or read.
+    pub fn open(dir: impl AsRef<Path>, max_entries: usize) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut stored_seqs = vec![];
+        for dir_entry in fs::read_dir(&dir)? {
+            let path = dir_entry?.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some(ENTRY_EXTENSION) => {
+                    if let Some(seq) = path
+                        .file_stem()
+                        .and_then(|s| s.to_str())
+                        .and_then(|s| s.parse::<u64>().ok())
+                    {
+                        stored_seqs.push(seq);
+                    }
+                }
+                // Remove publishes that were not completely written before a previous exit
+                Some(TMP_EXTENSION) => fs::remove_file(&path)?,
+                _ => {}
+            }
+        }
+        stored_seqs.sort_unstable();
+        if !stored_seqs.is_empty() {
+            log::info!(
+                "{} publish(es) from a previous run found in offline publish queue",
+                stored_seqs.len()
+            );
+        }
+
+        let next_seq = stored_seqs.last().map_or(0, |seq| seq + 1);
+        Ok(Self {
+            dir,
+            max_entries,
+            inner: Mutex::new(InnerQueue {
+                stored: stored_seqs.len(),
+                pending: stored_seqs.into(),
+                next_seq,
+                send_in_progress: false,
+                completions: HashMap::new(),
+            }),
+            enqueue_lock: tokio::sync::Mutex::new(()),
+            entry_added: Notify::new(),
+        })
+    }
+
+    /// Return the number of queued publishes not yet sent
+    pub fn depth(&self) -> usize {
+        self.inner.lock().unwrap().pending.len()
+    }
+
+    /// Queue a publish if the client is not connected, or if there are already publishes in the
+    /// queue waiting to be sent, so that publishes are always sent in order.
+    ///
+    /// Returns `None` if the publish was not queued and should be sent directly. Otherwise,
+    /// returns a receiver for the result of the eventual delivery.
+    ///
+    /// # Errors
+    /// Returns an [`EnqueueError`] if the publish needed to be queued, but could not be.
+    pub async fn enqueue(
+        &self,
+        connected: bool,
+        topic: &str,
+        retain: bool,
+        payload: &Bytes,
+        properties: Option<&PublishProperties>,
+    ) -> Result<Option<oneshot::Receiver<Result<(), CompletionError>>>, EnqueueError> {
+        let _enqueue_guard = self.enqueue_lock.lock().await;
+        let seq = {
+            let inner = self.inner.lock().unwrap();
+            if connected && inner.pending.is_empty() && !inner.send_in_progress {
+                return Ok(None);
+            }
+            if inner.stored >= self.max_entries {
+                return Err(EnqueueError::Full);
+            }
+            inner.next_seq
+        };
+
+        let bytes = encode_entry(topic, retain, payload, properties, SystemTime::now())
+            .map_err(EnqueueError::Io)?;
+        let dir = self.dir.clone();
+        let tmp_path = self.entry_path(seq, TMP_EXTENSION);
+        let entry_path = self.entry_path(seq, ENTRY_EXTENSION);
+        tokio::task::spawn_blocking(move || write_entry(&dir, &tmp_path, &entry_path, &bytes))
+            .await
+            .map_err(|e| EnqueueError::Io(io::Error::other(e)))?
+            .map_err(EnqueueError::Io)?;
+
+        let (completion_tx, completion_rx) = oneshot::channel();
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_seq += 1;
+        inner.stored += 1;
+        inner.pending.push_back(seq);
+        inner.completions.insert(seq, completion_tx);
+        self.entry_added.notify_one();
+        Ok(Some(completion_rx))
+    }
+
+    /// Wait for the next queued publish to send.
+    ///
+    /// Queued publishes that have expired or cannot be read are discarded.
+    /// Once the returned publish has been handed to the client, [`Self::sent`] must be called.
+    pub async fn next(&self) -> QueuedPublish {
+        loop {
+            let next_seq = {
+                let mut inner = self.inner.lock().unwrap();
+                let next_seq = inner.pending.pop_front();
+                inner.send_in_progress = next_seq.is_some();
+                next_seq
+            };
+            let Some(seq) = next_seq else {
+                self.entry_added.notified().await;
+                continue;
+            };
+
+            let entry_path = self.entry_path(seq, ENTRY_EXTENSION);
+            let entry = tokio::task::spawn_blocking(move || fs::read(entry_path))
+                .await
+                .unwrap_or_else(|e| Err(io::Error::other(e)))
+                .and_then(|bytes| decode_entry(seq, BytesMut::from(&bytes[..]), SystemTime::now()));
+            match entry {
+                Ok(Some(queued_publish)) => return queued_publish,
+                Ok(None) => {
+                    log::info!("Queued publish {seq} expired before it could be sent. Discarding.");
+                }
+                Err(e) => {
+                    log::error!("Cannot read queued publish {seq}. Discarding. Reason: {e:?}");
+                }
+            }
+            self.sent();
+            self.finish(seq, Err(CompletionError::Recv), false);
+        }
+    }
+
+    /// Indicate that the publish returned by [`Self::next`] has been handed to the client
+    pub fn sent(&self) {
+        self.inner.lock().unwrap().send_in_progress = false;
+    }
+
+    /// Indicate that delivery of a queued publish has completed with the provided result,
+    /// removing it from disk.
+    pub fn complete(&self, seq: u64, result: Result<(), CompletionError>) {
+        // NOTE: If the client was detached before delivery completed, the queued publish is kept
+        // on disk so that it will be replayed when the queue is next opened.
+        let keep_stored = matches!(result, Err(CompletionError::Recv));
+        self.finish(seq, result, keep_stored);
+    }
+
+    /// Helper for removing a queued publish and notifying of its completion
+    fn finish(&self, seq: u64, result: Result<(), CompletionError>, keep_stored: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if !keep_stored {
+            match fs::remove_file(self.entry_path(seq, ENTRY_EXTENSION)) {
+                Ok(()) => inner.stored -= 1,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => inner.stored -= 1,
+                Err(e) => log::error!("Cannot remove queued publish {seq}: {e:?}"),
+            }
+        }
+        if let Some(completion_tx) = inner.completions.remove(&seq) {
+            // Ignore the error - the receiver may no longer care about completion
+            let _ = completion_tx.send(result);
+        }
+    }
+
+    /// Return the file path for a queued publish
+    fn entry_path(&self, seq: u64, extension: &str) -> PathBuf {
+        self.dir.join(format!("{seq:020}.{extension}"))
+    }
+}
+
+/// Durably write an encoded publish to the provided entry path.
+///
+/// The publish is written to a temporary file first, so that a partially written publish is
+/// never replayed.
+fn write_entry(dir: &Path, tmp_path: &Path, entry_path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let result = fs::File::create(tmp_path)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(tmp_path, entry_path));
+    if let Err(e) = result {
+        // Ignore the error - the temporary file is removed when the queue is next opened
+        let _ = fs::remove_file(tmp_path);
+        return Err(e);
+    }
+    // Sync the directory so that the rename survives a crash
+    // NOTE: Directories cannot be opened as files on Windows, where the rename is durable once
+    // the file data is synced.
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Encode a publish for storage, preceded by the time it was queued.
+fn encode_entry(
+    topic: &str,
+    retain: bool,
+    payload: &Bytes,
+    properties: Option<&PublishProperties>,
+    queued_at: SystemTime,
+) -> io::Result<BytesMut> {
+    let mut publish = Publish::new(
+        topic,
+        QoS::AtLeastOnce,
+        payload.clone(),
+        properties.cloned(),
+    );
+    publish.retain = retain;
+    // NOTE: A packet identifier is required to encode a QoS 1 publish. The stored value is a
+    // placeholder, as a new packet identifier is assigned when the publish is sent.
+    publish.pkid = 1;
+
+    let queued_at = queued_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut bytes = BytesMut::with_capacity(8 + publish.size());
+    bytes.put_u64(queued_at);
+    publish
+        .write(&mut bytes)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{e:?}")))?;
+    Ok(bytes)
+}
+
+/// Decode a stored publish.
+///
+/// Returns `None` if the message expiry interval of the publish has elapsed.
+fn decode_entry(
+    seq: u64,
+    mut bytes: BytesMut,
+    now: SystemTime,
+) -> io::Result<Option<QueuedPublish>> {
+    if bytes.len() < 8 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let queued_at = bytes.get_u64();
+    let publish = match Packet::read(&mut bytes, None) {
+        Ok(Packet::Publish(publish)) => publish,
+        Ok(packet) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected packet: {packet:?}"),
+            ));
+        }
+        Err(e) => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}")));
+        }
+    };
+    let topic = String::from_utf8(publish.topic.to_vec())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    // Reduce the message expiry interval by the time spent in the queue
+    let mut properties = publish.properties.unwrap_or_default();
+    if let Some(expiry_interval) = properties.message_expiry_interval {
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let elapsed = now.saturating_sub(queued_at);
+        if elapsed >= u64::from(expiry_interval) {
+            return Ok(None);
+        }
+        // NOTE: This conversion cannot fail, as elapsed is less than the expiry interval
+        properties.message_expiry_interval =
+            Some(expiry_interval - u32::try_from(elapsed).unwrap());
+    }
+
+    Ok(Some(QueuedPublish {
+        seq,
+        topic,
+        retain: publish.retain,
+        payload: publish.payload,
+        properties,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn open_queue(dir: &tempfile::TempDir) -> OfflinePublishQueue {
+        OfflinePublishQueue::open(dir.path(), 10).unwrap()
+    }
+
+    #[tokio::test]
+    async fn not_queued_while_connected() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = open_queue(&dir);
+        assert!(
+            queue
+                .enqueue(true, "test/topic", false, &Bytes::from("payload"), None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn queued_while_disconnected_and_sent_in_order() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = open_queue(&dir);
+        let mut completion_rx1 = queue
+            .enqueue(false, "test/topic1", false, &Bytes::from("payload1"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        // Queued while connected, as there are already publishes waiting to be sent
+        let _completion_rx2 = queue
+            .enqueue(true, "test/topic2", true, &Bytes::from("payload2"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(queue.depth(), 2);
+
+        let first = queue.next().await;
+        assert_eq!(first.topic, "test/topic1");
+        assert_eq!(first.payload, Bytes::from("payload1"));
+        assert!(!first.retain);
+        queue.sent();
+        queue.complete(first.seq, Ok(()));
+        assert!(completion_rx1.try_recv().unwrap().is_ok());
+
+        let second = queue.next().await;
+        assert_eq!(second.topic, "test/topic2");
+        assert_eq!(second.payload, Bytes::from("payload2"));
+        assert!(second.retain);
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn replayed_after_reopen() {
+        let dir = tempfile::TempDir::new().unwrap();
+        {
+            let queue = open_queue(&dir);
+            for i in 0..3 {
+                queue
+                    .enqueue(false, &format!("test/topic{i}"), false, &Bytes::new(), None)
+                    .await
+                    .unwrap();
+            }
+            // The first publish completes delivery before the restart
+            let first = queue.next().await;
+            queue.sent();
+            queue.complete(first.seq, Ok(()));
+            // The second publish is sent, but the client is detached before delivery completes
+            let second = queue.next().await;
+            queue.sent();
+            queue.complete(second.seq, Err(CompletionError::Recv));
+        }
+
+        let queue = open_queue(&dir);
+        assert_eq!(queue.depth(), 2);
+        assert_eq!(queue.next().await.topic, "test/topic1");
+        queue.sent();
+        assert_eq!(queue.next().await.topic, "test/topic2");
+    }
+
+    #[tokio::test]
+    async fn full() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = OfflinePublishQueue::open(dir.path(), 1).unwrap();
+        queue
+            .enqueue(false, "test/topic", false, &Bytes::new(), None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            queue
+                .enqueue(false, "test/topic", false, &Bytes::new(), None)
+                .await,
+            Err(EnqueueError::Full)
+        ));
+    }
+
+    #[tokio::test]
+    async fn write_error() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = open_queue(&dir);
+        // The queue directory is removed, so the publish cannot be written
+        fs::remove_dir_all(dir.path()).unwrap();
+        assert!(matches!(
+            queue
+                .enqueue(false, "test/topic", false, &Bytes::new(), None)
+                .await,
+            Err(EnqueueError::Io(_))
+        ));
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[test]
+    fn message_expiry_interval() {
+        let properties = PublishProperties {
+            message_expiry_interval: Some(10),
+            ..Default::default()
+        };
+        let queued_at = SystemTime::now();
+        let bytes = encode_entry(
+            "test/topic",
+            false,
+            &Bytes::new(),
+            Some(&properties),
+            queued_at,
+        )
+        .unwrap();
+
+        // Expiry interval is reduced by the time spent in the queue
+        let queued_publish = decode_entry(0, bytes.clone(), queued_at + Duration::from_secs(4))
+            .unwrap()
+            .unwrap();
+        assert_eq!(queued_publish.properties.message_expiry_interval, Some(6));
+
+        // Expired publishes are not returned
+        assert!(
+            decode_entry(0, bytes, queued_at + Duration::from_secs(10))
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -3,24 +3,31 @@
 
 //! Tooling for sending/receiving publishes to/on multiple receivers which can be distributed in async contexts.
 
+mod held_acks;
 mod ordered_acker;
 mod plenary_ack;
+mod publish_channel;
+mod publish_filter;
 
 use std::collections::HashMap;
+use std::num::NonZeroUsize;
 use std::string::FromUtf8Error;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use thiserror::Error;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
 
-use crate::control_packet::{Publish, QoS};
+use crate::control_packet::{PubAckReasonCode, Publish, QoS};
 use crate::error::AckError;
 use crate::interface::{CompletionToken, MqttAck};
+use crate::metrics::Metrics;
 use crate::session::receiver::{
+    held_acks::HeldAckTracker,
     ordered_acker::{OrderedAcker, PkidAckQueue, PkidError},
-    plenary_ack::{PlenaryAck, PlenaryAckMember},
+    plenary_ack::{AckReason, PlenaryAck, PlenaryAckMember},
+    publish_channel::{PublishItem, TrySendError},
 };
-use crate::topic::{TopicFilter, TopicName, TopicParseError};
+use crate::topic::{TopicFilter, TopicName, TopicParseError, TopicTree};
 
 /// Token that can be used to acknowledge a received MQTT publish.
 #[derive(Debug)]
@@ -35,14 +42,36 @@ impl AckToken {
     pub async fn ack(self) -> Result<CompletionToken, AckError> {
         self.0.ack().await
     }
+
+    /// Acknowledge the received Publish message with the provided reason code and (optional)
+    /// reason string, and return a [`CompletionToken`] for the completion of the acknowledgement
+    /// process.
+    ///
+    /// Use a failure reason code (e.g. [`PubAckReasonCode::ImplementationSpecificError`]) to
+    /// reject the Publish message. If the Publish message was dispatched to multiple receivers,
+    /// a failure reason code provided by any of them takes precedence over a success reason code,
+    /// and otherwise the first reason provided is used.
+    ///
+    /// # Errors
+    /// Returns an [`AckError`] if the Publish message could not be acknowledged.
+    pub async fn ack_with_reason(
+        self,
+        reason_code: PubAckReasonCode,
+        reason_string: Option<String>,
+    ) -> Result<CompletionToken, AckError> {
+        self.0.ack_with_reason((reason_code, reason_string)).await
+    }
 }
 
-// NOTE: We need to use unbounded channels, because there is no way to know how many
+// NOTE: Channels are unbounded by default, because there is no way to know how many
 // publishes may be in-flight. The MQTT client can specify a receive_maximum, yes,
 // but that only applies to QoS1 and QoS2. There is no limit on QoS0.
 // See 3.1.2.11.3 in the MQTT 5.0 spec.
-pub type PublishTx = UnboundedSender<(Publish, Option<AckToken>)>;
-pub type PublishRx = UnboundedReceiver<(Publish, Option<AckToken>)>;
+// Bounded channels can be requested, in which case an OverflowPolicy determines the behavior
+// when the bound is reached.
+pub use held_acks::HeldAckTracker;
+pub use publish_channel::{OverflowPolicy, PublishRx, PublishTx};
+pub use publish_filter::PublishFilter;
 
 // NOTE: These errors should never happen in correct usage.
 // - Invalid publish topics should not happen, since we shouldn't be receiving Publishes from the
@@ -66,9 +95,30 @@ pub enum InvalidPublish {
     TopicNameFormat(#[from] TopicParseError),
 }
 
+/// A [`PublishTx`] for a filtered receiver, along with the receiver's [`PublishFilter`], if any
+struct FilteredTx {
+    tx: PublishTx,
+    publish_filter: Option<PublishFilter>,
+}
+
+impl FilteredTx {
+    /// Returns true if the receiver has been closed
+    fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Returns true if the [`Publish`] matches the [`PublishFilter`] of the receiver, if any
+    fn matches(&self, publish: &Publish) -> bool {
+        self.publish_filter
+            .as_ref()
+            .is_none_or(|publish_filter| publish_filter.matches(publish))
+    }
+}
+
 #[derive(Default)]
 pub struct PublishReceiverManager {
-    filtered_txs: HashMap<TopicFilter, Vec<PublishTx>>,
+    filtered_txs: TopicTree<Vec<FilteredTx>>,
+    identified_txs: HashMap<usize, Vec<PublishTx>>,
     unfiltered_txs: Vec<PublishTx>,
 }
 
@@ -82,31 +132,110 @@ impl PublishReceiverManager {
     /// # Arguments
     /// * `topic_filter` - The topic filter to match incoming publishes against
     pub fn create_filtered_receiver(&mut self, topic_filter: &TopicFilter) -> PublishRx {
+        let (tx, rx) = publish_channel::unbounded();
+        self.register_filtered_tx(
+            topic_filter,
+            FilteredTx {
+                tx,
+                publish_filter: None,
+            },
+        );
+        rx
+    }
+
+    /// Create a new [`PublishRx`] that will receive dispatched [`Publish`]es that match both the
+    /// provided topic filter and the provided [`PublishFilter`] for as long as it is open.
+    ///
+    /// A publish that matches the topic filter but not the [`PublishFilter`] is not considered
+    /// to have been dispatched to this receiver.
+    ///
+    /// # Arguments
+    /// * `topic_filter` - The topic filter to match incoming publishes against
+    /// * `publish_filter` - The filter to match the content of incoming publishes against
+    pub fn create_content_filtered_receiver(
+        &mut self,
+        topic_filter: &TopicFilter,
+        publish_filter: PublishFilter,
+    ) -> PublishRx {
+        let (tx, rx) = publish_channel::unbounded();
+        self.register_filtered_tx(
+            topic_filter,
+            FilteredTx {
+                tx,
+                publish_filter: Some(publish_filter),
+            },
+        );
+        rx
+    }
+
+    /// Create a new [`PublishRx`] that will receive dispatched [`Publish`]es that match the
+    /// provided topic filter for as long as it is open, holding at most `capacity` [`Publish`]es
+    /// that have not yet been received.
+    ///
+    /// # Arguments
+    /// * `topic_filter` - The topic filter to match incoming publishes against
+    /// * `capacity` - The maximum number of publishes held by the receiver
+    /// * `overflow_policy` - The policy to apply when a publish is dispatched to a full receiver
+    pub fn create_bounded_filtered_receiver(
+        &mut self,
+        topic_filter: &TopicFilter,
+        capacity: NonZeroUsize,
+        overflow_policy: OverflowPolicy,
+    ) -> PublishRx {
+        let (tx, rx) = publish_channel::bounded(capacity, overflow_policy);
+        self.register_filtered_tx(
+            topic_filter,
+            FilteredTx {
+                tx,
+                publish_filter: None,
+            },
+        );
+        rx
+    }
+
+    /// Register a [`FilteredTx`] for the provided topic filter
+    fn register_filtered_tx(&mut self, topic_filter: &TopicFilter, tx: FilteredTx) {
         // NOTE: We prune the filtered txs before registering any more to ensure that closed
-        // txs (or entire vectors of txs) don't stick around in the HashMap indefinitely, making
+        // txs (or entire vectors of txs) don't stick around in the TopicTree indefinitely, making
         // dispatching more expensive. We also do cleanup during a dispatch, but since dispatching
         // only looks at the elements of vectors that are relevant to a given dispatch (i.e. lazy pruning),
         // we still need to do a full pruning when registering new tx filters.
         self.prune_filtered_txs();
 
-        let (tx, rx) = unbounded_channel();
-        match self.filtered_txs.get_mut(topic_filter) {
-            // If the topic filter is already in use, add to the associated vector
-            Some(v) => {
-                v.push(tx);
-                // Otherwise, create a new vector and add
-            }
-            _ => {
-                self.filtered_txs.insert(topic_filter.clone(), vec![tx]);
-            }
-        }
+        // If the topic filter is already in use, add to the associated vector.
+        // Otherwise, create a new vector and add
+        self.filtered_txs
+            .get_or_insert_with(topic_filter, Vec::new)
+            .push(tx);
+    }
+
+    /// Create a new [`PublishRx`] that will receive dispatched [`Publish`]es that carry the
+    /// provided subscription identifier for as long as it is open.
+    ///
+    /// The topic of the [`Publish`] is not considered. Multiple receivers can be created for the
+    /// same subscription identifier, and each will receive all publishes that carry it.
+    ///
+    /// # Arguments
+    /// * `subscription_identifier` - The subscription identifier to match incoming publishes against
+    pub fn create_identified_receiver(&mut self, subscription_identifier: usize) -> PublishRx {
+        // NOTE: As with filtered receivers, prune before registering so that closed txs for
+        // subscription identifiers that are no longer dispatched to don't stick around.
+        self.identified_txs.retain(|_, v| {
+            v.retain(|tx| !tx.is_closed());
+            !v.is_empty()
+        });
 
+        let (tx, rx) = publish_channel::unbounded();
+        self.identified_txs
+            .entry(subscription_identifier)
+            .or_default()
+            .push(tx);
         rx
     }
 
     /// Create a new [`PublishRx`] that will receive all dispatched [`Publish`]es that do not
-    /// match the topic filters for any other filtered [`PublishRx`]s, for as long as it
-    /// is open.
+    /// match the topic filters (or subscription identifiers) for any other filtered
+    /// [`PublishRx`]s, for as long as it is open.
     ///
     /// Multiple unfiltered receivers can be created, and each will receive all publishes that are
     /// not matched by any filtered receiver.
@@ -115,7 +244,7 @@ impl PublishReceiverManager {
         // vector of any closed unfiltered txs here. Since there's not a HashMap, the lazy cleanup
         // during dispatch is sufficient.
 
-        let (tx, rx) = unbounded_channel();
+        let (tx, rx) = publish_channel::unbounded();
         self.unfiltered_txs.push(tx);
         rx
     }
@@ -123,8 +252,9 @@ impl PublishReceiverManager {
     /// Remove any closed filter receivers.
     ///
     /// Call this before any register
-    /// Note that the runtime is O(c * m) and not O(n * m) as it may seem.
-    /// (c = capacity, m = max number of duplicate listeners on a filter, n = number of filters).
+    /// Note that the runtime is O(l * m) and not O(n * m) as it may seem.
+    /// (l = number of nodes in the topic tree, m = max number of duplicate listeners on a filter,
+    /// n = number of filters).
     fn prune_filtered_txs(&mut self) {
         self.filtered_txs.retain(|_, v| {
             v.retain(|tx| !tx.is_closed());
@@ -139,8 +269,12 @@ where
     A: MqttAck + Clone + Send + Sync + 'static,
 {
     acker: OrderedAcker<A>,
+    /// Indicates if publishes are acked in the order they were received
+    ordered_acks: bool,
     pkid_ack_queue: Arc<Mutex<PkidAckQueue>>,
+    held_acks: Arc<Mutex<HeldAckTracker>>,
     receiver_manager: Arc<Mutex<PublishReceiverManager>>,
+    metrics: Metrics,
 }
 
 impl<A> IncomingPublishDispatcher<A>
@@ -156,11 +290,45 @@ where
         let acker = OrderedAcker::new(acker, pkid_ack_queue.clone());
         Self {
             acker,
+            ordered_acks: true,
             pkid_ack_queue,
+            held_acks: Arc::new(Mutex::new(HeldAckTracker::default())),
             receiver_manager: Arc::new(Mutex::new(PublishReceiverManager::default())),
+            metrics: Metrics::default(),
         }
     }
 
+    /// Record metrics for dispatched publishes and their acknowledgement with the provided
+    /// [`Metrics`].
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    /// Set whether publishes are acked in the order they were received (the default), or as soon
+    /// as all of their [`AckToken`]s have been acked.
+    ///
+    /// Unordered acking prevents a receiver that holds an [`AckToken`] for a long time from
+    /// delaying the acks of publishes dispatched after it, but must only be used with brokers
+    /// that do not require acks in receive order.
+    pub fn set_ordered_acks(&mut self, ordered_acks: bool) {
+        self.ordered_acks = ordered_acks;
+    }
+
+    // Get a shared reference to the [`HeldAckTracker`] for this dispatcher, for finding
+    // dispatched publishes whose AckTokens are being held for a long time.
+    pub fn get_held_ack_tracker(&self) -> Arc<Mutex<HeldAckTracker>> {
+        self.held_acks.clone()
+    }
+
+    /// Abandon acknowledgement of all publishes dispatched so far, as the MQTT session they were
+    /// received on has been lost. Their PKIDs may be re-used by the broker in the new MQTT session.
+    pub fn reset_acks(&self) {
+        self.pkid_ack_queue.lock().unwrap().reset();
+        self.held_acks.lock().unwrap().clear();
+        self.acker.wake_pending();
+        self.metrics.ack_queue_depth(0);
+    }
+
     // Get a shared reference to the [`PublishReceiverManager`] for this dispatcher.
     pub fn get_receiver_manager(&self) -> Arc<Mutex<PublishReceiverManager>> {
         self.receiver_manager.clone()
@@ -168,21 +336,25 @@ where
 
     /// Dispatch a [`Publish`] to all relevant receivers.
     ///
-    /// The [`Publish`] will be sent to any filtered receivers that correspond to the topic name.
-    /// If no filtered receivers that match the [`Publish`] topic name are present, the [`Publish`]
-    /// will be sent to all unfiltered receivers.
+    /// The [`Publish`] will be sent to any receivers for the subscription identifiers it carries,
+    /// and to any filtered receivers that correspond to the topic name (and the [`PublishFilter`]
+    /// of the receiver, if any). If no such receivers are present, the [`Publish`] will be sent
+    /// to all unfiltered receivers.
     ///
     /// Returns the number of receivers that the [`Publish`] was dispatched to.
     ///
     /// Note that once a publish is successfully dispatched (even to 0 receivers), the
     /// [`IncomingPublishDispatcher`] has taken responsibility for acknowledging the publish.
     ///
+    /// If any relevant receiver is full and uses [`OverflowPolicy::Block`], this will not return
+    /// until that receiver has capacity (or is closed).
+    ///
     /// # Arguments
     /// * `publish` - The [`Publish`] to dispatch to receivers
     ///
     /// # Errors
     /// Returns a [`DispatchError`] if the dispatch fails.
-    pub fn dispatch_publish(&mut self, publish: &Publish) -> Result<usize, DispatchError> {
+    pub async fn dispatch_publish(&mut self, publish: &Publish) -> Result<usize, DispatchError> {
         let topic_name = extract_publish_topic_name(publish)?;
 
         // Check if the incoming publish is a duplicate of a publish that is already in the
@@ -216,15 +388,39 @@ where
                 None
             } else {
                 // Insert the PKID into the PKID queue for ordered acking
-                self.pkid_ack_queue.lock().unwrap().insert(publish.pkid)?;
-                // Create an acking future for use with a PlenaryAck
+                let epoch = {
+                    let mut pkid_ack_queue = self.pkid_ack_queue.lock().unwrap();
+                    pkid_ack_queue.insert(publish.pkid)?;
+                    self.metrics.ack_queue_depth(pkid_ack_queue.len());
+                    pkid_ack_queue.epoch()
+                };
+                self.held_acks
+                    .lock()
+                    .unwrap()
+                    .dispatched(publish.pkid, topic_name.as_str(), epoch);
+                // Create an acking future function for use with a PlenaryAck
                 let ack_f = {
                     let acker = self.acker.clone();
+                    let ordered_acks = self.ordered_acks;
                     let publish = publish.clone();
-                    async move {
-                        let result = acker.ordered_ack(&publish).await;
+                    let pkid_ack_queue = self.pkid_ack_queue.clone();
+                    let held_acks = self.held_acks.clone();
+                    let metrics = self.metrics.clone();
+                    let dispatched = Instant::now();
+                    move |reason: Option<AckReason>| async move {
+                        // NOTE: This future only runs once all AckTokens have been acked (or
+                        // dropped), so the publish is no longer held by any receiver, even if it
+                        // must still wait for its turn to be acked.
+                        held_acks.lock().unwrap().released(publish.pkid, epoch);
+                        let result = if ordered_acks {
+                            acker.ordered_ack_for_epoch(&publish, epoch, reason).await
+                        } else {
+                            acker.immediate_ack_for_epoch(&publish, epoch, reason).await
+                        };
                         if result.is_ok() {
                             log::debug!("Sent ACK for PKID {}", publish.pkid);
+                            metrics.acked(dispatched.elapsed());
+                            metrics.ack_queue_depth(pkid_ack_queue.lock().unwrap().len());
                         } else {
                             // NOTE: can't get the error out of the option enum since we must
                             // return it to the caller.
@@ -239,11 +435,29 @@ where
 
         // Dispatch the publish to all relevant receivers
         let mut num_dispatches = 0;
-        // First, dispatch to all filtered receivers that match the topic name
-        num_dispatches += self.dispatch_filtered(&topic_name, publish, plenary_ack.as_ref());
+        // Sends to full receivers that must wait for capacity
+        let mut blocked = vec![];
+        // First, dispatch to all receivers for the subscription identifiers of the publish
+        num_dispatches += self.dispatch_identified(publish, plenary_ack.as_ref(), &mut blocked);
+        // Then, dispatch to all filtered receivers that match the topic name
+        num_dispatches +=
+            self.dispatch_filtered(&topic_name, publish, plenary_ack.as_ref(), &mut blocked);
         // Then, if no filters matched, dispatch to all unfiltered receivers (if present)
-        if num_dispatches == 0 {
-            num_dispatches += self.dispatch_unfiltered(publish, plenary_ack.as_ref());
+        if num_dispatches == 0 && blocked.is_empty() {
+            num_dispatches += self.dispatch_unfiltered(publish, plenary_ack.as_ref(), &mut blocked);
+        }
+        // Finally, wait for capacity on any full receivers.
+        // NOTE: This is done after releasing the lock on the receiver manager, so that receivers
+        // can continue to be created and dropped while waiting.
+        for (tx, item, receiver) in blocked {
+            log::debug!(
+                "Receiver full. Waiting for capacity to dispatch PUB with PKID {}",
+                publish.pkid
+            );
+            if tx.send(item).await.is_ok() {
+                num_dispatches += 1;
+                self.metrics.message_received(receiver.as_deref());
+            }
         }
 
         log::debug!(
@@ -259,32 +473,103 @@ where
         Ok(num_dispatches)
     }
 
+    /// Dispatch to receivers for the subscription identifiers of the publish
+    fn dispatch_identified(
+        &mut self,
+        publish: &Publish,
+        plenary_ack: Option<&PlenaryAck>,
+        blocked: &mut Vec<(PublishTx, PublishItem, Option<String>)>,
+    ) -> usize {
+        let Some(properties) = &publish.properties else {
+            return 0;
+        };
+        let mut num_dispatches = 0;
+        let mut closed = vec![]; // (subscription identifier, position in vector)
+
+        let mut receiver_manager = self.receiver_manager.lock().unwrap();
+
+        for subscription_identifier in &properties.subscription_identifiers {
+            let Some(v) = receiver_manager.identified_txs.get(subscription_identifier) else {
+                continue;
+            };
+            let receiver = format!("subscription:{subscription_identifier}");
+            for (pos, tx) in v.iter().enumerate() {
+                // NOTE: See dispatch_filtered for why closed receivers are removed dynamically.
+                match tx.try_send((publish.clone(), create_ack_token(plenary_ack))) {
+                    Ok(()) => {
+                        num_dispatches += 1;
+                        self.metrics.message_received(Some(&receiver));
+                    }
+                    Err(TrySendError::Full(item)) => {
+                        blocked.push((tx.clone(), item, Some(receiver.clone())));
+                    }
+                    Err(TrySendError::Closed(_)) => closed.push((*subscription_identifier, pos)),
+                }
+            }
+        }
+
+        // Remove any closed receivers.
+        // NOTE: Do this in reverse order to avoid index issues.
+        for (subscription_identifier, pos) in closed.iter().rev() {
+            if let Some(v) = receiver_manager
+                .identified_txs
+                .get_mut(subscription_identifier)
+            {
+                v.remove(*pos);
+                if v.is_empty() {
+                    receiver_manager
+                        .identified_txs
+                        .remove(subscription_identifier);
+                }
+            }
+        }
+
+        num_dispatches
+    }
+
     /// Dispatch to filtered receivers
     fn dispatch_filtered(
         &mut self,
         topic_name: &TopicName,
         publish: &Publish,
         plenary_ack: Option<&PlenaryAck>,
+        blocked: &mut Vec<(PublishTx, PublishItem, Option<String>)>,
     ) -> usize {
         let mut num_dispatches = 0;
         let mut closed = vec![]; // (topic filter, position in vector)
 
         let mut receiver_manager = self.receiver_manager.lock().unwrap();
 
-        let filtered = receiver_manager
-            .filtered_txs
-            .iter()
-            .filter(|(topic_filter, _)| topic_filter.matches_topic_name(topic_name));
-        for (topic_filter, v) in filtered {
-            for (pos, tx) in v.iter().enumerate() {
+        // NOTE: The TopicTree finds the matching topic filters in time proportional to the depth
+        // of the topic name, rather than checking every registered topic filter.
+        for (topic_filter, v) in receiver_manager.filtered_txs.matches(topic_name) {
+            for (pos, filtered_tx) in v.iter().enumerate() {
+                // NOTE: Closed receivers are checked for before the content filter, so that they
+                // are removed even if the publish does not match their content filter.
+                if filtered_tx.is_closed() {
+                    closed.push((topic_filter.clone(), pos));
+                    continue;
+                }
+                // Skip receivers whose content filter does not match. No ack token is created,
+                // so the publish does not wait on them to be acknowledged.
+                if !filtered_tx.matches(publish) {
+                    continue;
+                }
+                let tx = &filtered_tx.tx;
                 // Send the publish to the receiver, along with an ack token
                 // If the receiver is closed, add it to the list of closed receivers to remove after iteration.
                 // NOTE: Removing closed receivers must be done dynamically because the awaitable send allows
                 // for a channel to be closed sometime during the execution of this loop. You cannot simply
                 // use .prune() before the loop.
-                match tx.send((publish.clone(), create_ack_token(plenary_ack))) {
-                    Ok(()) => num_dispatches += 1,
-                    Err(_) => closed.push((topic_filter.clone(), pos)),
+                match tx.try_send((publish.clone(), create_ack_token(plenary_ack))) {
+                    Ok(()) => {
+                        num_dispatches += 1;
+                        self.metrics.message_received(Some(topic_filter.as_str()));
+                    }
+                    Err(TrySendError::Full(item)) => {
+                        blocked.push((tx.clone(), item, Some(topic_filter.as_str().to_string())));
+                    }
+                    Err(TrySendError::Closed(_)) => closed.push((topic_filter.clone(), pos)),
                 }
             }
         }
@@ -308,6 +593,7 @@ where
         &mut self,
         publish: &Publish,
         plenary_ack: Option<&PlenaryAck>,
+        blocked: &mut Vec<(PublishTx, PublishItem, Option<String>)>,
     ) -> usize {
         let mut num_dispatches = 0;
         let mut closed = vec![];
@@ -319,9 +605,13 @@ where
             // If the receiver is closed, add it to the list of closed receivers to remove after iteration.
             // NOTE: Removing closed receivers must be done dynamically because the awaitable send allows
             // for a channel to be closed sometime during the execution of this loop
-            match tx.send((publish.clone(), create_ack_token(plenary_ack))) {
-                Ok(()) => num_dispatches += 1,
-                Err(_) => closed.push(pos),
+            match tx.try_send((publish.clone(), create_ack_token(plenary_ack))) {
+                Ok(()) => {
+                    num_dispatches += 1;
+                    self.metrics.message_received(None);
+                }
+                Err(TrySendError::Full(item)) => blocked.push((tx.clone(), item, None)),
+                Err(TrySendError::Closed(_)) => closed.push(pos),
             }
         }
 
@@ -348,7 +638,7 @@ fn create_ack_token(plenary_ack: Option<&PlenaryAck>) -> Option<AckToken> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::control_packet::QoS;
+    use crate::control_packet::{PublishProperties, QoS};
     use crate::interface_mocks::{MockClient, MockClientCall};
     use std::str::FromStr;
     use std::time::Duration;
@@ -409,7 +699,7 @@ mod tests {
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
 
         // Was sent to 0 receivers
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 0);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 0);
     }
 
     #[test_case(QoS::AtMostOnce; "QoS 0")]
@@ -427,7 +717,7 @@ mod tests {
         // Dispatched publish is received by the unfiltered receiver
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 1);
         assert_expected_recv_value(&unfiltered_rx.try_recv().unwrap(), &publish);
     }
 
@@ -448,7 +738,7 @@ mod tests {
         // Dispatched publish is received by all unfiltered receivers
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish = create_publish_qos(&topic_name, "payload", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 3);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 3);
         assert_expected_recv_value(&unfiltered_rx1.try_recv().unwrap(), &publish);
         assert_expected_recv_value(&unfiltered_rx2.try_recv().unwrap(), &publish);
         assert_expected_recv_value(&unfiltered_rx3.try_recv().unwrap(), &publish);
@@ -469,7 +759,7 @@ mod tests {
 
         // Dispatched publish is received by the unfiltered_receiver
         let publish1 = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish1).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish1).await.unwrap(), 1);
         assert_expected_recv_value(&unfiltered_rx.try_recv().unwrap(), &publish1);
 
         // Create a filtered receiver that does NOT match the topic name
@@ -482,7 +772,7 @@ mod tests {
 
         // Dispatching a publish to the same topic is still only received by the unfiltered receiver
         let publish2 = create_publish_qos(&topic_name, "publish 2", 2, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish2).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish2).await.unwrap(), 1);
         assert_expected_recv_value(&unfiltered_rx.try_recv().unwrap(), &publish2);
         assert_eq!(filtered_rx1.try_recv().unwrap_err(), TryRecvError::Empty);
 
@@ -496,7 +786,7 @@ mod tests {
 
         // Dispatching a publish to the same topic is now received by the matching filtered receiver ONLY
         let publish3 = create_publish_qos(&topic_name, "publish 3", 3, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish3).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish3).await.unwrap(), 1);
         assert_eq!(unfiltered_rx.try_recv().unwrap_err(), TryRecvError::Empty);
         assert_eq!(filtered_rx1.try_recv().unwrap_err(), TryRecvError::Empty);
         assert_expected_recv_value(&filtered_rx2.try_recv().unwrap(), &publish3);
@@ -506,7 +796,7 @@ mod tests {
 
         // Dispatching a publish to the same topic is now received by the unfiltered receiver once again
         let publish4 = create_publish_qos(&topic_name, "publish 4", 4, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish4).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish4).await.unwrap(), 1);
         assert_expected_recv_value(&unfiltered_rx.try_recv().unwrap(), &publish4);
         assert_eq!(filtered_rx1.try_recv().unwrap_err(), TryRecvError::Empty);
     }
@@ -536,7 +826,7 @@ mod tests {
 
         // Dispatched publish is not received
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 0);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 0);
         assert_eq!(filtered_rx.try_recv().unwrap_err(), TryRecvError::Empty);
     }
 
@@ -568,7 +858,7 @@ mod tests {
 
         // Dispatched publish is received by only the matching filtered receiver
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 1);
         assert_expected_recv_value(&filtered_rx1.try_recv().unwrap(), &publish);
         assert_eq!(filtered_rx2.try_recv().unwrap_err(), TryRecvError::Empty);
     }
@@ -601,7 +891,7 @@ mod tests {
 
         // Dispatched publish is received by only the matching filtered receiver
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 1);
         assert_expected_recv_value(&filtered_rx1.try_recv().unwrap(), &publish);
         assert_eq!(filtered_rx2.try_recv().unwrap_err(), TryRecvError::Empty);
     }
@@ -642,7 +932,7 @@ mod tests {
 
         // Dispatched publish is received by all matching filtered receivers
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 2);
         assert_expected_recv_value(&filtered_rx1.try_recv().unwrap(), &publish);
         assert_expected_recv_value(&filtered_rx2.try_recv().unwrap(), &publish);
         assert_eq!(filtered_rx3.try_recv().unwrap_err(), TryRecvError::Empty);
@@ -714,7 +1004,7 @@ mod tests {
 
         // Dispatched publish goes to the matching filtered receivers only
         let publish = create_publish_qos(&topic_name, "payload 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 4);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 4);
         assert_expected_recv_value(&filtered_rx1.try_recv().unwrap(), &publish);
         assert_expected_recv_value(&filtered_rx2.try_recv().unwrap(), &publish);
         assert_expected_recv_value(&filtered_rx3.try_recv().unwrap(), &publish);
@@ -725,6 +1015,175 @@ mod tests {
         assert_eq!(filtered_rx8.try_recv().unwrap_err(), TryRecvError::Empty);
     }
 
+    #[test_case(QoS::AtMostOnce; "QoS 0")]
+    #[test_case(QoS::AtLeastOnce; "QoS 1")]
+    #[test_case(QoS::ExactlyOnce; "QoS 2")]
+    #[tokio::test]
+    async fn dispatch_content_filtered_receivers(qos: QoS) {
+        let client = MockClient::new();
+        let mut dispatcher = IncomingPublishDispatcher::new(client);
+        let manager = dispatcher.get_receiver_manager();
+        let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
+        let topic_filter = TopicFilter::from_str("sport/#").unwrap();
+
+        // Create content filtered receivers on the same topic filter for different tenants
+        let mut contoso_rx = manager.lock().unwrap().create_content_filtered_receiver(
+            &topic_filter,
+            PublishFilter::UserProperty("tenant".to_string(), "contoso".to_string()),
+        );
+        let mut fabrikam_rx = manager.lock().unwrap().create_content_filtered_receiver(
+            &topic_filter,
+            PublishFilter::UserProperty("tenant".to_string(), "fabrikam".to_string()),
+        );
+        // And a filtered receiver without a content filter
+        let mut filtered_rx = manager
+            .lock()
+            .unwrap()
+            .create_filtered_receiver(&topic_filter);
+
+        // Dispatched publish is received only by the matching content filtered receiver and the
+        // receiver without a content filter
+        let mut publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
+        publish.properties = Some(PublishProperties {
+            user_properties: vec![("tenant".to_string(), "contoso".to_string())],
+            ..Default::default()
+        });
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 2);
+        assert_expected_recv_value(&contoso_rx.try_recv().unwrap(), &publish);
+        assert_eq!(fabrikam_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        assert_expected_recv_value(&filtered_rx.try_recv().unwrap(), &publish);
+    }
+
+    #[test_case(QoS::AtLeastOnce; "QoS 1")]
+    #[test_case(QoS::ExactlyOnce; "QoS 2")]
+    #[tokio::test]
+    async fn dispatch_no_matching_content_filter(qos: QoS) {
+        let client = MockClient::new();
+        let mock_controller = client.mock_controller();
+        let mut dispatcher = IncomingPublishDispatcher::new(client);
+        let manager = dispatcher.get_receiver_manager();
+        let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
+
+        // Create a receiver whose topic filter matches, but whose content filter does not
+        let topic_filter = TopicFilter::from_str("sport/tennis/player1").unwrap();
+        let mut filtered_rx = manager.lock().unwrap().create_content_filtered_receiver(
+            &topic_filter,
+            PublishFilter::ContentType("application/json".to_string()),
+        );
+
+        // Dispatched publish is sent to 0 receivers, and is acked
+        let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 0);
+        assert_eq!(filtered_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(mock_controller.ack_count(), 1);
+
+        // Create an unfiltered receiver
+        let mut unfiltered_rx = manager.lock().unwrap().create_unfiltered_receiver();
+
+        // Since the content filter does not match, the publish goes to the unfiltered receiver,
+        // and is not acked until the unfiltered receiver acks it
+        let publish = create_publish_qos(&topic_name, "publish 2", 2, qos);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 1);
+        assert_eq!(filtered_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        let (r_publish, ack_token) = unfiltered_rx.try_recv().unwrap();
+        assert_eq!(r_publish, publish);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(mock_controller.ack_count(), 1);
+        ack_token.unwrap().ack().await.unwrap();
+        assert_eq!(mock_controller.ack_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn dispatch_removes_closed_content_filtered_receiver() {
+        let client = MockClient::new();
+        let mut dispatcher = IncomingPublishDispatcher::new(client);
+        let manager = dispatcher.get_receiver_manager();
+        let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
+        let topic_filter = TopicFilter::from_str("sport/#").unwrap();
+
+        // Create content filtered receivers on the same topic filter, and close one of them
+        let contoso_rx = manager.lock().unwrap().create_content_filtered_receiver(
+            &topic_filter,
+            PublishFilter::UserProperty("tenant".to_string(), "contoso".to_string()),
+        );
+        let mut fabrikam_rx = manager.lock().unwrap().create_content_filtered_receiver(
+            &topic_filter,
+            PublishFilter::UserProperty("tenant".to_string(), "fabrikam".to_string()),
+        );
+        drop(contoso_rx);
+
+        // Dispatched publish does not match the content filter of the closed receiver, but the
+        // closed receiver is still removed
+        let mut publish = create_publish_qos(&topic_name, "publish 1", 1, QoS::AtLeastOnce);
+        publish.properties = Some(PublishProperties {
+            user_properties: vec![("tenant".to_string(), "fabrikam".to_string())],
+            ..Default::default()
+        });
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 1);
+        assert_expected_recv_value(&fabrikam_rx.try_recv().unwrap(), &publish);
+        let receiver_manager = manager.lock().unwrap();
+        let filtered_txs = receiver_manager.filtered_txs.get(&topic_filter).unwrap();
+        assert_eq!(filtered_txs.len(), 1);
+        assert!(filtered_txs.iter().all(|tx| !tx.is_closed()));
+    }
+
+    #[test_case(QoS::AtMostOnce; "QoS 0")]
+    #[test_case(QoS::AtLeastOnce; "QoS 1")]
+    #[test_case(QoS::ExactlyOnce; "QoS 2")]
+    #[tokio::test]
+    async fn dispatch_identified_receivers(qos: QoS) {
+        let client = MockClient::new();
+        let mut dispatcher = IncomingPublishDispatcher::new(client);
+        let manager = dispatcher.get_receiver_manager();
+        let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
+
+        // Create receivers for two subscriptions with overlapping topic filters, and an
+        // unfiltered receiver
+        let mut identified_rx1 = manager.lock().unwrap().create_identified_receiver(1);
+        let mut identified_rx2 = manager.lock().unwrap().create_identified_receiver(2);
+        let mut unfiltered_rx = manager.lock().unwrap().create_unfiltered_receiver();
+
+        // Publish delivered for subscription 1 only is received by its receiver only
+        let mut publish1 = create_publish_qos(&topic_name, "publish 1", 1, qos);
+        publish1.properties = Some(PublishProperties {
+            subscription_identifiers: vec![1],
+            ..Default::default()
+        });
+        assert_eq!(dispatcher.dispatch_publish(&publish1).await.unwrap(), 1);
+        assert_expected_recv_value(&identified_rx1.try_recv().unwrap(), &publish1);
+        assert_eq!(identified_rx2.try_recv().unwrap_err(), TryRecvError::Empty);
+        assert_eq!(unfiltered_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+
+        // Publish delivered for both subscriptions is received by both receivers
+        let mut publish2 = create_publish_qos(&topic_name, "publish 2", 2, qos);
+        publish2.properties = Some(PublishProperties {
+            subscription_identifiers: vec![1, 2],
+            ..Default::default()
+        });
+        assert_eq!(dispatcher.dispatch_publish(&publish2).await.unwrap(), 2);
+        assert_expected_recv_value(&identified_rx1.try_recv().unwrap(), &publish2);
+        assert_expected_recv_value(&identified_rx2.try_recv().unwrap(), &publish2);
+        assert_eq!(unfiltered_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+
+        // Publish without a known subscription identifier goes to the unfiltered receiver
+        let mut publish3 = create_publish_qos(&topic_name, "publish 3", 3, qos);
+        publish3.properties = Some(PublishProperties {
+            subscription_identifiers: vec![3],
+            ..Default::default()
+        });
+        assert_eq!(dispatcher.dispatch_publish(&publish3).await.unwrap(), 1);
+        assert_eq!(identified_rx1.try_recv().unwrap_err(), TryRecvError::Empty);
+        assert_eq!(identified_rx2.try_recv().unwrap_err(), TryRecvError::Empty);
+        assert_expected_recv_value(&unfiltered_rx.try_recv().unwrap(), &publish3);
+
+        // Once a receiver is dropped, it is removed on the next dispatch to it
+        drop(identified_rx2);
+        assert_eq!(dispatcher.dispatch_publish(&publish2).await.unwrap(), 1);
+        assert_expected_recv_value(&identified_rx1.try_recv().unwrap(), &publish2);
+        assert!(!manager.lock().unwrap().identified_txs.contains_key(&2));
+    }
+
     #[test_case(QoS::AtLeastOnce; "QoS 1")]
     #[test_case(QoS::ExactlyOnce; "QoS 2")]
     #[tokio::test]
@@ -740,7 +1199,7 @@ mod tests {
         // Dispatch a publish with a PKID
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish1 = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish1).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish1).await.unwrap(), 1);
 
         // Publish is received, but do not ack yet
         let (r_publish1, ack_token1) = unfiltered_rx.try_recv().unwrap();
@@ -749,7 +1208,7 @@ mod tests {
         // Dispatch a publish with the same PKID and get an error
         let publish2 = create_publish_qos(&topic_name, "publish 2", 1, qos);
         assert!(matches!(
-            dispatcher.dispatch_publish(&publish2).unwrap_err(),
+            dispatcher.dispatch_publish(&publish2).await.unwrap_err(),
             DispatchError::InvalidPublishPkid(_)
         ));
 
@@ -764,7 +1223,7 @@ mod tests {
         assert_eq!(mock_controller.ack_count(), 1);
 
         // Dispatching the second publish again can now succeed
-        assert_eq!(dispatcher.dispatch_publish(&publish2).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish2).await.unwrap(), 1);
 
         // Second publish can now be received
         let (r_publish2, ack_token2) = unfiltered_rx.try_recv().unwrap();
@@ -795,7 +1254,7 @@ mod tests {
         assert!(!TopicName::is_valid_topic_name(invalid_topic_name));
         let publish = Publish::new(invalid_topic_name, qos, "some payload", None);
         assert!(matches!(
-            dispatcher.dispatch_publish(&publish).unwrap_err(),
+            dispatcher.dispatch_publish(&publish).await.unwrap_err(),
             DispatchError::InvalidPublishTopic(_)
         ));
 
@@ -817,14 +1276,14 @@ mod tests {
 
         // Dispatching publish with no receivers is dispatched 0 times
         let publish1 = create_publish(&topic_name, "publish 1", 1);
-        assert_eq!(dispatcher.dispatch_publish(&publish1).unwrap(), 0);
+        assert_eq!(dispatcher.dispatch_publish(&publish1).await.unwrap(), 0);
 
         // Create an unfiltered receiver
         let mut unfiltered_rx1 = manager.lock().unwrap().create_unfiltered_receiver();
 
         // Dispatching a publish now goes to the newly created unfiltered receiver
         let publish2 = create_publish(&topic_name, "publish 2", 2);
-        assert_eq!(dispatcher.dispatch_publish(&publish2).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish2).await.unwrap(), 1);
         assert_expected_recv_value(&unfiltered_rx1.try_recv().unwrap(), &publish2);
 
         // Create another unfiltered receiver
@@ -832,7 +1291,7 @@ mod tests {
 
         // Dispatching a publish now goes to both unfiltered receivers
         let publish3 = create_publish(&topic_name, "publish 3", 3);
-        assert_eq!(dispatcher.dispatch_publish(&publish3).unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_publish(&publish3).await.unwrap(), 2);
         assert_expected_recv_value(&unfiltered_rx1.try_recv().unwrap(), &publish3);
         assert_expected_recv_value(&unfiltered_rx2.try_recv().unwrap(), &publish3);
 
@@ -844,7 +1303,7 @@ mod tests {
 
         // Dispatching a publish now goes only to the newly created filtered receiver
         let publish4 = create_publish(&topic_name, "publish 4", 4);
-        assert_eq!(dispatcher.dispatch_publish(&publish4).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish4).await.unwrap(), 1);
         assert_expected_recv_value(&filtered_rx1.try_recv().unwrap(), &publish4);
         assert_eq!(unfiltered_rx1.try_recv().unwrap_err(), TryRecvError::Empty);
         assert_eq!(unfiltered_rx2.try_recv().unwrap_err(), TryRecvError::Empty);
@@ -857,7 +1316,7 @@ mod tests {
 
         // Dispatching a publish now goes only to both filtered receivers
         let publish5 = create_publish(&topic_name, "publish 5", 5);
-        assert_eq!(dispatcher.dispatch_publish(&publish5).unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_publish(&publish5).await.unwrap(), 2);
         assert_expected_recv_value(&filtered_rx1.try_recv().unwrap(), &publish5);
         assert_expected_recv_value(&filtered_rx2.try_recv().unwrap(), &publish5);
         assert_eq!(unfiltered_rx1.try_recv().unwrap_err(), TryRecvError::Empty);
@@ -868,7 +1327,7 @@ mod tests {
 
         // Dispatching a publish now goes to only the remaining filtered receiver
         let publish6 = create_publish(&topic_name, "publish 6", 6);
-        assert_eq!(dispatcher.dispatch_publish(&publish6).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish6).await.unwrap(), 1);
         assert_expected_recv_value(&filtered_rx1.try_recv().unwrap(), &publish6);
         assert_eq!(unfiltered_rx1.try_recv().unwrap_err(), TryRecvError::Empty);
         assert_eq!(unfiltered_rx2.try_recv().unwrap_err(), TryRecvError::Empty);
@@ -878,7 +1337,7 @@ mod tests {
 
         // Dispatching a publish now goes to only the unfiltered receivers
         let publish7 = create_publish(&topic_name, "publish 7", 7);
-        assert_eq!(dispatcher.dispatch_publish(&publish7).unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_publish(&publish7).await.unwrap(), 2);
         assert_expected_recv_value(&unfiltered_rx1.try_recv().unwrap(), &publish7);
         assert_expected_recv_value(&unfiltered_rx2.try_recv().unwrap(), &publish7);
 
@@ -887,7 +1346,7 @@ mod tests {
 
         // Dispatching a publish now goes to only the remaining unfiltered receiver
         let publish8 = create_publish(&topic_name, "publish 8", 8);
-        assert_eq!(dispatcher.dispatch_publish(&publish8).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish8).await.unwrap(), 1);
         assert_expected_recv_value(&unfiltered_rx1.try_recv().unwrap(), &publish8);
 
         // Drop the remaining unfiltered receiver
@@ -895,7 +1354,7 @@ mod tests {
 
         // Dispatching a publish now goes to 0 receivers
         let publish9 = create_publish(&topic_name, "publish 9", 9);
-        assert_eq!(dispatcher.dispatch_publish(&publish9).unwrap(), 0);
+        assert_eq!(dispatcher.dispatch_publish(&publish9).await.unwrap(), 0);
     }
 
     #[tokio::test]
@@ -1315,7 +1774,7 @@ mod tests {
         assert!(topic_name.matches_topic_filter(&topic_filter5)); // Type 2
         assert!(topic_name.matches_topic_filter(&topic_filter6)); // Type 3
         let publish = create_publish(&topic_name, "payload 1", 1);
-        dispatcher.dispatch_publish(&publish).unwrap();
+        dispatcher.dispatch_publish(&publish).await.unwrap();
 
         // The entries are now updated to remove the dropped filters if the dispatched publish topic name
         // matches the dropped filter.
@@ -1410,7 +1869,7 @@ mod tests {
         assert!(topic_name.matches_topic_filter(&topic_filter2)); // Type 1
         assert!(!topic_name.matches_topic_filter(&topic_filter4)); // Type 2
         let publish = create_publish(&topic_name, "payload 2", 2);
-        dispatcher.dispatch_publish(&publish).unwrap();
+        dispatcher.dispatch_publish(&publish).await.unwrap();
 
         // Only the dropped receiver entries filters with a filter that was matched by the dispatched
         // publish topic name were removed.
@@ -1485,7 +1944,7 @@ mod tests {
         // Dispatch a publish
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish = create_publish(&topic_name, "payload 1", 1);
-        dispatcher.dispatch_publish(&publish).unwrap();
+        dispatcher.dispatch_publish(&publish).await.unwrap();
 
         // The entries are now updated to remove the dropped receiver
         assert_eq!(manager.lock().unwrap().unfiltered_txs.len(), 2);
@@ -1507,7 +1966,7 @@ mod tests {
         // Dispatch without creating any receivers, and the publish is sent to 0 receivers
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 0);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 0);
 
         // The publish was acked on the mock client
         tokio::time::sleep(Duration::from_secs(1)).await;
@@ -1534,7 +1993,7 @@ mod tests {
             .create_filtered_receiver(&topic_filter);
 
         // Dispatch publish again, and it is still sent to 0 receivers
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 0);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 0);
 
         // The publish was acked on the mock client
         tokio::time::sleep(Duration::from_secs(1)).await;
@@ -1564,7 +2023,7 @@ mod tests {
         drop(filtered_rx);
 
         // Dispatch publish again, and it is still sent to 0 receivers
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 0);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 0);
 
         // The publish was acked on the mock client
         tokio::time::sleep(Duration::from_secs(1)).await;
@@ -1586,7 +2045,7 @@ mod tests {
         let unfiltered_rx = manager.lock().unwrap().create_unfiltered_receiver();
 
         // Dispatch publish again, and it is now sent to 1 receiver
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 1);
 
         // This time the publish was not acked on the mock client
         tokio::time::sleep(Duration::from_secs(1)).await;
@@ -1605,7 +2064,7 @@ mod tests {
         // Dispatch without creating any receivers, and the publish is sent to 0 receivers
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish = create_publish_qos(&topic_name, "publish 1", 0, QoS::AtMostOnce);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 0);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 0);
 
         // The publish was not acked on the mock client
         tokio::time::sleep(Duration::from_secs(1)).await;
@@ -1620,7 +2079,7 @@ mod tests {
             .create_filtered_receiver(&topic_filter);
 
         // Dispatch publish again, and it is still sent to 0 receivers
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 0);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 0);
 
         // The publish was not acked on the mock client
         tokio::time::sleep(Duration::from_secs(1)).await;
@@ -1630,7 +2089,7 @@ mod tests {
         let mut unfiltered_rx = manager.lock().unwrap().create_unfiltered_receiver();
 
         // Dispatch publish again, and it is now sent to 1 receiver
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 1);
 
         // The publish was not acked on the mock client
         tokio::time::sleep(Duration::from_secs(1)).await;
@@ -1672,7 +2131,7 @@ mod tests {
         // Dispatched publish is received by the unfiltered receiver
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 1);
         let (r_publish, ack_token) = unfiltered_rx.try_recv().unwrap();
 
         // No ack has occurred for the publish yet
@@ -1709,7 +2168,7 @@ mod tests {
         // Dispatched publish is received by the unfiltered receivers
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish = create_publish_qos(&topic_name, "payload", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 2);
         let (r_publish1, ack_token1) = unfiltered_rx1.try_recv().unwrap();
         let (r_publish2, ack_token2) = unfiltered_rx2.try_recv().unwrap();
 
@@ -1762,10 +2221,10 @@ mod tests {
         let publish2 = create_publish_qos(&topic_name, "payload 2", 2, qos);
         let publish3 = create_publish_qos(&topic_name, "payload 3", 3, qos);
         let publish4 = create_publish_qos(&topic_name, "payload 4", 4, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish1).unwrap(), 1);
-        assert_eq!(dispatcher.dispatch_publish(&publish2).unwrap(), 1);
-        assert_eq!(dispatcher.dispatch_publish(&publish3).unwrap(), 1);
-        assert_eq!(dispatcher.dispatch_publish(&publish4).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish1).await.unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish2).await.unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish3).await.unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish4).await.unwrap(), 1);
 
         // Receive the publishes
         let (r_publish1, ack_token1) = unfiltered_rx.try_recv().unwrap();
@@ -1850,10 +2309,10 @@ mod tests {
         let publish2 = create_publish_qos(&topic_name, "payload 2", 2, qos);
         let publish3 = create_publish_qos(&topic_name, "payload 3", 3, qos);
         let publish4 = create_publish_qos(&topic_name, "payload 4", 4, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish1).unwrap(), 2);
-        assert_eq!(dispatcher.dispatch_publish(&publish2).unwrap(), 2);
-        assert_eq!(dispatcher.dispatch_publish(&publish3).unwrap(), 2);
-        assert_eq!(dispatcher.dispatch_publish(&publish4).unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_publish(&publish1).await.unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_publish(&publish2).await.unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_publish(&publish3).await.unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_publish(&publish4).await.unwrap(), 2);
 
         // Receive the publishes
         let (_r1_publish1, r1_ack_token1) = unfiltered_rx1.try_recv().unwrap();
@@ -1949,6 +2408,171 @@ mod tests {
         }
     }
 
+    #[test_case(QoS::AtLeastOnce; "QoS 1")]
+    #[test_case(QoS::ExactlyOnce; "QoS 2")]
+    #[tokio::test]
+    async fn ack_token_unordered_ack_unordered_mode(qos: QoS) {
+        let client = MockClient::new();
+        let mock_controller = client.mock_controller();
+        let mut dispatcher = IncomingPublishDispatcher::new(client);
+        dispatcher.set_ordered_acks(false);
+        let manager = dispatcher.get_receiver_manager();
+
+        // Create unfiltered receivers
+        let mut unfiltered_rx = manager.lock().unwrap().create_unfiltered_receiver();
+
+        // Dispatch two publishes
+        let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
+        let publish1 = create_publish_qos(&topic_name, "payload 1", 1, qos);
+        let publish2 = create_publish_qos(&topic_name, "payload 2", 2, qos);
+        assert_eq!(dispatcher.dispatch_publish(&publish1).await.unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish2).await.unwrap(), 1);
+
+        // Receive the publishes
+        let (_, ack_token1) = unfiltered_rx.try_recv().unwrap();
+        let (r_publish2, ack_token2) = unfiltered_rx.try_recv().unwrap();
+
+        // Acking the second publish results in the ack triggering on the client, without waiting
+        // for the first publish to be acked
+        ack_token2.unwrap().ack().await.unwrap();
+        assert_eq!(mock_controller.ack_count(), 1);
+        match &mock_controller.call_sequence()[0] {
+            MockClientCall::Ack(call) => {
+                assert_eq!(call.publish, r_publish2);
+            }
+            _ => panic!("Expected AcknowledgePublish"),
+        }
+
+        // PKID 2 can be re-used while the first publish is still held
+        let publish3 = create_publish_qos(&topic_name, "payload 3", 2, qos);
+        assert_eq!(dispatcher.dispatch_publish(&publish3).await.unwrap(), 1);
+        let (_, ack_token3) = unfiltered_rx.try_recv().unwrap();
+        ack_token3.unwrap().ack().await.unwrap();
+        assert_eq!(mock_controller.ack_count(), 2);
+
+        ack_token1.unwrap().ack().await.unwrap();
+        assert_eq!(mock_controller.ack_count(), 3);
+    }
+
+    #[test_case(QoS::AtLeastOnce; "QoS 1")]
+    #[test_case(QoS::ExactlyOnce; "QoS 2")]
+    #[tokio::test]
+    async fn ack_token_ack_with_reason(qos: QoS) {
+        let client = MockClient::new();
+        let mock_controller = client.mock_controller();
+        let mut dispatcher = IncomingPublishDispatcher::new(client);
+        let manager = dispatcher.get_receiver_manager();
+
+        // Create filtered receivers that both match the topic
+        let topic_filter1 = TopicFilter::from_str("sport/tennis/+").unwrap();
+        let topic_filter2 = TopicFilter::from_str("sport/+/player1").unwrap();
+        let mut filtered_rx1 = manager
+            .lock()
+            .unwrap()
+            .create_filtered_receiver(&topic_filter1);
+        let mut filtered_rx2 = manager
+            .lock()
+            .unwrap()
+            .create_filtered_receiver(&topic_filter2);
+
+        // Dispatch a publish to both receivers
+        let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
+        let publish = create_publish_qos(&topic_name, "payload 1", 1, qos);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 2);
+        let (_, ack_token1) = filtered_rx1.try_recv().unwrap();
+        let (_, ack_token2) = filtered_rx2.try_recv().unwrap();
+
+        // The failure reason of the second receiver takes precedence over the success reason of
+        // the first, even though the first was provided earlier
+        let jh1 = tokio::task::spawn(
+            ack_token1
+                .unwrap()
+                .ack_with_reason(PubAckReasonCode::NoMatchingSubscribers, None),
+        );
+        ack_token2
+            .unwrap()
+            .ack_with_reason(
+                PubAckReasonCode::PayloadFormatInvalid,
+                Some("invalid payload".to_string()),
+            )
+            .await
+            .unwrap();
+        jh1.await.unwrap().unwrap();
+
+        assert_eq!(mock_controller.ack_count(), 1);
+        match &mock_controller.call_sequence()[0] {
+            MockClientCall::Ack(call) => {
+                assert_eq!(call.publish, publish);
+                assert_eq!(call.reason_code, PubAckReasonCode::PayloadFormatInvalid);
+                assert_eq!(call.reason_string, Some("invalid payload".to_string()));
+            }
+            _ => panic!("Expected AcknowledgePublish"),
+        }
+    }
+
+    #[test_case(QoS::AtLeastOnce; "QoS 1")]
+    #[test_case(QoS::ExactlyOnce; "QoS 2")]
+    #[tokio::test]
+    async fn held_ack_tokens(qos: QoS) {
+        let client = MockClient::new();
+        let mut dispatcher = IncomingPublishDispatcher::new(client);
+        let manager = dispatcher.get_receiver_manager();
+        let held_acks = dispatcher.get_held_ack_tracker();
+
+        // Create filtered receivers
+        let topic_filter1 = TopicFilter::from_str("sport/tennis/+").unwrap();
+        let topic_filter2 = TopicFilter::from_str("sport/badminton/+").unwrap();
+        let mut filtered_rx1 = manager
+            .lock()
+            .unwrap()
+            .create_filtered_receiver(&topic_filter1);
+        let mut filtered_rx2 = manager
+            .lock()
+            .unwrap()
+            .create_filtered_receiver(&topic_filter2);
+
+        // Dispatch a publish to each receiver
+        let topic_name1 = TopicName::from_str("sport/tennis/player1").unwrap();
+        let topic_name2 = TopicName::from_str("sport/badminton/player1").unwrap();
+        let publish1 = create_publish_qos(&topic_name1, "payload 1", 1, qos);
+        let publish2 = create_publish_qos(&topic_name2, "payload 2", 2, qos);
+        assert_eq!(dispatcher.dispatch_publish(&publish1).await.unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish2).await.unwrap(), 1);
+        let (_, ack_token1) = filtered_rx1.try_recv().unwrap();
+        let (_, ack_token2) = filtered_rx2.try_recv().unwrap();
+
+        // Both publishes are held until their ack tokens are acked
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let held = held_acks
+            .lock()
+            .unwrap()
+            .held_for(Duration::from_millis(100));
+        assert_eq!(held.len(), 2);
+        assert_eq!(held[0].pkid, 1);
+        assert_eq!(held[0].topic, topic_name1.as_str());
+        assert_eq!(held[1].pkid, 2);
+        assert_eq!(held[1].topic, topic_name2.as_str());
+
+        // Acking the second publish releases it, even though its ack must wait for the first
+        let jh2 = tokio::task::spawn(ack_token2.unwrap().ack());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!jh2.is_finished());
+        let held = held_acks.lock().unwrap().held_for(Duration::ZERO);
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0].pkid, 1);
+
+        // Resetting the acks stops tracking all held publishes
+        dispatcher.reset_acks();
+        assert!(
+            held_acks
+                .lock()
+                .unwrap()
+                .held_for(Duration::ZERO)
+                .is_empty()
+        );
+        drop(ack_token1);
+    }
+
     #[test_case(QoS::AtLeastOnce; "QoS 1")]
     #[test_case(QoS::ExactlyOnce; "QoS 2")]
     #[tokio::test]
@@ -1964,7 +2588,7 @@ mod tests {
         // Dispatched publish is received by the unfiltered receiver
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 1);
         let (r_publish, ack_token) = unfiltered_rx.try_recv().unwrap();
 
         // No ack has occurred for the publish yet
@@ -2002,7 +2626,7 @@ mod tests {
         // Dispatched publish is received by the unfiltered receiver
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 2);
         let (_, ack_token1) = unfiltered_rx1.try_recv().unwrap();
         let (_, ack_token2) = unfiltered_rx2.try_recv().unwrap();
 
@@ -2047,7 +2671,7 @@ mod tests {
         // Dispatch to the receiver, but do not actually receive on the receiver
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 1);
 
         // No ack has occurred for the publish yet
         assert_eq!(mock_controller.ack_count(), 0);
@@ -2084,7 +2708,7 @@ mod tests {
         // Dispatch to the receivers, but do not actually receive with them
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 2);
 
         // No ack has occurred for the publish yet
         assert_eq!(mock_controller.ack_count(), 0);
@@ -2128,7 +2752,7 @@ mod tests {
         // Dispatch to the receiver, but do not actually receive with it
         let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
         let publish = create_publish_qos(&topic_name, "publish 1", 1, qos);
-        assert_eq!(dispatcher.dispatch_publish(&publish).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish).await.unwrap(), 1);
 
         // Drop the dispatcher and manager
         drop(dispatcher);
@@ -2158,4 +2782,52 @@ mod tests {
         let r_result = unfiltered_rx.recv().await;
         assert!(r_result.is_none());
     }
+
+    #[test_case(QoS::AtLeastOnce; "QoS 1")]
+    #[test_case(QoS::ExactlyOnce; "QoS 2")]
+    #[tokio::test]
+    async fn bounded_receiver_overflow_acks_in_order(qos: QoS) {
+        let client = MockClient::new();
+        let mock_controller = client.mock_controller();
+        let mut dispatcher = IncomingPublishDispatcher::new(client);
+        let manager = dispatcher.get_receiver_manager();
+
+        // Create a bounded filtered receiver that drops new publishes when full
+        let topic_filter = TopicFilter::from_str("sport/tennis/+").unwrap();
+        let mut filtered_rx = manager.lock().unwrap().create_bounded_filtered_receiver(
+            &topic_filter,
+            NonZeroUsize::new(1).unwrap(),
+            OverflowPolicy::DropNewest,
+        );
+
+        // Dispatch two publishes. The second overflows the receiver and is dropped.
+        let topic_name = TopicName::from_str("sport/tennis/player1").unwrap();
+        let publish1 = create_publish_qos(&topic_name, "publish 1", 1, qos);
+        let publish2 = create_publish_qos(&topic_name, "publish 2", 2, qos);
+        assert_eq!(dispatcher.dispatch_publish(&publish1).await.unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_publish(&publish2).await.unwrap(), 1);
+        assert_eq!(filtered_rx.dropped_count(), 1);
+
+        // The dropped publish is not acked before the publish that preceded it
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(mock_controller.ack_count(), 0);
+
+        // Receive and ack the first publish
+        let (r_publish, ack_token) = filtered_rx.try_recv().unwrap();
+        assert_eq!(r_publish, publish1);
+        assert_eq!(filtered_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        ack_token.unwrap().ack().await.unwrap().await.unwrap();
+
+        // Both publishes are now acked, in order
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(mock_controller.ack_count(), 2);
+        let calls = mock_controller.call_sequence();
+        match (&calls[0], &calls[1]) {
+            (MockClientCall::Ack(call1), MockClientCall::Ack(call2)) => {
+                assert_eq!(call1.publish, publish1);
+                assert_eq!(call2.publish, publish2);
+            }
+            _ => panic!("Expected AcknowledgePublish"),
+        }
+    }
 }
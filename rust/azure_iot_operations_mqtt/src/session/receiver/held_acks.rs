@@ -0,0 +1,115 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Diagnostics for finding dispatched publishes whose ack tokens are being held for a long time.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// A dispatched publish that has not yet been acked by all of the receivers it was dispatched to
+#[derive(Debug, PartialEq)]
+pub struct HeldAck {
+    /// PKID of the publish
+    pub pkid: u16,
+    /// Topic of the publish
+    pub topic: String,
+    /// How long the ack tokens for the publish have been held
+    pub held: Duration,
+}
+
+/// Tracker of the dispatched publishes whose ack tokens have not all been acked (or dropped)
+#[derive(Default)]
+pub struct HeldAckTracker {
+    /// Topic, dispatch time and [`PkidAckQueue`](super::ordered_acker::PkidAckQueue) epoch of
+    /// each held publish, by PKID
+    held: HashMap<u16, (String, Instant, u64)>,
+}
+
+impl HeldAckTracker {
+    /// Begin tracking a publish dispatched in the provided epoch
+    pub fn dispatched(&mut self, pkid: u16, topic: &str, epoch: u64) {
+        self.held
+            .insert(pkid, (topic.to_string(), Instant::now(), epoch));
+    }
+
+    /// Stop tracking a publish dispatched in the provided epoch, as all of its ack tokens have
+    /// been released.
+    ///
+    /// NOTE: The epoch is required so that a release from a lost MQTT session cannot stop the
+    /// tracking of a publish in the current MQTT session that re-uses the same PKID.
+    pub fn released(&mut self, pkid: u16, epoch: u64) {
+        if self.held.get(&pkid).is_some_and(|(_, _, e)| *e == epoch) {
+            self.held.remove(&pkid);
+        }
+    }
+
+    /// Stop tracking all publishes.
+    /// Use when the MQTT session the publishes were received on has been lost.
+    pub fn clear(&mut self) {
+        self.held.clear();
+    }
+
+    /// Return the publishes whose ack tokens have been held for at least the threshold,
+    /// longest held first
+    pub fn held_for(&self, threshold: Duration) -> Vec<HeldAck> {
+        let mut held_acks: Vec<HeldAck> = self
+            .held
+            .iter()
+            .map(|(pkid, (topic, dispatched, _))| HeldAck {
+                pkid: *pkid,
+                topic: topic.clone(),
+                held: dispatched.elapsed(),
+            })
+            .filter(|held_ack| held_ack.held >= threshold)
+            .collect();
+        held_acks.sort_by(|a, b| b.held.cmp(&a.held));
+        held_acks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_for_threshold() {
+        let mut tracker = HeldAckTracker::default();
+        tracker.dispatched(1, "sport/tennis/player1", 0);
+        std::thread::sleep(Duration::from_millis(100));
+        tracker.dispatched(2, "sport/tennis/player2", 0);
+        tracker.dispatched(3, "sport/tennis/player3", 0);
+
+        // Only the publish dispatched before the threshold is reported
+        let held_acks = tracker.held_for(Duration::from_millis(50));
+        assert_eq!(held_acks.len(), 1);
+        assert_eq!(held_acks[0].pkid, 1);
+        assert_eq!(held_acks[0].topic, "sport/tennis/player1");
+        assert!(held_acks[0].held >= Duration::from_millis(100));
+
+        // All publishes are reported with a threshold of 0, longest held first
+        let held_acks = tracker.held_for(Duration::ZERO);
+        assert_eq!(held_acks.len(), 3);
+        assert_eq!(held_acks[0].pkid, 1);
+
+        // Released publishes are no longer reported
+        tracker.released(1, 0);
+        assert!(tracker.held_for(Duration::from_millis(50)).is_empty());
+        assert_eq!(tracker.held_for(Duration::ZERO).len(), 2);
+
+        tracker.clear();
+        assert!(tracker.held_for(Duration::ZERO).is_empty());
+    }
+
+    #[test]
+    fn release_from_previous_epoch() {
+        let mut tracker = HeldAckTracker::default();
+        tracker.dispatched(1, "sport/tennis/player1", 1);
+
+        // A release from a previous epoch does not stop tracking the re-used PKID
+        tracker.released(1, 0);
+        assert_eq!(tracker.held_for(Duration::ZERO).len(), 1);
+
+        tracker.released(1, 1);
+        assert!(tracker.held_for(Duration::ZERO).is_empty());
+    }
+}
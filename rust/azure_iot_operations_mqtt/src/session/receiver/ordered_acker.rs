@@ -12,6 +12,7 @@ use tokio::sync::Notify;
 use crate::control_packet::Publish;
 use crate::error::{AckError, AckErrorKind};
 use crate::interface::{CompletionToken, MqttAck};
+use crate::session::receiver::plenary_ack::AckReason;
 
 /// Error related to PKID
 #[derive(Error, Debug, PartialEq)]
@@ -67,6 +68,23 @@ where
     /// Returns an [`AckError`] if the publish cannot be acknowledged. Note that if ack fails,
     /// its position the queue will be relinquished.
     pub async fn ordered_ack(&self, publish: &Publish) -> Result<CompletionToken, AckError> {
+        let epoch = self.pkid_ack_queue.lock().unwrap().epoch();
+        self.ordered_ack_for_epoch(publish, epoch, None).await
+    }
+
+    /// Acknowledge a received publish, when it is this publish's turn to be acked, provided the
+    /// [`PkidAckQueue`] has not been reset since the provided epoch.
+    /// If a reason is provided, it will be included in the PUBACK.
+    ///
+    /// # Errors
+    /// Returns an [`AckError`] if the publish cannot be acknowledged. Note that if ack fails,
+    /// its position the queue will be relinquished.
+    pub async fn ordered_ack_for_epoch(
+        &self,
+        publish: &Publish,
+        epoch: u64,
+        reason: Option<AckReason>,
+    ) -> Result<CompletionToken, AckError> {
         // No need to ack QoS0 publishes. Skip.
         if publish.pkid == 0 {
             return Ok(CompletionToken(Box::new(async { Ok(()) })));
@@ -95,6 +113,12 @@ where
             let should_ack = {
                 let mut pkid_ack_queue = self.pkid_ack_queue.lock().unwrap();
                 let mut pending_acks = self.pending_acks.lock().unwrap();
+                // If the queue was reset, the PKID belongs to a lost MQTT session, and may now
+                // be in use by a different publish. It must not be acked.
+                if pkid_ack_queue.epoch() != epoch {
+                    pending_acks.remove(&publish.pkid);
+                    return Err(AckError::new(AckErrorKind::SessionLost));
+                }
                 if let Some(next_ack_pkid) = pkid_ack_queue.check_next_ack_pkid() {
                     if next_ack_pkid == &publish.pkid {
                         // Publish PKID is the next ack, so pop data
@@ -118,7 +142,7 @@ where
 
             // Ack the publish if it is this publish's turn to be acked
             if should_ack {
-                let ct = self.acker.ack(publish).await?;
+                let ct = self.send_ack(publish, reason).await?;
                 // NOTE: Only notify the waiters AFTER the ack is completed to ensure that no scheduling
                 // shenanigans allow ack order to be altered.
                 self.notify.notify_waiters();
@@ -128,6 +152,70 @@ where
             self.notify.notified().await;
         }
     }
+
+    /// Acknowledge a received publish immediately, regardless of its position in the queue,
+    /// provided the [`PkidAckQueue`] has not been reset since the provided epoch.
+    /// If a reason is provided, it will be included in the PUBACK.
+    ///
+    /// Use instead of [`OrderedAcker::ordered_ack_for_epoch`] when the broker does not require
+    /// publishes to be acked in the order they were received.
+    ///
+    /// # Errors
+    /// Returns an [`AckError`] if the publish cannot be acknowledged.
+    pub async fn immediate_ack_for_epoch(
+        &self,
+        publish: &Publish,
+        epoch: u64,
+        reason: Option<AckReason>,
+    ) -> Result<CompletionToken, AckError> {
+        // No need to ack QoS0 publishes. Skip.
+        if publish.pkid == 0 {
+            return Ok(CompletionToken(Box::new(async { Ok(()) })));
+        }
+
+        // Remove the PKID from the queue so that it can be re-used.
+        // NOTE: As with ordered acks, this is done before the ack itself so that the lock does
+        // not need to be held through an await operation.
+        {
+            let mut pkid_ack_queue = self.pkid_ack_queue.lock().unwrap();
+            // If the queue was reset, the PKID belongs to a lost MQTT session, and may now
+            // be in use by a different publish. It must not be acked.
+            if pkid_ack_queue.epoch() != epoch {
+                return Err(AckError::new(AckErrorKind::SessionLost));
+            }
+            if !pkid_ack_queue.remove(publish.pkid) {
+                // The PKID was already removed by another ack invocation
+                return Err(AckError::new(AckErrorKind::AlreadyAcked));
+            }
+        }
+
+        let ct = self.send_ack(publish, reason).await?;
+        // Wake any ordered acks that may have been waiting on this PKID
+        self.notify.notify_waiters();
+        Ok(ct)
+    }
+
+    /// Helper for sending the ack for a publish, with the reason, if provided
+    async fn send_ack(
+        &self,
+        publish: &Publish,
+        reason: Option<AckReason>,
+    ) -> Result<CompletionToken, AckError> {
+        match reason {
+            Some((reason_code, reason_string)) => {
+                self.acker
+                    .ack_with_reason(publish, reason_code, reason_string)
+                    .await
+            }
+            None => self.acker.ack(publish).await,
+        }
+    }
+
+    /// Wake all pending acks so they can check their turn.
+    /// Use after resetting the [`PkidAckQueue`].
+    pub fn wake_pending(&self) {
+        self.notify.notify_waiters();
+    }
 }
 
 /// Queue of PKIDs in the order they should be acked.
@@ -137,6 +225,8 @@ pub struct PkidAckQueue {
     queue: VecDeque<u16>,
     /// The set of PKIDs that are currently in the queue
     tracked_pkids: HashSet<u16>,
+    /// The number of times the queue has been reset
+    epoch: u64,
 }
 
 impl PkidAckQueue {
@@ -171,11 +261,35 @@ impl PkidAckQueue {
         }
     }
 
+    /// Remove a PKID from anywhere in the queue.
+    ///
+    /// Returns whether the PKID was in the queue
+    pub fn remove(&mut self, pkid: u16) -> bool {
+        if !self.tracked_pkids.remove(&pkid) {
+            return false;
+        }
+        self.queue.retain(|p| *p != pkid);
+        true
+    }
+
     /// Returns whether the PKID is already in the queue
     pub fn contains(&mut self, pkid: u16) -> bool {
         self.tracked_pkids.contains(&pkid)
     }
 
+    /// Remove all PKIDs from the queue, beginning a new epoch.
+    /// Use when the MQTT session the PKIDs belong to has been lost.
+    pub fn reset(&mut self) {
+        self.queue.clear();
+        self.tracked_pkids.clear();
+        self.epoch += 1;
+    }
+
+    /// Return the number of times the queue has been reset
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
     // Number of PKIDs in the queue
     #[allow(dead_code)]
     pub fn len(&self) -> usize {
@@ -220,6 +334,23 @@ mod tests {
         assert_eq!(pkid_queue.pop_next_ack_pkid(), None);
     }
 
+    #[test]
+    fn pkid_queue_remove() {
+        let mut pkid_queue = PkidAckQueue::default();
+        pkid_queue.insert(1).unwrap();
+        pkid_queue.insert(2).unwrap();
+        pkid_queue.insert(3).unwrap();
+        assert!(pkid_queue.remove(2));
+        assert!(!pkid_queue.remove(2));
+        assert!(!pkid_queue.contains(2));
+        assert_eq!(pkid_queue.len(), 2);
+        assert_eq!(pkid_queue.pop_next_ack_pkid(), Some(1));
+        assert_eq!(pkid_queue.pop_next_ack_pkid(), Some(3));
+        // A removed PKID can be re-inserted
+        pkid_queue.insert(2).unwrap();
+        assert_eq!(pkid_queue.check_next_ack_pkid(), Some(&2));
+    }
+
     #[test]
     fn pkid_queue_duplicate() {
         let mut pkid_queue = PkidAckQueue::default();
@@ -290,6 +421,59 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn ack_after_reset() {
+        let pkid_queue = Arc::new(Mutex::new(PkidAckQueue::default()));
+        pkid_queue.lock().unwrap().insert(1).unwrap();
+        pkid_queue.lock().unwrap().insert(2).unwrap();
+
+        let mock_client = MockClient::new();
+        let mock_client_controller = mock_client.mock_controller();
+        let acker = OrderedAcker::new(mock_client, pkid_queue.clone());
+
+        let topic_name = TopicName::from_str("test").unwrap();
+        let publish1 = create_publish_qos(&topic_name, "publish 1", 1, QoS::AtLeastOnce);
+        let publish2 = create_publish_qos(&topic_name, "publish 2", 2, QoS::AtLeastOnce);
+        let old_epoch = pkid_queue.lock().unwrap().epoch();
+
+        // Publish 2 waits for its turn to ack
+        let pending_ack = tokio::task::spawn({
+            let acker = acker.clone();
+            let publish2 = publish2.clone();
+            async move {
+                acker
+                    .ordered_ack_for_epoch(&publish2, old_epoch, None)
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!pending_ack.is_finished());
+
+        // Resetting the queue (e.g. on session loss) causes pending acks to fail without acking
+        pkid_queue.lock().unwrap().reset();
+        acker.wake_pending();
+        assert_eq!(
+            pending_ack.await.unwrap().unwrap_err().kind(),
+            &AckErrorKind::SessionLost
+        );
+
+        // PKID 1 is re-used in the new epoch, but the ack from the old epoch is not sent
+        pkid_queue.lock().unwrap().insert(1).unwrap();
+        assert_eq!(
+            acker
+                .ordered_ack_for_epoch(&publish1, old_epoch, None)
+                .await
+                .unwrap_err()
+                .kind(),
+            &AckErrorKind::SessionLost
+        );
+        assert_eq!(mock_client_controller.ack_count(), 0);
+
+        // Acks in the new epoch are sent
+        acker.ordered_ack(&publish1).await.unwrap();
+        assert_eq!(mock_client_controller.ack_count(), 1);
+    }
+
     #[test_case(QoS::AtLeastOnce; "QoS 1")]
     #[test_case(QoS::ExactlyOnce; "QoS 2")]
     #[tokio::test]
@@ -364,6 +548,78 @@ mod tests {
         }
     }
 
+    #[test_case(QoS::AtLeastOnce; "QoS 1")]
+    #[test_case(QoS::ExactlyOnce; "QoS 2")]
+    #[tokio::test]
+    async fn ack_immediate_invokes(qos: QoS) {
+        let pkid_queue = Arc::new(Mutex::new(PkidAckQueue::default()));
+        pkid_queue.lock().unwrap().insert(1).unwrap();
+        pkid_queue.lock().unwrap().insert(2).unwrap();
+        pkid_queue.lock().unwrap().insert(3).unwrap();
+        let epoch = pkid_queue.lock().unwrap().epoch();
+
+        let mock_client = MockClient::new();
+        let mock_client_controller = mock_client.mock_controller();
+        let acker = OrderedAcker::new(mock_client, pkid_queue.clone());
+
+        let topic_name = TopicName::from_str("test").unwrap();
+        let publish1 = create_publish_qos(&topic_name, "publish 1", 1, qos);
+        let publish3 = create_publish_qos(&topic_name, "publish 3", 3, qos);
+
+        // Acking the publishes in a different order than the PKID queue does not wait for the
+        // earlier publishes to be acked
+        acker
+            .immediate_ack_for_epoch(&publish3, epoch, None)
+            .await
+            .unwrap();
+        assert_eq!(mock_client_controller.ack_count(), 1);
+        acker
+            .immediate_ack_for_epoch(&publish1, epoch, None)
+            .await
+            .unwrap();
+        assert_eq!(mock_client_controller.ack_count(), 2);
+        assert_eq!(pkid_queue.lock().unwrap().len(), 1);
+
+        // Validate order
+        let calls = mock_client_controller.call_sequence();
+        assert_eq!(calls.len(), 2);
+        match &calls[0] {
+            MockClientCall::Ack(call) => {
+                assert_eq!(call.publish.pkid, 3);
+            }
+            _ => panic!("Unexpected call"),
+        }
+        match &calls[1] {
+            MockClientCall::Ack(call) => {
+                assert_eq!(call.publish.pkid, 1);
+            }
+            _ => panic!("Unexpected call"),
+        }
+
+        // Acking the same publish again fails
+        assert_eq!(
+            acker
+                .immediate_ack_for_epoch(&publish3, epoch, None)
+                .await
+                .unwrap_err()
+                .kind(),
+            &AckErrorKind::AlreadyAcked
+        );
+
+        // Acks from before a reset are not sent
+        pkid_queue.lock().unwrap().reset();
+        pkid_queue.lock().unwrap().insert(1).unwrap();
+        assert_eq!(
+            acker
+                .immediate_ack_for_epoch(&publish1, epoch, None)
+                .await
+                .unwrap_err()
+                .kind(),
+            &AckErrorKind::SessionLost
+        );
+        assert_eq!(mock_client_controller.ack_count(), 2);
+    }
+
     #[tokio::test]
     async fn qos0() {
         let mock_client = MockClient::new();
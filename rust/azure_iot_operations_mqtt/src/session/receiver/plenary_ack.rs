@@ -13,6 +13,7 @@ use futures::future::{FutureExt, Shared};
 use tokio::sync::Notify;
 
 use crate::{
+    control_packet::PubAckReasonCode,
     error::{AckError, CompletionError},
     interface::CompletionToken,
 };
@@ -34,6 +35,9 @@ type PlenaryAckOpFuture =
 type CompletionTokenFuture =
     Shared<Pin<Box<dyn Future<Output = Result<(), CompletionError>> + Send>>>;
 
+/// Reason code and (optional) reason string to send with an ack
+pub type AckReason = (PubAckReasonCode, Option<String>);
+
 /// State of a plenary acking operation
 #[derive(Default, Debug)]
 struct PlenaryState {
@@ -43,6 +47,8 @@ struct PlenaryState {
     signals: usize,
     /// Indicates plenary has commenced (i.e. the number of members cannot change)
     commenced: bool,
+    /// Reason to send with the ack, if any member provided one
+    reason: Option<AckReason>,
     /// Notify to trigger when all signals have been reported and the plenary has commenced
     approved: Arc<Notify>,
 }
@@ -69,6 +75,23 @@ impl PlenaryState {
         }
     }
 
+    /// Set the reason to send with the ack.
+    /// The first failure reason provided takes precedence, followed by the first other reason.
+    fn set_reason(&mut self, reason: AckReason) {
+        let replace = match &self.reason {
+            None => true,
+            Some((reason_code, _)) => !reason_code.is_failure() && reason.0.is_failure(),
+        };
+        if replace {
+            self.reason = Some(reason);
+        }
+    }
+
+    /// Take the reason to send with the ack, if any
+    fn take_reason(&mut self) -> Option<AckReason> {
+        self.reason.take()
+    }
+
     /// Indicate the plenary has commenced
     fn commence(&mut self) {
         self.commenced = true;
@@ -93,9 +116,29 @@ pub struct PlenaryAckMember {
 }
 
 impl PlenaryAckMember {
-    pub async fn ack(mut self) -> Result<CompletionToken, AckError> {
+    pub async fn ack(self) -> Result<CompletionToken, AckError> {
+        self.signal_and_wait(None).await
+    }
+
+    /// Ack with the provided reason. Whether this reason is the one provided to the ack operation
+    /// depends on the reasons provided by the other members (see [`PlenaryAck`]).
+    pub async fn ack_with_reason(self, reason: AckReason) -> Result<CompletionToken, AckError> {
+        self.signal_and_wait(Some(reason)).await
+    }
+
+    /// Signal the member has arrived (with a reason, if any), and wait for the ack to be completed
+    async fn signal_and_wait(
+        mut self,
+        reason: Option<AckReason>,
+    ) -> Result<CompletionToken, AckError> {
         // Signal the member has arrived
-        self.state.lock().unwrap().signal();
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(reason) = reason {
+                state.set_reason(reason);
+            }
+            state.signal();
+        }
         self.signaled = true;
         // Wait for the ack to be completed
         // NOTE: Cloning the future here isn't ideal, but is necessary under the current
@@ -136,6 +179,9 @@ impl Drop for PlenaryAckMember {
 /// Represents an acknowledgement operation that will only trigger once all the
 /// [`PlenaryAckMember`]s that are created by it have acked.
 /// The result of that operation will be reported to the individual [`PlenaryAckMember`]s.
+///
+/// If any [`PlenaryAckMember`]s ack with an [`AckReason`], the operation is provided the first
+/// failure reason, or if there is none, the first other reason.
 pub struct PlenaryAck {
     /// Shared state among members of the plenary
     state: Arc<Mutex<PlenaryState>>,
@@ -144,25 +190,32 @@ pub struct PlenaryAck {
 }
 
 impl PlenaryAck {
-    /// Create a new [`PlenaryAck`] with the given ack future that will be triggered once all members ack
-    pub fn new(
-        ack_future: impl Future<Output = Result<CompletionToken, AckError>> + Send + 'static,
-    ) -> Self {
-        let state = PlenaryState::default();
-        let approved = state.get_approved_notify();
-
-        let ack_op_f = async move {
-            // Wait for the ack to be approved by all members signal
-            approved.notified().await;
-            // Trigger the ack operation
-            let ct = ack_future.await?;
-            // Return the completion token as a boxed shared future so that it can be propagated to
-            // the multiple members that may be waiting for it.
-            Ok(ct.boxed().shared())
+    /// Create a new [`PlenaryAck`] with the given function creating the ack future that will be
+    /// triggered once all members ack. The function is provided the [`AckReason`] for the ack, if
+    /// any.
+    pub fn new<F>(ack_future_fn: impl FnOnce(Option<AckReason>) -> F + Send + 'static) -> Self
+    where
+        F: Future<Output = Result<CompletionToken, AckError>> + Send + 'static,
+    {
+        let state = Arc::new(Mutex::new(PlenaryState::default()));
+        let approved = state.lock().unwrap().get_approved_notify();
+
+        let ack_op_f = {
+            let state = state.clone();
+            async move {
+                // Wait for the ack to be approved by all members signal
+                approved.notified().await;
+                // Trigger the ack operation
+                let reason = state.lock().unwrap().take_reason();
+                let ct = ack_future_fn(reason).await?;
+                // Return the completion token as a boxed shared future so that it can be propagated to
+                // the multiple members that may be waiting for it.
+                Ok(ct.boxed().shared())
+            }
         };
 
         Self {
-            state: Arc::new(Mutex::new(state)),
+            state,
             plenary_op_f: ack_op_f.boxed().shared(),
         }
     }
@@ -271,7 +324,7 @@ mod test {
             }
         };
 
-        let plenary_ack = PlenaryAck::new(mock_ack_f);
+        let plenary_ack = PlenaryAck::new(|_| mock_ack_f);
 
         // Commence without creating any members
         plenary_ack.commence();
@@ -294,7 +347,7 @@ mod test {
             }
         };
 
-        let plenary_ack = PlenaryAck::new(mock_ack_f);
+        let plenary_ack = PlenaryAck::new(|_| mock_ack_f);
         let member1 = plenary_ack.create_member();
 
         // Commence, and then ack w/ plenary member
@@ -324,7 +377,7 @@ mod test {
             }
         };
 
-        let plenary_ack = PlenaryAck::new(mock_ack_f);
+        let plenary_ack = PlenaryAck::new(|_| mock_ack_f);
         let member1 = plenary_ack.create_member();
 
         let m1_ack = tokio::task::spawn(member1.ack());
@@ -358,7 +411,7 @@ mod test {
             }
         };
 
-        let plenary_ack = PlenaryAck::new(mock_ack_f);
+        let plenary_ack = PlenaryAck::new(|_| mock_ack_f);
         let member1 = plenary_ack.create_member();
         let member2 = plenary_ack.create_member();
         let member3 = plenary_ack.create_member();
@@ -409,7 +462,7 @@ mod test {
             }
         };
 
-        let plenary_ack = PlenaryAck::new(mock_ack_f);
+        let plenary_ack = PlenaryAck::new(|_| mock_ack_f);
         let member1 = plenary_ack.create_member();
         let member2 = plenary_ack.create_member();
         let member3 = plenary_ack.create_member();
@@ -458,7 +511,7 @@ mod test {
             }
         };
 
-        let plenary_ack = PlenaryAck::new(mock_ack_f);
+        let plenary_ack = PlenaryAck::new(|_| mock_ack_f);
         let member1 = plenary_ack.create_member();
         let member2 = plenary_ack.create_member();
         let member3 = plenary_ack.create_member();
@@ -510,7 +563,7 @@ mod test {
             }
         };
 
-        let plenary_ack = PlenaryAck::new(mock_ack_f);
+        let plenary_ack = PlenaryAck::new(|_| mock_ack_f);
         let member1 = plenary_ack.create_member();
 
         // Commence, and then drop the member before it acks
@@ -533,7 +586,7 @@ mod test {
             }
         };
 
-        let plenary_ack = PlenaryAck::new(mock_ack_f);
+        let plenary_ack = PlenaryAck::new(|_| mock_ack_f);
         let member1 = plenary_ack.create_member();
 
         // Commence, and then ack w/ plenary member
@@ -563,7 +616,7 @@ mod test {
             }
         };
 
-        let plenary_ack = PlenaryAck::new(mock_ack_f);
+        let plenary_ack = PlenaryAck::new(|_| mock_ack_f);
         let member1 = plenary_ack.create_member();
 
         // Drop the plenary ack
@@ -588,7 +641,7 @@ mod test {
             }
         };
 
-        let plenary_ack = PlenaryAck::new(mock_ack_f);
+        let plenary_ack = PlenaryAck::new(|_| mock_ack_f);
 
         // Drop the plenary ack
         drop(plenary_ack);
@@ -596,4 +649,72 @@ mod test {
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
         assert!(!*mock_ack_triggered.lock().unwrap());
     }
+
+    #[tokio::test]
+    async fn ack_reason_precedence() {
+        let mock_ack_reason = Arc::new(Mutex::new(None));
+
+        let mock_ack_f_fn = {
+            let mock_ack_reason = mock_ack_reason.clone();
+            move |reason: Option<AckReason>| async move {
+                *mock_ack_reason.lock().unwrap() = Some(reason);
+                Ok(create_completion_token())
+            }
+        };
+
+        let plenary_ack = PlenaryAck::new(mock_ack_f_fn);
+        let member1 = plenary_ack.create_member();
+        let member2 = plenary_ack.create_member();
+        let member3 = plenary_ack.create_member();
+        let member4 = plenary_ack.create_member();
+        plenary_ack.commence();
+
+        // The failure reason takes precedence over a non-failure reason provided before it, but
+        // not over a failure reason provided before it
+        let m1_ack = tokio::task::spawn(member1.ack());
+        let m2_ack = tokio::task::spawn(
+            member2.ack_with_reason((PubAckReasonCode::NoMatchingSubscribers, None)),
+        );
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        let m3_ack = tokio::task::spawn(member3.ack_with_reason((
+            PubAckReasonCode::QuotaExceeded,
+            Some("quota exceeded".to_string()),
+        )));
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        member4
+            .ack_with_reason((PubAckReasonCode::UnspecifiedError, None))
+            .await
+            .unwrap();
+        m1_ack.await.unwrap().unwrap();
+        m2_ack.await.unwrap().unwrap();
+        m3_ack.await.unwrap().unwrap();
+
+        assert_eq!(
+            *mock_ack_reason.lock().unwrap(),
+            Some(Some((
+                PubAckReasonCode::QuotaExceeded,
+                Some("quota exceeded".to_string())
+            )))
+        );
+    }
+
+    #[tokio::test]
+    async fn ack_no_reason() {
+        let mock_ack_reason = Arc::new(Mutex::new(None));
+
+        let mock_ack_f_fn = {
+            let mock_ack_reason = mock_ack_reason.clone();
+            move |reason: Option<AckReason>| async move {
+                *mock_ack_reason.lock().unwrap() = Some(reason);
+                Ok(create_completion_token())
+            }
+        };
+
+        let plenary_ack = PlenaryAck::new(mock_ack_f_fn);
+        let member1 = plenary_ack.create_member();
+        plenary_ack.commence();
+        member1.ack().await.unwrap();
+
+        assert_eq!(*mock_ack_reason.lock().unwrap(), Some(None));
+    }
 }
@@ -0,0 +1,364 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Channel for delivering dispatched publishes to a receiver, with optional bounded capacity.
+
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+use tokio::sync::mpsc::error::TryRecvError;
+
+use crate::control_packet::Publish;
+use crate::session::receiver::AckToken;
+
+/// Item delivered over a publish channel
+pub type PublishItem = (Publish, Option<AckToken>);
+
+/// Policy for handling an incoming publish dispatched to a bounded receiver that is full.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OverflowPolicy {
+    /// Wait for the receiver to have capacity before dispatching the publish.
+    ///
+    /// Note that this halts the processing of ALL incoming MQTT traffic on the session until the
+    /// receiver has capacity.
+    Block,
+    /// Drop the incoming publish.
+    DropNewest,
+    /// Drop the oldest publish held by the receiver to make room for the incoming publish.
+    DropOldest,
+    /// Drop the incoming publish and close the receiver.
+    /// Publishes already held by the receiver can still be received.
+    Close,
+}
+
+/// Error returned when a publish could not be sent on a channel
+pub enum TrySendError {
+    /// The receiver is closed
+    Closed(PublishItem),
+    /// The receiver is full, and the overflow policy is [`OverflowPolicy::Block`]
+    Full(PublishItem),
+}
+
+/// State of a channel shared between sender(s) and the receiver
+struct ChannelState {
+    /// Publishes not yet received
+    queue: VecDeque<PublishItem>,
+    /// Indicates the receiver has been closed, and no more publishes can be sent
+    closed: bool,
+    /// Number of senders
+    num_tx: usize,
+    /// Number of publishes dropped due to overflow
+    dropped: u64,
+}
+
+/// Publish channel shared between sender(s) and the receiver
+struct Channel {
+    /// Shared state
+    state: Mutex<ChannelState>,
+    /// Maximum number of publishes held, and the policy to apply when exceeded (if bounded)
+    bound: Option<(usize, OverflowPolicy)>,
+    /// Notifier for the receiver that a publish was sent or the senders were dropped
+    recv_notify: Notify,
+    /// Notifier for a blocked sender that capacity is available or the receiver was closed
+    send_notify: Notify,
+}
+
+/// Create a new publish channel with unbounded capacity
+pub fn unbounded() -> (PublishTx, PublishRx) {
+    new_channel(None)
+}
+
+/// Create a new publish channel that holds at most `capacity` publishes, applying the provided
+/// [`OverflowPolicy`] when full.
+pub fn bounded(capacity: NonZeroUsize, overflow_policy: OverflowPolicy) -> (PublishTx, PublishRx) {
+    new_channel(Some((capacity.get(), overflow_policy)))
+}
+
+fn new_channel(bound: Option<(usize, OverflowPolicy)>) -> (PublishTx, PublishRx) {
+    let channel = Arc::new(Channel {
+        state: Mutex::new(ChannelState {
+            queue: VecDeque::new(),
+            closed: false,
+            num_tx: 1,
+            dropped: 0,
+        }),
+        bound,
+        recv_notify: Notify::new(),
+        send_notify: Notify::new(),
+    });
+    (PublishTx(channel.clone()), PublishRx(channel))
+}
+
+/// Sending half of a publish channel
+pub struct PublishTx(Arc<Channel>);
+
+impl PublishTx {
+    /// Send a publish without waiting, applying the [`OverflowPolicy`] if the receiver is full.
+    ///
+    /// Returns `Ok(())` if the publish was either sent or handled by the overflow policy.
+    ///
+    /// # Errors
+    /// Returns a [`TrySendError`] containing the publish if the receiver is closed, or if the
+    /// receiver is full and the overflow policy is [`OverflowPolicy::Block`].
+    pub fn try_send(&self, item: PublishItem) -> Result<(), TrySendError> {
+        let mut state = self.0.state.lock().unwrap();
+        if state.closed {
+            return Err(TrySendError::Closed(item));
+        }
+        // NOTE: Publishes that are dropped are dropped after releasing the lock, as dropping an
+        // AckToken triggers an ack.
+        let overflow = match self.0.bound {
+            Some((capacity, policy)) if state.queue.len() >= capacity => Some(policy),
+            _ => None,
+        };
+        match overflow {
+            None => {
+                state.queue.push_back(item);
+                drop(state);
+                self.0.recv_notify.notify_one();
+            }
+            Some(OverflowPolicy::Block) => return Err(TrySendError::Full(item)),
+            Some(OverflowPolicy::DropNewest) => {
+                state.dropped += 1;
+                drop(state);
+                log::warn!(
+                    "Receiver full. Dropping incoming PUB with PKID {}",
+                    item.0.pkid
+                );
+                drop(item);
+            }
+            Some(OverflowPolicy::DropOldest) => {
+                state.dropped += 1;
+                let oldest = state.queue.pop_front();
+                state.queue.push_back(item);
+                drop(state);
+                self.0.recv_notify.notify_one();
+                if let Some(oldest) = oldest {
+                    log::warn!(
+                        "Receiver full. Dropping oldest PUB with PKID {}",
+                        oldest.0.pkid
+                    );
+                }
+            }
+            Some(OverflowPolicy::Close) => {
+                state.dropped += 1;
+                state.closed = true;
+                drop(state);
+                self.0.recv_notify.notify_one();
+                log::warn!(
+                    "Receiver full. Dropping incoming PUB with PKID {} and closing receiver",
+                    item.0.pkid
+                );
+                drop(item);
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a publish, waiting for the receiver to have capacity if it is full and the
+    /// [`OverflowPolicy`] is [`OverflowPolicy::Block`].
+    ///
+    /// # Errors
+    /// Returns the publish if the receiver is closed.
+    pub async fn send(&self, mut item: PublishItem) -> Result<(), PublishItem> {
+        loop {
+            // NOTE: Create the notified future before checking capacity, so that a notification
+            // between the check and the wait is not missed.
+            let notified = self.0.send_notify.notified();
+            match self.try_send(item) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Closed(i)) => return Err(i),
+                Err(TrySendError::Full(i)) => item = i,
+            }
+            notified.await;
+        }
+    }
+
+    /// Returns true if the receiver has been closed
+    pub fn is_closed(&self) -> bool {
+        self.0.state.lock().unwrap().closed
+    }
+}
+
+impl Clone for PublishTx {
+    fn clone(&self) -> Self {
+        self.0.state.lock().unwrap().num_tx += 1;
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for PublishTx {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.num_tx -= 1;
+        if state.num_tx == 0 {
+            drop(state);
+            self.0.recv_notify.notify_one();
+        }
+    }
+}
+
+/// Receiving half of a publish channel
+pub struct PublishRx(Arc<Channel>);
+
+impl PublishRx {
+    /// Receive the next publish.
+    ///
+    /// Returns `None` once the channel is closed (or all senders are dropped) and all
+    /// previously sent publishes have been received.
+    pub async fn recv(&mut self) -> Option<PublishItem> {
+        loop {
+            match self.try_recv() {
+                Ok(item) => return Some(item),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => self.0.recv_notify.notified().await,
+            }
+        }
+    }
+
+    /// Receive the next publish if one is available, without waiting.
+    ///
+    /// # Errors
+    /// Returns [`TryRecvError::Empty`] if there is no publish available, or
+    /// [`TryRecvError::Disconnected`] if the channel is closed and there will be no more
+    /// publishes.
+    pub fn try_recv(&mut self) -> Result<PublishItem, TryRecvError> {
+        let mut state = self.0.state.lock().unwrap();
+        if let Some(item) = state.queue.pop_front() {
+            drop(state);
+            self.0.send_notify.notify_one();
+            Ok(item)
+        } else if state.closed || state.num_tx == 0 {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Close the receiver, preventing further publishes from being sent.
+    /// Publishes already sent can still be received.
+    pub fn close(&mut self) {
+        self.0.state.lock().unwrap().closed = true;
+        self.0.send_notify.notify_one();
+    }
+
+    /// Return the number of publishes dropped due to receiver overflow
+    pub fn dropped_count(&self) -> u64 {
+        self.0.state.lock().unwrap().dropped
+    }
+}
+
+impl Drop for PublishRx {
+    fn drop(&mut self) {
+        let queue = {
+            let mut state = self.0.state.lock().unwrap();
+            state.closed = true;
+            std::mem::take(&mut state.queue)
+        };
+        self.0.send_notify.notify_one();
+        // Drop any publishes not yet received after releasing the lock
+        drop(queue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control_packet::QoS;
+
+    fn create_item(pkid: u16) -> PublishItem {
+        let mut publish = Publish::new("test/topic", QoS::AtMostOnce, "payload", None);
+        publish.pkid = pkid;
+        (publish, None)
+    }
+
+    #[tokio::test]
+    async fn unbounded_send_recv() {
+        let (tx, mut rx) = unbounded();
+        for pkid in 1..=100 {
+            assert!(tx.try_send(create_item(pkid)).is_ok());
+        }
+        for pkid in 1..=100 {
+            assert_eq!(rx.recv().await.unwrap().0.pkid, pkid);
+        }
+        assert_eq!(rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        drop(tx);
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn overflow_drop_newest() {
+        let (tx, mut rx) = bounded(NonZeroUsize::new(2).unwrap(), OverflowPolicy::DropNewest);
+        for pkid in 1..=4 {
+            assert!(tx.try_send(create_item(pkid)).is_ok());
+        }
+        assert_eq!(rx.dropped_count(), 2);
+        assert_eq!(rx.try_recv().unwrap().0.pkid, 1);
+        assert_eq!(rx.try_recv().unwrap().0.pkid, 2);
+        assert_eq!(rx.try_recv().unwrap_err(), TryRecvError::Empty);
+    }
+
+    #[tokio::test]
+    async fn overflow_drop_oldest() {
+        let (tx, mut rx) = bounded(NonZeroUsize::new(2).unwrap(), OverflowPolicy::DropOldest);
+        for pkid in 1..=4 {
+            assert!(tx.try_send(create_item(pkid)).is_ok());
+        }
+        assert_eq!(rx.dropped_count(), 2);
+        assert_eq!(rx.try_recv().unwrap().0.pkid, 3);
+        assert_eq!(rx.try_recv().unwrap().0.pkid, 4);
+        assert_eq!(rx.try_recv().unwrap_err(), TryRecvError::Empty);
+    }
+
+    #[tokio::test]
+    async fn overflow_close() {
+        let (tx, mut rx) = bounded(NonZeroUsize::new(2).unwrap(), OverflowPolicy::Close);
+        for pkid in 1..=3 {
+            assert!(tx.try_send(create_item(pkid)).is_ok());
+        }
+        assert!(tx.is_closed());
+        assert!(matches!(
+            tx.try_send(create_item(4)),
+            Err(TrySendError::Closed(_))
+        ));
+        assert_eq!(rx.dropped_count(), 1);
+        // Publishes held before closure can still be received
+        assert_eq!(rx.recv().await.unwrap().0.pkid, 1);
+        assert_eq!(rx.recv().await.unwrap().0.pkid, 2);
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn overflow_block() {
+        let (tx, mut rx) = bounded(NonZeroUsize::new(1).unwrap(), OverflowPolicy::Block);
+        assert!(tx.try_send(create_item(1)).is_ok());
+        assert!(matches!(
+            tx.try_send(create_item(2)),
+            Err(TrySendError::Full(_))
+        ));
+
+        // Send waits until there is capacity
+        let send_f = tokio::task::spawn({
+            let tx = tx.clone();
+            async move { tx.send(create_item(2)).await.is_ok() }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!send_f.is_finished());
+        assert_eq!(rx.recv().await.unwrap().0.pkid, 1);
+        assert!(send_f.await.unwrap());
+        assert_eq!(rx.recv().await.unwrap().0.pkid, 2);
+        assert_eq!(rx.dropped_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn blocked_send_fails_on_close() {
+        let (tx, mut rx) = bounded(NonZeroUsize::new(1).unwrap(), OverflowPolicy::Block);
+        assert!(tx.try_send(create_item(1)).is_ok());
+        let send_f = tokio::task::spawn(async move { tx.send(create_item(2)).await.is_err() });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        rx.close();
+        assert!(send_f.await.unwrap());
+    }
+}
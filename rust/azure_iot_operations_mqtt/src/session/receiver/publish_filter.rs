@@ -0,0 +1,190 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Filters for routing dispatched publishes by their content, in addition to their topic.
+
+use std::fmt;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::control_packet::Publish;
+
+/// Filter over the content of an incoming [`Publish`], used to further narrow which publishes
+/// matching a topic filter are delivered to a receiver.
+///
+/// Filters can be combined with [`PublishFilter::All`], [`PublishFilter::Any`] and
+/// [`PublishFilter::Not`], or replaced entirely with an arbitrary predicate via
+/// [`PublishFilter::predicate`].
+#[derive(Clone)]
+pub enum PublishFilter {
+    /// Matches publishes with the provided content type
+    ContentType(String),
+    /// Matches publishes with a user property of the provided name and value
+    UserProperty(String, String),
+    /// Matches publishes with a user property of the provided name, regardless of value
+    HasUserProperty(String),
+    /// Matches publishes with the provided correlation data
+    CorrelationData(Bytes),
+    /// Matches publishes that match all of the provided filters
+    All(Vec<PublishFilter>),
+    /// Matches publishes that match any of the provided filters
+    Any(Vec<PublishFilter>),
+    /// Matches publishes that do not match the provided filter
+    Not(Box<PublishFilter>),
+    /// Matches publishes for which the predicate returns true
+    Predicate(Arc<dyn Fn(&Publish) -> bool + Send + Sync>),
+}
+
+impl PublishFilter {
+    /// Create a [`PublishFilter`] that matches publishes for which the provided predicate
+    /// returns true.
+    ///
+    /// The predicate is evaluated while dispatching incoming publishes, and thus should be cheap
+    /// and must not block.
+    #[must_use]
+    pub fn predicate<F>(f: F) -> Self
+    where
+        F: Fn(&Publish) -> bool + Send + Sync + 'static,
+    {
+        Self::Predicate(Arc::new(f))
+    }
+
+    /// Returns true if the provided [`Publish`] matches this filter.
+    #[must_use]
+    pub fn matches(&self, publish: &Publish) -> bool {
+        let properties = publish.properties.as_ref();
+        match self {
+            Self::ContentType(content_type) => properties
+                .and_then(|p| p.content_type.as_ref())
+                .is_some_and(|ct| ct == content_type),
+            Self::UserProperty(name, value) => properties.is_some_and(|p| {
+                p.user_properties
+                    .iter()
+                    .any(|(n, v)| n == name && v == value)
+            }),
+            Self::HasUserProperty(name) => {
+                properties.is_some_and(|p| p.user_properties.iter().any(|(n, _)| n == name))
+            }
+            Self::CorrelationData(correlation_data) => properties
+                .and_then(|p| p.correlation_data.as_ref())
+                .is_some_and(|cd| cd == correlation_data),
+            Self::All(filters) => filters.iter().all(|f| f.matches(publish)),
+            Self::Any(filters) => filters.iter().any(|f| f.matches(publish)),
+            Self::Not(filter) => !filter.matches(publish),
+            Self::Predicate(f) => f(publish),
+        }
+    }
+}
+
+impl fmt::Debug for PublishFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ContentType(content_type) => {
+                f.debug_tuple("ContentType").field(content_type).finish()
+            }
+            Self::UserProperty(name, value) => f
+                .debug_tuple("UserProperty")
+                .field(name)
+                .field(value)
+                .finish(),
+            Self::HasUserProperty(name) => f.debug_tuple("HasUserProperty").field(name).finish(),
+            Self::CorrelationData(correlation_data) => f
+                .debug_tuple("CorrelationData")
+                .field(correlation_data)
+                .finish(),
+            Self::All(filters) => f.debug_tuple("All").field(filters).finish(),
+            Self::Any(filters) => f.debug_tuple("Any").field(filters).finish(),
+            Self::Not(filter) => f.debug_tuple("Not").field(filter).finish(),
+            Self::Predicate(_) => f.write_str("Predicate(..)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control_packet::{PublishProperties, QoS};
+
+    fn create_publish(properties: Option<PublishProperties>) -> Publish {
+        Publish::new(
+            "sport/tennis/player1",
+            QoS::AtLeastOnce,
+            "payload",
+            properties,
+        )
+    }
+
+    fn create_properties() -> PublishProperties {
+        PublishProperties {
+            content_type: Some("application/json".to_string()),
+            correlation_data: Some(Bytes::from_static(b"correlation")),
+            user_properties: vec![
+                ("__srcId".to_string(), "device1".to_string()),
+                ("tenant".to_string(), "contoso".to_string()),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn content_type() {
+        let publish = create_publish(Some(create_properties()));
+        assert!(PublishFilter::ContentType("application/json".to_string()).matches(&publish));
+        assert!(!PublishFilter::ContentType("text/plain".to_string()).matches(&publish));
+        assert!(
+            !PublishFilter::ContentType("application/json".to_string())
+                .matches(&create_publish(None))
+        );
+    }
+
+    #[test]
+    fn user_property() {
+        let publish = create_publish(Some(create_properties()));
+        assert!(
+            PublishFilter::UserProperty("tenant".to_string(), "contoso".to_string())
+                .matches(&publish)
+        );
+        assert!(
+            !PublishFilter::UserProperty("tenant".to_string(), "fabrikam".to_string())
+                .matches(&publish)
+        );
+        assert!(PublishFilter::HasUserProperty("__srcId".to_string()).matches(&publish));
+        assert!(!PublishFilter::HasUserProperty("__ts".to_string()).matches(&publish));
+        assert!(
+            !PublishFilter::HasUserProperty("__srcId".to_string()).matches(&create_publish(None))
+        );
+    }
+
+    #[test]
+    fn correlation_data() {
+        let publish = create_publish(Some(create_properties()));
+        assert!(
+            PublishFilter::CorrelationData(Bytes::from_static(b"correlation")).matches(&publish)
+        );
+        assert!(!PublishFilter::CorrelationData(Bytes::from_static(b"other")).matches(&publish));
+    }
+
+    #[test]
+    fn combinators() {
+        let publish = create_publish(Some(create_properties()));
+        let json = PublishFilter::ContentType("application/json".to_string());
+        let text = PublishFilter::ContentType("text/plain".to_string());
+
+        assert!(PublishFilter::All(vec![json.clone()]).matches(&publish));
+        assert!(!PublishFilter::All(vec![json.clone(), text.clone()]).matches(&publish));
+        assert!(PublishFilter::All(vec![]).matches(&publish));
+        assert!(PublishFilter::Any(vec![json.clone(), text.clone()]).matches(&publish));
+        assert!(!PublishFilter::Any(vec![text.clone()]).matches(&publish));
+        assert!(!PublishFilter::Any(vec![]).matches(&publish));
+        assert!(PublishFilter::Not(Box::new(text)).matches(&publish));
+        assert!(!PublishFilter::Not(Box::new(json)).matches(&publish));
+    }
+
+    #[test]
+    fn predicate() {
+        let publish = create_publish(None);
+        assert!(PublishFilter::predicate(|p| p.payload.as_ref() == b"payload").matches(&publish));
+        assert!(!PublishFilter::predicate(|p| p.retain).matches(&publish));
+    }
+}
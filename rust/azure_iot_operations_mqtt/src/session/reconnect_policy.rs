@@ -2,17 +2,35 @@
 // Licensed under the MIT License.
 
 //! Reconnect policies for a [`Session`](crate::session::Session).
+//!
+//! A [`Session`](crate::session::Session) never reconnects after errors that
+//! [`classify_connection_error`] classifies as [`ErrorClassification::Fatal`], regardless of the
+//! reconnect policy. For all other errors, the reconnect policy is consulted.
+//!
+//! Policies determining the delay between reconnect attempts ([`ExponentialBackoffWithJitter`],
+//! [`FixedInterval`], [`DecorrelatedJitter`] and [`CappedLinearBackoff`]) do not consider the
+//! error that caused the connection loss. They can be wrapped in an [`ErrorClassifyingPolicy`] to
+//! halt reconnection on additional errors, and in a [`MaxElapsedTime`] to halt reconnection after
+//! a total amount of time.
 
+use std::sync::Mutex;
 use std::time::Duration;
 
+use tokio::time::Instant;
+
 use rand::Rng;
 
+use crate::control_packet::ConnectReturnCode;
 use crate::error::ConnectionError;
 
 /// Trait defining interface for reconnect policies.
 pub trait ReconnectPolicy {
     /// Get the next reconnect delay.
     /// Returns None if no reconnect should be attempted.
+    ///
+    /// `prev_attempts` is the number of reconnect attempts made since the last successful
+    /// connection, and `error` is the error that caused the connection loss or failed attempt,
+    /// including connections refused by the MQTT broker.
     fn next_reconnect_delay(&self, prev_attempts: u32, error: &ConnectionError)
     -> Option<Duration>;
 }
@@ -81,3 +99,405 @@ impl ReconnectPolicy for ExponentialBackoffWithJitter {
         }
     }
 }
+
+/// A reconnect policy that waits the same amount of time between each reconnect attempt.
+#[derive(Clone)]
+pub struct FixedInterval {
+    /// The time to wait between reconnect attempts.
+    pub interval: Duration,
+    /// The max number of reconnect attempts before giving up.
+    pub max_reconnect_attempts: Option<u32>,
+}
+
+impl Default for FixedInterval {
+    /// Indefinite reconnect, waiting 5 seconds between attempts.
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            max_reconnect_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy for FixedInterval {
+    fn next_reconnect_delay(
+        &self,
+        prev_attempts: u32,
+        _error: &ConnectionError,
+    ) -> Option<Duration> {
+        if !attempts_remaining(prev_attempts, self.max_reconnect_attempts) {
+            return None;
+        }
+        Some(self.interval)
+    }
+}
+
+/// A reconnect policy that increases the delay between reconnect attempts by a fixed amount,
+/// up to the specified max wait time.
+#[derive(Clone)]
+pub struct CappedLinearBackoff {
+    /// The time to wait before the first reconnect attempt.
+    pub initial_delay: Duration,
+    /// The amount the delay increases by for each subsequent reconnect attempt.
+    pub increment: Duration,
+    /// The longest possible time to wait between reconnect attempts.
+    pub max_wait: Duration,
+    /// The max number of reconnect attempts before giving up.
+    pub max_reconnect_attempts: Option<u32>,
+}
+
+impl Default for CappedLinearBackoff {
+    /// Indefinite reconnect, starting at 1 second and increasing by 1 second per attempt,
+    /// with a max wait time of 30 seconds.
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            increment: Duration::from_secs(1),
+            max_wait: Duration::from_secs(30),
+            max_reconnect_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy for CappedLinearBackoff {
+    fn next_reconnect_delay(
+        &self,
+        prev_attempts: u32,
+        _error: &ConnectionError,
+    ) -> Option<Duration> {
+        if !attempts_remaining(prev_attempts, self.max_reconnect_attempts) {
+            return None;
+        }
+        let delay = self
+            .increment
+            .saturating_mul(prev_attempts)
+            .saturating_add(self.initial_delay);
+        Some(delay.min(self.max_wait))
+    }
+}
+
+/// A reconnect policy using "decorrelated jitter" backoff, where each delay is chosen at random
+/// between the base delay and three times the previous delay, up to the specified max wait time.
+///
+/// This spreads out the reconnect attempts of many clients that lost connection at the same time
+/// more effectively than [`ExponentialBackoffWithJitter`].
+pub struct DecorrelatedJitter {
+    /// The shortest possible time to wait between reconnect attempts.
+    pub base_delay: Duration,
+    /// The longest possible time to wait between reconnect attempts.
+    pub max_wait: Duration,
+    /// The max number of reconnect attempts before giving up.
+    pub max_reconnect_attempts: Option<u32>,
+    /// The delay chosen for the previous reconnect attempt
+    prev_delay: Mutex<Duration>,
+}
+
+impl DecorrelatedJitter {
+    /// Create a new [`DecorrelatedJitter`] reconnect policy
+    #[must_use]
+    pub fn new(
+        base_delay: Duration,
+        max_wait: Duration,
+        max_reconnect_attempts: Option<u32>,
+    ) -> Self {
+        Self {
+            base_delay,
+            max_wait,
+            max_reconnect_attempts,
+            prev_delay: Mutex::new(base_delay),
+        }
+    }
+}
+
+impl Default for DecorrelatedJitter {
+    /// Indefinite reconnect, with a base delay of 128 milliseconds and a max wait time of 60 seconds.
+    fn default() -> Self {
+        Self::new(Duration::from_millis(128), Duration::from_secs(60), None)
+    }
+}
+
+impl ReconnectPolicy for DecorrelatedJitter {
+    fn next_reconnect_delay(
+        &self,
+        prev_attempts: u32,
+        _error: &ConnectionError,
+    ) -> Option<Duration> {
+        if !attempts_remaining(prev_attempts, self.max_reconnect_attempts) {
+            return None;
+        }
+        let mut prev_delay = self.prev_delay.lock().unwrap();
+        // Start over from the base delay after a successful connection
+        if prev_attempts == 0 {
+            *prev_delay = self.base_delay;
+        }
+        let upper = prev_delay.saturating_mul(3).max(self.base_delay);
+        let delay = rand::thread_rng()
+            .gen_range(self.base_delay..=upper)
+            .min(self.max_wait);
+        *prev_delay = delay;
+        Some(delay)
+    }
+}
+
+/// Classification of a [`ConnectionError`] by an [`ErrorClassifyingPolicy`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorClassification {
+    /// Reconnecting may succeed, so the wrapped policy should be consulted
+    Retryable,
+    /// Reconnecting will not succeed without intervention, so reconnection should halt
+    Fatal,
+}
+
+/// Classification of a [`ConnectionError`] applied by a [`Session`](crate::session::Session)
+/// before consulting its reconnect policy, and by default by an [`ErrorClassifyingPolicy`].
+///
+/// Connections refused by the MQTT broker due to the identity or configuration of the client
+/// (e.g. "not authorized" or "client identifier not valid") are [`ErrorClassification::Fatal`].
+/// All other errors, such as I/O errors, or connections refused because the MQTT broker is
+/// temporarily unavailable or busy, are [`ErrorClassification::Retryable`].
+#[must_use]
+pub fn classify_connection_error(error: &ConnectionError) -> ErrorClassification {
+    match error {
+        ConnectionError::ConnectionRefused(
+            ConnectReturnCode::RefusedProtocolVersion
+            | ConnectReturnCode::BadClientId
+            | ConnectReturnCode::MalformedPacket
+            | ConnectReturnCode::ProtocolError
+            | ConnectReturnCode::UnsupportedProtocolVersion
+            | ConnectReturnCode::ClientIdentifierNotValid
+            | ConnectReturnCode::BadUserNamePassword
+            | ConnectReturnCode::NotAuthorized
+            | ConnectReturnCode::Banned
+            | ConnectReturnCode::BadAuthenticationMethod
+            | ConnectReturnCode::TopicNameInvalid
+            | ConnectReturnCode::PacketTooLarge
+            | ConnectReturnCode::PayloadFormatInvalid
+            | ConnectReturnCode::RetainNotSupported
+            | ConnectReturnCode::QoSNotSupported,
+        ) => ErrorClassification::Fatal,
+        _ => ErrorClassification::Retryable,
+    }
+}
+
+/// A reconnect policy that classifies the [`ConnectionError`] causing each reconnect, halting
+/// reconnection on [`ErrorClassification::Fatal`] errors, and otherwise deferring to the
+/// wrapped policy.
+///
+/// Note that errors classified as [`ErrorClassification::Fatal`] by [`classify_connection_error`]
+/// always halt reconnection of a [`Session`](crate::session::Session), so a custom classifier can
+/// only halt reconnection on additional errors.
+pub struct ErrorClassifyingPolicy<P: ReconnectPolicy> {
+    /// The wrapped policy
+    inner: P,
+    /// Classifier for connection errors
+    classifier: Box<dyn Fn(&ConnectionError) -> ErrorClassification + Send + Sync>,
+}
+
+impl<P: ReconnectPolicy> ErrorClassifyingPolicy<P> {
+    /// Wrap the provided policy, classifying errors with [`classify_connection_error`]
+    #[must_use]
+    pub fn new(inner: P) -> Self {
+        Self::with_classifier(inner, classify_connection_error)
+    }
+
+    /// Wrap the provided policy, classifying errors with the provided classifier
+    #[must_use]
+    pub fn with_classifier(
+        inner: P,
+        classifier: impl Fn(&ConnectionError) -> ErrorClassification + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            inner,
+            classifier: Box::new(classifier),
+        }
+    }
+}
+
+impl<P: ReconnectPolicy> ReconnectPolicy for ErrorClassifyingPolicy<P> {
+    fn next_reconnect_delay(
+        &self,
+        prev_attempts: u32,
+        error: &ConnectionError,
+    ) -> Option<Duration> {
+        match (self.classifier)(error) {
+            ErrorClassification::Retryable => self.inner.next_reconnect_delay(prev_attempts, error),
+            ErrorClassification::Fatal => {
+                log::warn!("Connection error is not retryable: {error}");
+                None
+            }
+        }
+    }
+}
+
+/// A reconnect policy that halts reconnection once the specified amount of time has elapsed since
+/// the connection was lost, and otherwise defers to the wrapped policy.
+pub struct MaxElapsedTime<P: ReconnectPolicy> {
+    /// The wrapped policy
+    inner: P,
+    /// The longest possible time to spend reconnecting after a connection loss
+    max_elapsed: Duration,
+    /// The time the current series of reconnect attempts began
+    start: Mutex<Option<Instant>>,
+}
+
+impl<P: ReconnectPolicy> MaxElapsedTime<P> {
+    /// Wrap the provided policy, halting reconnection after `max_elapsed` has passed since the
+    /// connection was lost.
+    #[must_use]
+    pub fn new(inner: P, max_elapsed: Duration) -> Self {
+        Self {
+            inner,
+            max_elapsed,
+            start: Mutex::new(None),
+        }
+    }
+}
+
+impl<P: ReconnectPolicy> ReconnectPolicy for MaxElapsedTime<P> {
+    fn next_reconnect_delay(
+        &self,
+        prev_attempts: u32,
+        error: &ConnectionError,
+    ) -> Option<Duration> {
+        let now = Instant::now();
+        let mut start = self.start.lock().unwrap();
+        // A new series of reconnect attempts begins after a successful connection
+        if prev_attempts == 0 || start.is_none() {
+            *start = Some(now);
+        }
+        // NOTE: This cannot be None, as it was set above
+        let elapsed = now.duration_since(start.unwrap());
+        let delay = self.inner.next_reconnect_delay(prev_attempts, error)?;
+        // Do not begin a reconnect attempt after the max elapsed time
+        if elapsed.saturating_add(delay) > self.max_elapsed {
+            return None;
+        }
+        Some(delay)
+    }
+}
+
+/// Return true if there are reconnect attempts remaining
+fn attempts_remaining(prev_attempts: u32, max_reconnect_attempts: Option<u32>) -> bool {
+    if let Some(max_attempts) = max_reconnect_attempts {
+        prev_attempts < max_attempts
+    } else {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    fn io_error() -> ConnectionError {
+        ConnectionError::Io(io::Error::from(io::ErrorKind::ConnectionReset))
+    }
+
+    #[test]
+    fn fixed_interval() {
+        let policy = FixedInterval {
+            interval: Duration::from_secs(2),
+            max_reconnect_attempts: Some(2),
+        };
+        assert_eq!(
+            policy.next_reconnect_delay(0, &io_error()),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            policy.next_reconnect_delay(1, &io_error()),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(policy.next_reconnect_delay(2, &io_error()), None);
+    }
+
+    #[test]
+    fn capped_linear_backoff() {
+        let policy = CappedLinearBackoff {
+            initial_delay: Duration::from_secs(1),
+            increment: Duration::from_secs(2),
+            max_wait: Duration::from_secs(4),
+            max_reconnect_attempts: None,
+        };
+        assert_eq!(
+            policy.next_reconnect_delay(0, &io_error()),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            policy.next_reconnect_delay(1, &io_error()),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            policy.next_reconnect_delay(2, &io_error()),
+            Some(Duration::from_secs(4))
+        );
+        assert_eq!(
+            policy.next_reconnect_delay(u32::MAX - 1, &io_error()),
+            Some(Duration::from_secs(4))
+        );
+    }
+
+    #[test]
+    fn decorrelated_jitter_bounds() {
+        let policy =
+            DecorrelatedJitter::new(Duration::from_millis(100), Duration::from_secs(1), Some(50));
+        for attempt in 0..50 {
+            let delay = policy.next_reconnect_delay(attempt, &io_error()).unwrap();
+            assert!(delay >= Duration::from_millis(100));
+            assert!(delay <= Duration::from_secs(1));
+        }
+        assert_eq!(policy.next_reconnect_delay(50, &io_error()), None);
+    }
+
+    #[test]
+    fn error_classifying_policy() {
+        let policy = ErrorClassifyingPolicy::new(FixedInterval::default());
+        assert!(policy.next_reconnect_delay(0, &io_error()).is_some());
+        assert!(
+            policy
+                .next_reconnect_delay(
+                    0,
+                    &ConnectionError::ConnectionRefused(ConnectReturnCode::ServerBusy)
+                )
+                .is_some()
+        );
+        assert!(
+            policy
+                .next_reconnect_delay(
+                    0,
+                    &ConnectionError::ConnectionRefused(ConnectReturnCode::NotAuthorized)
+                )
+                .is_none()
+        );
+
+        // Custom classifier
+        let policy = ErrorClassifyingPolicy::with_classifier(FixedInterval::default(), |e| {
+            if matches!(e, ConnectionError::Io(_)) {
+                ErrorClassification::Fatal
+            } else {
+                ErrorClassification::Retryable
+            }
+        });
+        assert!(policy.next_reconnect_delay(0, &io_error()).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_elapsed_time() {
+        let policy = MaxElapsedTime::new(
+            FixedInterval {
+                interval: Duration::from_millis(100),
+                max_reconnect_attempts: None,
+            },
+            Duration::from_millis(250),
+        );
+        assert!(policy.next_reconnect_delay(0, &io_error()).is_some());
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert!(policy.next_reconnect_delay(1, &io_error()).is_some());
+        tokio::time::advance(Duration::from_millis(100)).await;
+        // The next reconnect attempt would begin after the max elapsed time
+        assert!(policy.next_reconnect_delay(2, &io_error()).is_none());
+        // Elapsed time is reset after a successful connection
+        assert!(policy.next_reconnect_delay(0, &io_error()).is_some());
+    }
+}
@@ -3,22 +3,32 @@
 
 //! Internal implementation of [`Session`] and [`SessionExitHandle`].
 
-use std::fs;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use tokio::sync::Notify;
 use tokio_util::sync::CancellationToken;
 
-use crate::auth::{self, SatAuthContext};
-use crate::control_packet::QoS;
+use crate::auth::{AuthContext, AuthProvider, SatFileAuthProvider};
+#[cfg(feature = "capture")]
+use crate::capture::CaptureWriter;
+use crate::control_packet::{DisconnectReasonCode, QoS};
 use crate::error::ConnectionError;
 use crate::interface::{Event, Incoming, MqttClient, MqttDisconnect, MqttEventLoop};
+use crate::metrics::{Meter, Metrics};
+use crate::rumqttc_adapter as adapter;
+use crate::session::endpoints::BrokerEndpoints;
+use crate::session::events::{SessionEvent, SessionEventSender, SessionEventStream};
 use crate::session::managed_client::SessionManagedClient;
-use crate::session::receiver::{IncomingPublishDispatcher, PublishReceiverManager};
-use crate::session::reconnect_policy::ReconnectPolicy;
+use crate::session::offline_queue::OfflinePublishQueue;
+use crate::session::receiver::{HeldAckTracker, IncomingPublishDispatcher, PublishReceiverManager};
+use crate::session::reconnect_policy::{
+    ErrorClassification, ReconnectPolicy, classify_connection_error,
+};
 use crate::session::state::SessionState;
+use crate::session::subscription_registry::SubscriptionRegistry;
 use crate::session::{SessionError, SessionErrorRepr, SessionExitError, SessionExitErrorKind};
+use crate::tls_watcher::{TlsFileWatcher, TlsFiles};
 
 /// Client that manages connections over a single MQTT session.
 ///
@@ -37,14 +47,36 @@ where
     client_id: String,
     /// File path to the SAT token
     sat_file: Option<String>,
+    /// Provider for enhanced authentication. Takes precedence over the SAT file.
+    auth_provider: Option<Arc<dyn AuthProvider>>,
+    /// TLS material to be reloaded when its files change, and the debounce for detecting changes
+    tls_files: Option<(TlsFiles, Duration)>,
+    /// MQTT broker endpoints to fail over between.
+    /// If not present, the endpoint of the underlying event loop is always used.
+    endpoints: Option<BrokerEndpoints>,
+    /// Disk-backed queue for QoS 1 publishes made while disconnected
+    offline_queue: Option<Arc<OfflinePublishQueue>>,
+    /// Registry of active subscriptions, used to recover the MQTT session if it is lost.
+    /// If not present, losing the MQTT session ends the Session.
+    subscriptions: Option<Arc<tokio::sync::Mutex<SubscriptionRegistry>>>,
     /// Manager for the receivers of the Session
     receiver_manager: Arc<Mutex<PublishReceiverManager>>,
     /// Receiver dispatcher for incoming publishes
     incoming_pub_dispatcher: IncomingPublishDispatcher<C>,
+    /// Threshold after which incoming publishes whose ack tokens are still held are logged.
+    /// If not present, held ack tokens are not logged.
+    held_ack_warning_threshold: Option<Duration>,
     /// Reconnect policy
     reconnect_policy: Box<dyn ReconnectPolicy>,
     /// Current state
     state: Arc<SessionState>,
+    /// Sender for lifecycle events
+    events: SessionEventSender,
+    /// Metrics recorder
+    metrics: Metrics,
+    /// Capture of the events polled from the event loop, if capturing
+    #[cfg(feature = "capture")]
+    capture: Option<CaptureWriter>,
     /// Notifier for a force exit signal
     notify_force_exit: Arc<Notify>,
 }
@@ -72,14 +104,87 @@ where
             event_loop,
             client_id,
             sat_file,
+            auth_provider: None,
+            tls_files: None,
+            endpoints: None,
+            offline_queue: None,
+            subscriptions: None,
             receiver_manager,
             incoming_pub_dispatcher,
+            held_ack_warning_threshold: None,
             reconnect_policy,
             state: Arc::new(SessionState::default()),
+            events: SessionEventSender::new(),
+            metrics: Metrics::default(),
+            #[cfg(feature = "capture")]
+            capture: None,
             notify_force_exit: Arc::new(Notify::new()),
         }
     }
 
+    /// Use the provided [`AuthProvider`] for enhanced authentication.
+    pub(crate) fn set_auth_provider(&mut self, auth_provider: Arc<dyn AuthProvider>) {
+        self.auth_provider = Some(auth_provider);
+    }
+
+    /// Watch the provided TLS files for changes while running, and use the updated TLS
+    /// material from the next connection attempt. A change is only reloaded once the files have
+    /// not changed for the provided debounce duration.
+    ///
+    /// NOTE: Rotation does not interrupt an established connection. The updated TLS material is
+    /// used when the connection is next re-established.
+    pub(crate) fn set_tls_files(&mut self, tls_files: TlsFiles, debounce: Duration) {
+        self.tls_files = Some((tls_files, debounce));
+    }
+
+    /// Fail over between the provided MQTT broker endpoints (hostname, TCP port), in order.
+    /// The first endpoint must be the one the underlying event loop is configured with.
+    pub(crate) fn set_broker_endpoints(&mut self, endpoints: Vec<(String, u16)>) {
+        self.endpoints = BrokerEndpoints::new(endpoints);
+    }
+
+    /// Queue QoS 1 publishes made while disconnected in the provided [`OfflinePublishQueue`],
+    /// and send them in order once connected.
+    pub(crate) fn set_offline_queue(&mut self, offline_queue: OfflinePublishQueue) {
+        self.offline_queue = Some(Arc::new(offline_queue));
+    }
+
+    /// Recover from the loss of the MQTT session by restoring all active subscriptions, rather
+    /// than ending the Session.
+    pub(crate) fn set_recover_lost_session(&mut self) {
+        self.subscriptions = Some(Arc::new(tokio::sync::Mutex::new(
+            SubscriptionRegistry::default(),
+        )));
+    }
+
+    /// Acknowledge incoming publishes as soon as all of their ack tokens have been acked, rather
+    /// than in the order they were received.
+    pub(crate) fn set_unordered_acks(&mut self) {
+        self.incoming_pub_dispatcher.set_ordered_acks(false);
+    }
+
+    /// Periodically log a warning for each incoming publish whose ack tokens have been held for
+    /// at least the provided threshold.
+    pub(crate) fn set_held_ack_warning_threshold(&mut self, threshold: Duration) {
+        self.held_ack_warning_threshold = Some(threshold);
+    }
+
+    /// Record metrics with the provided [`Meter`].
+    ///
+    /// Must be called before creating any [`SessionManagedClient`]s for their metrics to be
+    /// recorded.
+    pub(crate) fn set_meter(&mut self, meter: Arc<dyn Meter>) {
+        self.metrics = Metrics::new(meter);
+        self.incoming_pub_dispatcher
+            .set_metrics(self.metrics.clone());
+    }
+
+    /// Record every event polled from the event loop with the provided [`CaptureWriter`].
+    #[cfg(feature = "capture")]
+    pub(crate) fn set_capture(&mut self, capture: CaptureWriter) {
+        self.capture = Some(capture);
+    }
+
     /// Return a new instance of [`SessionExitHandle`] that can be used to end this [`Session`]
     pub fn create_exit_handle(&self) -> SessionExitHandle<C> {
         SessionExitHandle {
@@ -93,6 +198,7 @@ where
     pub fn create_connection_monitor(&self) -> SessionConnectionMonitor {
         SessionConnectionMonitor {
             state: self.state.clone(),
+            events: self.events.clone(),
         }
     }
 
@@ -102,6 +208,10 @@ where
             client_id: self.client_id.clone(),
             pub_sub: self.client.clone(),
             receiver_manager: self.receiver_manager.clone(),
+            state: self.state.clone(),
+            offline_queue: self.offline_queue.clone(),
+            subscriptions: self.subscriptions.clone(),
+            metrics: self.metrics.clone(),
         }
     }
 
@@ -115,47 +225,77 @@ where
     pub async fn run(mut self) -> Result<(), SessionError> {
         self.state.transition_running();
 
-        let mut sat_auth_context = None;
-        let mut sat_auth_tx = None;
+        // Use the SAT file for enhanced authentication if no other provider was set
+        if self.auth_provider.is_none() {
+            if let Some(sat_file) = &self.sat_file {
+                let sat_auth_provider =
+                    SatFileAuthProvider::new(sat_file.clone()).map_err(|e| {
+                        log::error!("Error while creating SAT auth provider: {e:?}");
+                        SessionErrorRepr::SatAuthError(e)
+                    })?;
+                self.auth_provider = Some(Arc::new(sat_auth_provider));
+            }
+        }
+
+        let mut auth_context = None;
+        let mut auth_tx = None;
 
-        if let Some(sat_file) = &self.sat_file {
-            // Set the authentication method
+        if let Some(auth_provider) = self.auth_provider.clone() {
+            // Set the authentication method and data
             self.event_loop
-                .set_authentication_method(Some(auth::SAT_AUTHENTICATION_METHOD.to_string()));
-
-            // Read the SAT auth file
-            match fs::read(sat_file) {
-                Ok(sat_auth_data) => {
-                    // Set the authentication data
-                    self.event_loop
-                        .set_authentication_data(Some(sat_auth_data.into()));
-                }
-                Err(e) => {
-                    log::error!("Cannot read SAT auth file: {sat_file}");
-                    // TODO: This should happen in the auth module, it should be fixed in the future.
-                    return Err(SessionErrorRepr::IoError(e))?;
-                }
-            }
+                .set_authentication_method(Some(auth_provider.authentication_method()));
+            self.event_loop.set_auth_provider(auth_provider.clone());
+            self.refresh_authentication_data(auth_provider.as_ref())
+                .await?;
 
             let (auth_watch_channel_tx, auth_watch_channel_rx) =
                 tokio::sync::mpsc::unbounded_channel();
-            sat_auth_tx = Some(auth_watch_channel_tx);
-            sat_auth_context = Some(
-                SatAuthContext::new(sat_file.clone(), auth_watch_channel_rx).map_err(|e| {
-                    log::error!("Error while creating SAT auth context: {e:?}");
-                    SessionErrorRepr::SatAuthError(e)
-                })?,
-            );
+            auth_tx = Some(auth_watch_channel_tx);
+            auth_context = Some(AuthContext::new(auth_provider, auth_watch_channel_rx));
         }
 
+        // Watch TLS files for rotation.
+        // Failure to watch is not fatal, as the current TLS material is still valid.
+        let tls_file_watcher = self.tls_files.take().and_then(|(tls_files, debounce)| {
+            TlsFileWatcher::new(tls_files, debounce)
+                .inspect_err(|e| {
+                    log::warn!("Cannot watch TLS files, rotation will not be detected: {e:?}");
+                })
+                .ok()
+        });
+
         // Background tasks
         let cancel_token = CancellationToken::new();
         tokio::spawn({
             let cancel_token = cancel_token.clone();
             let client = self.client.clone();
-            run_background(client, sat_auth_context, cancel_token)
+            let offline_queue = self.offline_queue.clone();
+            let metrics = self.metrics.clone();
+            let state = self.state.clone();
+            let events = self.events.clone();
+            let held_acks = self.held_ack_warning_threshold.map(|threshold| {
+                (
+                    self.incoming_pub_dispatcher.get_held_ack_tracker(),
+                    threshold,
+                )
+            });
+            run_background(
+                client,
+                auth_context,
+                offline_queue,
+                metrics,
+                held_acks,
+                state,
+                events,
+                cancel_token,
+            )
         });
 
+        if let Some(endpoints) = &self.endpoints {
+            let (hostname, port) = endpoints.current();
+            self.state.set_endpoint(hostname, *port);
+        }
+
         // Indicates whether this session has been previously connected
         let mut prev_connected = false;
         // Number of previous reconnect attempts
@@ -163,6 +303,10 @@ where
         // Return value for the session indicating reason for exit
         let mut result = Ok(());
 
+        self.events.send(SessionEvent::ConnectAttempt {
+            reconnect_attempt: 0,
+        });
+
         // Handle events
         loop {
             // Poll the next event/error unless a force exit occurs.
@@ -173,6 +317,18 @@ where
                 next = self.event_loop.poll() => { next },
             };
 
+            #[cfg(feature = "capture")]
+            if let Some(capture) = &mut self.capture {
+                if let Err(e) = capture.record(&next) {
+                    log::error!("Error writing packet capture. Capture stopped: {e}");
+                    self.capture = None;
+                }
+            }
+
+            // Reload rotated TLS material as soon as the change is detected, so that it is used
+            // for the next connection attempt
+            self.reload_tls_if_changed(tls_file_watcher.as_ref());
+
             match next {
                 Ok(Event::Incoming(Incoming::ConnAck(connack))) => {
                     // Update connection state
@@ -180,9 +336,22 @@ where
                     // Reset the counter on reconnect attempts
                     prev_reconnect_attempts = 0;
                     log::debug!("Incoming CONNACK: {connack:?}");
+                    self.events.send(SessionEvent::Connected {
+                        session_present: connack.session_present,
+                        reason_code: connack.code,
+                        properties: connack.properties.clone(),
+                    });
 
-                    // If the session is not present after a reconnect, end the session.
-                    if prev_connected && !connack.session_present {
+                    // If the session is not present after a reconnect, recover the session if
+                    // configured to do so.
+                    if prev_connected && !connack.session_present && self.subscriptions.is_some() {
+                        log::warn!(
+                            "Session state not present on broker after reconnect. Recovering session."
+                        );
+                        self.recover_session();
+                    }
+                    // Otherwise, if the session is not present after a reconnect, end the session.
+                    else if prev_connected && !connack.session_present {
                         log::error!(
                             "Session state not present on broker after reconnect. Ending session."
                         );
@@ -211,18 +380,20 @@ where
                 Ok(Event::Incoming(Incoming::Auth(auth))) => {
                     log::debug!("Incoming AUTH: {auth:?}");
 
-                    if let Some(sat_auth_tx) = &sat_auth_tx {
+                    // NOTE: AUTH packets received while connecting are part of the CONNECT
+                    // exchange, which is completed by the event loop, so are not forwarded.
+                    if let Some(auth_tx) = auth_tx.as_ref().filter(|_| self.state.is_connected()) {
                         // Notify the background task that the auth data has changed
                         // TODO: This is a bit of a hack, but it works for now. Ideally, the reauth
                         // method on rumqttc would return a completion token and we could use that
                         // in the background task to know when the reauth is complete.
-                        match sat_auth_tx.send(auth.code) {
+                        match auth_tx.send(auth) {
                             Ok(()) => {}
                             Err(e) => {
                                 // This should never happen unless the background task has exited
                                 // in which case the session is already in a bad state and we should
                                 // have already exited.
-                                log::error!("Error sending auth code to SAT auth task: {e:?}");
+                                log::error!("Error sending AUTH to auth task: {e:?}");
                             }
                         }
                     }
@@ -231,7 +402,11 @@ where
                     log::debug!("Incoming PUB: {publish:?}");
 
                     // Dispatch the message to receivers
-                    match self.incoming_pub_dispatcher.dispatch_publish(&publish) {
+                    match self
+                        .incoming_pub_dispatcher
+                        .dispatch_publish(&publish)
+                        .await
+                    {
                         Ok(_) => {}
                         Err(e) => {
                             // If the dispatch fails, we must be responsible for acking.
@@ -279,6 +454,30 @@ where
                     }
                 }
 
+                Ok(Event::Incoming(Incoming::Disconnect(disconnect))) => {
+                    log::debug!("Incoming DISCONNECT: {disconnect:?}");
+                    let (reason_string, server_reference) = disconnect
+                        .properties
+                        .map(|p| (p.reason_string, p.server_reference))
+                        .unwrap_or_default();
+                    // Use the server the MQTT broker referred the client to for the reconnect
+                    if let Some(endpoints) = &mut self.endpoints {
+                        match disconnect.reason_code {
+                            DisconnectReasonCode::UseAnotherServer => {
+                                endpoints.redirect(server_reference.as_deref(), false);
+                            }
+                            DisconnectReasonCode::ServerMoved => {
+                                endpoints.redirect(server_reference.as_deref(), true);
+                            }
+                            _ => {}
+                        }
+                    }
+                    self.events.send(SessionEvent::ServerDisconnect {
+                        reason_code: disconnect.reason_code,
+                        reason_string,
+                    });
+                }
+
                 Ok(_e) => {
                     // There could be additional incoming and outgoing event responses here if
                     // more filters like the above one are applied
@@ -297,26 +496,67 @@ where
                     break;
                 }
 
-                // Connection refused by broker - unrecoverable
-                Err(ConnectionError::ConnectionRefused(rc)) => {
-                    log::error!("Connection Refused: rc: {rc:?}");
-                    result = Err(SessionErrorRepr::ConnectionError(next.unwrap_err()));
-                    break;
-                }
-
-                // Other errors are passed to reconnect policy
+                // Errors (including connections refused by the broker) are passed to reconnect policy
                 Err(e) => {
+                    // Indicates the error is a failed connection attempt, rather than the loss of
+                    // an established connection
+                    let attempt_failed = !self.state.is_connected();
                     self.state.transition_disconnected();
 
                     // Always log the error itself at error level
                     log::error!("Error: {e:?}");
+                    if let ConnectionError::ConnectionRefused(rc) = e {
+                        log::error!("Connection Refused: rc: {rc:?}");
+                        self.events
+                            .send(SessionEvent::ConnectionRefused { reason_code: rc });
+                    } else {
+                        self.events.send(SessionEvent::ConnectionLost {
+                            error: e.to_string(),
+                        });
+                    }
 
-                    // Defer decision to reconnect policy
-                    if let Some(delay) = self
-                        .reconnect_policy
-                        .next_reconnect_delay(prev_reconnect_attempts, &e)
-                    {
+                    // Get up-to-date authentication data before reconnecting
+                    if let Some(auth_provider) = self.auth_provider.clone() {
+                        if self
+                            .refresh_authentication_data(auth_provider.as_ref())
+                            .await
+                            .is_err()
+                        {
+                            log::warn!("Previous authentication data will be used to reconnect");
+                        }
+                    }
+
+                    // Connections refused for a reason that will not be resolved by retrying are not
+                    // retried, regardless of the reconnect policy. Otherwise, defer decision to
+                    // reconnect policy.
+                    let next_delay = match classify_connection_error(&e) {
+                        ErrorClassification::Fatal => {
+                            log::warn!("Connection error is not retryable: {e}");
+                            None
+                        }
+                        ErrorClassification::Retryable => self
+                            .reconnect_policy
+                            .next_reconnect_delay(prev_reconnect_attempts, &e),
+                    };
+                    if let Some(delay) = next_delay {
+                        // Fail over to the next MQTT broker endpoint if necessary
+                        // NOTE: Connections refused with a "use another server" reason code are
+                        // also failed attempts, so they fail over too. The Server Reference in a
+                        // refusing CONNACK is not supported, as it is not available from the
+                        // underlying MQTT client.
+                        if let Some(endpoints) = &mut self.endpoints {
+                            if let Some((hostname, port)) = endpoints.next(attempt_failed) {
+                                log::info!("Failing over to MQTT broker at {hostname}:{port}");
+                                self.event_loop.set_broker_endpoint(hostname, *port);
+                                self.state.set_endpoint(hostname, *port);
+                            }
+                        }
                         log::info!("Attempting reconnect in {delay:?}");
+                        self.metrics.reconnect();
+                        self.events.send(SessionEvent::ReconnectScheduled {
+                            prev_attempts: prev_reconnect_attempts,
+                            delay,
+                        });
                         // Wait for either the reconnect delay time, or a force exit signal
                         tokio::select! {
                             () = tokio::time::sleep(delay) => {}
@@ -326,20 +566,128 @@ where
                                 break;
                             }
                         }
+                        // Pick up any TLS material rotated during the delay before reconnecting
+                        self.reload_tls_if_changed(tls_file_watcher.as_ref());
                     } else {
                         log::info!("Reconnect attempts halted by reconnect policy");
-                        result = Err(SessionErrorRepr::ReconnectHalted);
+                        // A refused connection is reported as the cause, as it is more informative
+                        result = if matches!(e, ConnectionError::ConnectionRefused(_)) {
+                            Err(SessionErrorRepr::ConnectionError(e))
+                        } else {
+                            Err(SessionErrorRepr::ReconnectHalted)
+                        };
                         break;
                     }
                     prev_reconnect_attempts += 1;
+                    self.events.send(SessionEvent::ConnectAttempt {
+                        reconnect_attempt: prev_reconnect_attempts,
+                    });
                 }
             }
         }
         self.state.transition_exited();
         cancel_token.cancel();
+        self.events.send(SessionEvent::Exited {
+            error: result.as_ref().err().map(ToString::to_string),
+        });
         result.map_err(std::convert::Into::into)
     }
 
+    /// Helper for setting the authentication data for the next connection attempt
+    async fn refresh_authentication_data(
+        &mut self,
+        auth_provider: &dyn AuthProvider,
+    ) -> Result<(), SessionErrorRepr> {
+        let data = auth_provider
+            .initial_authentication_data()
+            .await
+            .inspect_err(|e| log::error!("Cannot get authentication data: {e:?}"))?;
+        self.event_loop.set_authentication_data(data);
+        Ok(())
+    }
+
+    /// Helper for reloading TLS material from the watched files if they have changed, and
+    /// reporting the result
+    fn reload_tls_if_changed(&mut self, tls_file_watcher: Option<&TlsFileWatcher>) {
+        let Some(tls_file_watcher) = tls_file_watcher else {
+            return;
+        };
+        if !tls_file_watcher.take_changed() {
+            return;
+        }
+        let files = tls_file_watcher.files();
+        match adapter::tls_config(&files.material, files.use_websocket) {
+            Ok(transport) => {
+                self.event_loop.set_transport(transport);
+                log::info!("TLS files rotated. Updated TLS material will be used on reconnect");
+                self.events.send(SessionEvent::TlsReloaded);
+            }
+            Err(e) => {
+                // NOTE: The previous TLS material remains in use. It may still be valid, and if
+                // the files were only partially updated, the next change will trigger a reload.
+                log::error!("Error reloading rotated TLS files: {e:?}");
+                self.events.send(SessionEvent::TlsReloadFailed {
+                    error: format!("{e:?}"),
+                });
+            }
+        }
+    }
+
+    /// Helper for recovering from the loss of the MQTT session
+    fn recover_session(&self) {
+        // Publishes received on the lost MQTT session can no longer be acked, and their PKIDs
+        // may be re-used by the broker.
+        self.incoming_pub_dispatcher.reset_acks();
+
+        let Some(subscriptions) = &self.subscriptions else {
+            return;
+        };
+        // NOTE: The subscriptions are restored in a separate task, as the event loop must continue
+        // to be polled for the subscribe requests to be sent.
+        tokio::spawn({
+            let client = self.client.clone();
+            let subscriptions = subscriptions.clone();
+            let events = self.events.clone();
+            async move {
+                // NOTE: The registry is locked until all subscribe requests have been sent, so
+                // that any subscribe or unsubscribe made by the user concurrently is sent after
+                // them, and takes precedence.
+                let registry = subscriptions.lock().await;
+                let mut pending = vec![];
+                for subscription in registry.recoverable() {
+                    let result = client
+                        .subscribe_with_options(
+                            subscription.topic_filter.clone(),
+                            subscription.options,
+                        )
+                        .await;
+                    pending.push((subscription.topic_filter, result));
+                }
+                drop(registry);
+
+                let mut resubscribed = 0;
+                for (topic_filter, result) in pending {
+                    let result = match result {
+                        Ok(ct) => ct.await.map_err(|e| format!("{e:?}")),
+                        Err(e) => Err(format!("{e:?}")),
+                    };
+                    match result {
+                        Ok(()) => {
+                            log::debug!(
+                                "Restored subscription to {topic_filter} after session loss"
+                            );
+                            resubscribed += 1;
+                        }
+                        Err(e) => log::error!(
+                            "Cannot restore subscription to {topic_filter} after session loss: {e}"
+                        ),
+                    }
+                }
+                events.send(SessionEvent::SessionRecovered { resubscribed });
+            }
+        });
+    }
+
     /// Helper for triggering a session exit and logging the result
     async fn trigger_session_exit(&self) {
         let exit_handle = self.create_exit_handle();
@@ -353,48 +701,144 @@ where
 /// Run background tasks for [`Session.run()`]
 async fn run_background(
     client: impl MqttClient + Clone,
-    sat_auth_context: Option<SatAuthContext>,
+    auth_context: Option<AuthContext>,
+    offline_queue: Option<Arc<OfflinePublishQueue>>,
+    metrics: Metrics,
+    held_acks: Option<(Arc<Mutex<HeldAckTracker>>, Duration)>,
+    state: Arc<SessionState>,
+    events: SessionEventSender,
     cancel_token: CancellationToken,
 ) {
-    /// Maintain the SAT token authentication by renewing it when the SAT file changes
-    async fn maintain_sat_auth(mut sat_auth_context: SatAuthContext, client: impl MqttClient) -> ! {
+    /// Maintain enhanced authentication by re-authenticating when the provider requests it
+    async fn maintain_auth(
+        mut auth_context: AuthContext,
+        client: impl MqttClient,
+        events: SessionEventSender,
+    ) -> ! {
         let mut retrying = false;
         loop {
-            // Wait for the SAT file to change if not retrying
+            // Wait for the provider to request re-authentication if not retrying
             if !retrying {
-                sat_auth_context.notified().await;
+                auth_context.reauthentication_needed().await;
             }
 
             // Re-authenticate the client
-            if sat_auth_context
-                .reauth(Duration::from_secs(10), &client)
-                .await
-                .is_ok()
-            {
-                log::debug!("SAT token renewed successfully");
-                // Drain the notification so we don't re-auth again for a prior change to the SAT file
-                sat_auth_context.drain_notify().await;
-                retrying = false;
-                continue;
+            match auth_context.reauth(Duration::from_secs(10), &client).await {
+                Ok(()) => {
+                    log::debug!("Re-authentication successful");
+                    events.send(SessionEvent::Reauthenticated);
+                    retrying = false;
+                    continue;
+                }
+                Err(e) => {
+                    log::error!("Error re-authenticating: {e}. Retrying...");
+                    events.send(SessionEvent::ReauthenticationFailed {
+                        error: e.to_string(),
+                    });
+                }
             }
-            log::error!("Error renewing SAT token, retrying...");
             retrying = true;
             // Wait before retrying
             tokio::time::sleep(Duration::from_secs(10)).await;
         }
     }
 
-    // Run the background tasks
-    if let Some(sat_auth_context) = sat_auth_context {
-        tokio::select! {
-            () = cancel_token.cancelled() => {
-                log::debug!("Session background task cancelled");
+    /// Send publishes from the offline publish queue in order while connected
+    async fn send_offline_queue(
+        offline_queue: Arc<OfflinePublishQueue>,
+        client: impl MqttClient + Clone,
+        metrics: Metrics,
+        state: Arc<SessionState>,
+    ) {
+        loop {
+            state.condition_connected().await;
+            let queued_publish = offline_queue.next().await;
+            let seq = queued_publish.seq;
+            let result = client
+                .publish_with_properties(
+                    queued_publish.topic,
+                    QoS::AtLeastOnce,
+                    queued_publish.retain,
+                    queued_publish.payload,
+                    queued_publish.properties,
+                )
+                .await;
+            offline_queue.sent();
+            match result {
+                Ok(ct) => {
+                    log::debug!("Queued publish {seq} sent from offline publish queue");
+                    metrics.publish_sent(QoS::AtLeastOnce);
+                    tokio::spawn({
+                        let offline_queue = offline_queue.clone();
+                        async move {
+                            offline_queue.complete(seq, ct.await);
+                        }
+                    });
+                }
+                Err(e) => {
+                    // NOTE: The client can only fail to publish if it is detached, in which case
+                    // the queued publish remains on disk to be sent when the queue is next opened.
+                    log::error!("Cannot send queued publish {seq}: {e:?}");
+                    return;
+                }
             }
-            () = maintain_sat_auth(sat_auth_context, client) => {
-                log::error!("`maintain_sat_auth` task ended unexpectedly.");
+        }
+    }
+
+    /// Periodically log a warning for each incoming publish whose ack tokens have been held for
+    /// at least the threshold, as they may be delaying the acks of other publishes
+    async fn warn_held_acks(held_acks: Arc<Mutex<HeldAckTracker>>, threshold: Duration) -> ! {
+        // NOTE: The interval cannot be zero
+        let mut interval = tokio::time::interval(threshold.max(Duration::from_secs(1)));
+        loop {
+            interval.tick().await;
+            let held = held_acks.lock().unwrap().held_for(threshold);
+            for held_ack in held {
+                log::warn!(
+                    "Ack token for PUB with PKID {} on topic {} held for {:?}",
+                    held_ack.pkid,
+                    held_ack.topic,
+                    held_ack.held
+                );
             }
         }
     }
+
+    // Run the background tasks
+    let auth_task = async {
+        match auth_context {
+            Some(auth_context) => maintain_auth(auth_context, client.clone(), events).await,
+            None => std::future::pending::<()>().await,
+        }
+    };
+    let offline_queue_task = async {
+        match offline_queue {
+            Some(offline_queue) => {
+                send_offline_queue(offline_queue, client.clone(), metrics, state).await
+            }
+            None => std::future::pending::<()>().await,
+        }
+    };
+    let held_acks_task = async {
+        match held_acks {
+            Some((held_acks, threshold)) => warn_held_acks(held_acks, threshold).await,
+            None => std::future::pending::<()>().await,
+        }
+    };
+    tokio::select! {
+        () = cancel_token.cancelled() => {
+            log::debug!("Session background task cancelled");
+        }
+        () = auth_task => {
+            log::error!("`maintain_auth` task ended unexpectedly.");
+        }
+        () = offline_queue_task => {
+            log::error!("`send_offline_queue` task ended unexpectedly.");
+        }
+        () = held_acks_task => {
+            log::error!("`warn_held_acks` task ended unexpectedly.");
+        }
+    }
 }
 
 /// Handle used to end an MQTT session.
@@ -551,6 +995,7 @@ where
 #[derive(Clone)]
 pub struct SessionConnectionMonitor {
     state: Arc<SessionState>,
+    events: SessionEventSender,
 }
 
 impl SessionConnectionMonitor {
@@ -572,4 +1017,18 @@ impl SessionConnectionMonitor {
     pub async fn disconnected(&self) {
         self.state.condition_disconnected().await;
     }
+
+    /// Return a new [`SessionEventStream`] that receives all subsequent [`SessionEvent`]s
+    /// from the [`Session`].
+    #[must_use]
+    pub fn events(&self) -> SessionEventStream {
+        self.events.subscribe()
+    }
+
+    /// Returns the MQTT broker endpoint (hostname, TCP port) the [`Session`] is currently
+    /// connected to, or `None` if not connected or connected over a Unix domain socket.
+    #[must_use]
+    pub fn connected_endpoint(&self) -> Option<(String, u16)> {
+        self.state.connected_endpoint()
+    }
 }
@@ -25,6 +25,8 @@ struct InnerSessionState {
     connected: bool,
     /// Indicates if a Session exit is desired, and if so, by whom.
     desire_exit: DesireExit,
+    /// The MQTT broker endpoint (hostname, TCP port) of the current connection attempt, if known.
+    endpoint: Option<(String, u16)>,
 }
 
 // NOTE: There could be more methods implemented here, but they would not be used yet,
@@ -43,6 +45,17 @@ impl SessionState {
         self.state.read().unwrap().connected
     }
 
+    /// Return the MQTT broker endpoint (hostname, TCP port) the Session is currently connected
+    /// to, if connected and the endpoint is known.
+    pub fn connected_endpoint(&self) -> Option<(String, u16)> {
+        let state = self.state.read().unwrap();
+        if state.connected {
+            state.endpoint.clone()
+        } else {
+            None
+        }
+    }
+
     /// Return true if the a Session exit is desired
     pub fn desire_exit(&self) -> bool {
         !matches!(self.state.read().unwrap().desire_exit, DesireExit::No)
@@ -117,6 +130,13 @@ impl SessionState {
         log::debug!("{state:?}");
     }
 
+    /// Update the MQTT broker endpoint (hostname, TCP port) used for connection attempts
+    pub fn set_endpoint(&self, hostname: &str, port: u16) {
+        let mut state = self.state.write().unwrap();
+        state.endpoint = Some((hostname.to_string(), port));
+        log::debug!("{state:?}");
+    }
+
     /// Update the state to reflect the Session is running
     pub fn transition_running(&self) {
         let mut state = self.state.write().unwrap();
@@ -170,6 +190,7 @@ impl Default for InnerSessionState {
             lifecycle_status: LifecycleStatus::NotStarted,
             connected: false,
             desire_exit: DesireExit::No,
+            endpoint: None,
         }
     }
 }
@@ -192,6 +213,7 @@ impl fmt::Debug for InnerSessionState {
             .field("lifecycle_status", &self.lifecycle_status)
             .field("connected", &self.connected)
             .field("desire_exit", &self.desire_exit)
+            .field("endpoint", &self.endpoint)
             .finish()
     }
 }
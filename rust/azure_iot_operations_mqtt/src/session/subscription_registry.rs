@@ -0,0 +1,160 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Internal registry of the active subscriptions of a [`Session`](super::Session), used to
+//! restore them after the MQTT session is lost.
+
+use std::collections::HashMap;
+
+use crate::control_packet::SubscribeOptions;
+
+/// An active subscription
+#[derive(Clone, Debug)]
+pub struct Subscription {
+    /// Topic filter of the subscription
+    pub topic_filter: String,
+    /// Options of the subscription
+    pub options: SubscribeOptions,
+}
+
+/// Registry of active subscriptions, in the order they were made.
+///
+/// A subscription is only recorded once the MQTT broker has acknowledged it, while an
+/// unsubscribe takes effect as soon as it is sent.
+#[derive(Default)]
+pub struct SubscriptionRegistry {
+    /// Subscriptions acknowledged by the MQTT broker
+    subscriptions: Vec<Subscription>,
+    /// Sequence number of the latest subscribe sent for each topic filter that has not yet
+    /// completed
+    pending: HashMap<String, u64>,
+    /// Sequence number for the next subscribe
+    next_seq: u64,
+}
+
+impl SubscriptionRegistry {
+    /// Indicate that a subscribe to a topic filter was sent.
+    ///
+    /// Returns the sequence number to complete the subscribe with.
+    pub fn subscribe_sent(&mut self, topic_filter: &str) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.pending.insert(topic_filter.to_string(), seq);
+        seq
+    }
+
+    /// Indicate that a subscribe has completed, recording the subscription if it was successful
+    /// (`options` is `Some`), replacing any existing subscription to the same topic filter.
+    ///
+    /// A subscribe that was superseded by a later (un)subscribe to the same topic filter is
+    /// ignored.
+    pub fn subscribe_completed(
+        &mut self,
+        seq: u64,
+        topic_filter: &str,
+        options: Option<SubscribeOptions>,
+    ) {
+        if self.pending.get(topic_filter) != Some(&seq) {
+            return;
+        }
+        self.pending.remove(topic_filter);
+        if let Some(options) = options {
+            self.remove(topic_filter);
+            self.subscriptions.push(Subscription {
+                topic_filter: topic_filter.to_string(),
+                options,
+            });
+        }
+    }
+
+    /// Indicate that an unsubscribe from a topic filter was sent, removing the subscription to
+    /// it, if there is one.
+    pub fn unsubscribe_sent(&mut self, topic_filter: &str) {
+        self.pending.remove(topic_filter);
+        self.remove(topic_filter);
+    }
+
+    /// Return the subscriptions to restore after the MQTT session is lost.
+    ///
+    /// Subscriptions with a subscribe in progress are excluded, as the subscribe in progress
+    /// reflects the latest options.
+    pub fn recoverable(&self) -> Vec<Subscription> {
+        self.subscriptions
+            .iter()
+            .filter(|subscription| !self.pending.contains_key(&subscription.topic_filter))
+            .cloned()
+            .collect()
+    }
+
+    /// Remove the subscription to a topic filter, if there is one.
+    fn remove(&mut self, topic_filter: &str) {
+        self.subscriptions
+            .retain(|subscription| subscription.topic_filter != topic_filter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control_packet::QoS;
+
+    fn options(qos: QoS) -> SubscribeOptions {
+        SubscribeOptions::from_properties(qos, None)
+    }
+
+    fn subscribe(registry: &mut SubscriptionRegistry, topic_filter: &str, qos: QoS) {
+        let seq = registry.subscribe_sent(topic_filter);
+        registry.subscribe_completed(seq, topic_filter, Some(options(qos)));
+    }
+
+    #[test]
+    fn subscribe_and_unsubscribe() {
+        let mut registry = SubscriptionRegistry::default();
+        subscribe(&mut registry, "topic/1", QoS::AtLeastOnce);
+        subscribe(&mut registry, "topic/2", QoS::AtMostOnce);
+        subscribe(&mut registry, "topic/3", QoS::AtLeastOnce);
+        // Re-subscribing replaces the previous subscription
+        subscribe(&mut registry, "topic/1", QoS::AtMostOnce);
+        registry.unsubscribe_sent("topic/2");
+
+        let subscriptions = registry.recoverable();
+        assert_eq!(subscriptions.len(), 2);
+        assert_eq!(subscriptions[0].topic_filter, "topic/3");
+        assert_eq!(subscriptions[1].topic_filter, "topic/1");
+        assert_eq!(subscriptions[1].options.qos(), QoS::AtMostOnce);
+    }
+
+    #[test]
+    fn subscribe_not_recorded_until_successful() {
+        let mut registry = SubscriptionRegistry::default();
+        let seq = registry.subscribe_sent("topic/1");
+        assert!(registry.recoverable().is_empty());
+        // Failed subscribes are not recorded
+        registry.subscribe_completed(seq, "topic/1", None);
+        assert!(registry.recoverable().is_empty());
+    }
+
+    #[test]
+    fn superseded_subscribe_ignored() {
+        let mut registry = SubscriptionRegistry::default();
+        subscribe(&mut registry, "topic/1", QoS::AtLeastOnce);
+
+        // A subscribe in progress excludes the existing subscription from recovery
+        let seq1 = registry.subscribe_sent("topic/1");
+        assert!(registry.recoverable().is_empty());
+
+        // A subscribe completing after a later unsubscribe is not recorded
+        registry.unsubscribe_sent("topic/1");
+        registry.subscribe_completed(seq1, "topic/1", Some(options(QoS::AtMostOnce)));
+        assert!(registry.recoverable().is_empty());
+
+        // A subscribe completing after a later subscribe is not recorded
+        let seq2 = registry.subscribe_sent("topic/1");
+        let seq3 = registry.subscribe_sent("topic/1");
+        registry.subscribe_completed(seq3, "topic/1", Some(options(QoS::AtLeastOnce)));
+        registry.subscribe_completed(seq2, "topic/1", Some(options(QoS::AtMostOnce)));
+        let subscriptions = registry.recoverable();
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].options.qos(), QoS::AtLeastOnce);
+    }
+}
@@ -1,21 +1,38 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
+use std::num::NonZeroUsize;
+#[cfg(feature = "config-file")]
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
 use bytes::Bytes;
 
 use crate::MqttConnectionSettings;
+use crate::auth::AuthProvider;
+#[cfg(feature = "capture")]
+use crate::capture::{CaptureOptions, CaptureWriter};
+#[cfg(feature = "config-file")]
+use crate::config_file::{ConfigFile, ConfigFileError};
 use crate::control_packet::{
-    Publish, PublishProperties, QoS, SubscribeProperties, UnsubscribeProperties,
+    Publish, PublishProperties, QoS, SubscribeOptions, SubscribeProperties, UnsubscribeProperties,
 };
 use crate::error::{PublishError, SubscribeError, UnsubscribeError};
-use crate::interface::{AckToken, CompletionToken, ManagedClient, MqttPubSub, PubReceiver};
+use crate::interface::{
+    AckToken, CompletionToken, ManagedClient, MqttPubSub, OverflowPolicy, PubReceiver,
+    PublishFilter,
+};
+use crate::metrics::Meter;
 use crate::rumqttc_adapter as adapter;
 use crate::session::managed_client;
+use crate::session::offline_queue::OfflinePublishQueue;
 use crate::session::reconnect_policy::{ExponentialBackoffWithJitter, ReconnectPolicy};
 use crate::session::session;
-use crate::session::{SessionConfigError, SessionError, SessionExitError};
+use crate::session::{
+    SessionConfigError, SessionConfigErrorRepr, SessionError, SessionEventStream, SessionExitError,
+};
+use crate::tls_watcher::TlsFiles;
 use crate::topic::TopicParseError;
 
 /// Client that manages connections over a single MQTT session.
@@ -52,7 +69,12 @@ pub struct SessionPubReceiver(managed_client::SessionPubReceiver);
 pub struct SessionOptions {
     /// MQTT Connection Settings for configuring the [`Session`]
     pub connection_settings: MqttConnectionSettings,
-    /// Reconnect Policy to by used by the `Session`
+    /// Reconnect Policy to by used by the `Session`.
+    /// By default, reconnects with exponential backoff.
+    ///
+    /// Regardless of the policy, the `Session` does not reconnect if the connection is refused by
+    /// the MQTT broker for a reason that will not be resolved by retrying (see
+    /// [`classify_connection_error`](crate::session::reconnect_policy::classify_connection_error)).
     #[builder(default = "Box::new(ExponentialBackoffWithJitter::default())")]
     pub reconnect_policy: Box<dyn ReconnectPolicy>,
     /// Maximum number of queued outgoing messages not yet accepted by the MQTT Session
@@ -61,6 +83,70 @@ pub struct SessionOptions {
     /// Indicates if the Session should use features specific for use with the AIO MQTT Broker
     #[builder(default = "true")]
     pub aio_broker_features: bool,
+    /// Provider for enhanced authentication.
+    /// If not provided, SAT authentication is used when a SAT file is set in the connection settings.
+    #[builder(default = "None", setter(strip_option))]
+    pub auth_provider: Option<Arc<dyn AuthProvider>>,
+    /// Directory used to persist QoS 1 publishes made while the [`Session`] is disconnected.
+    /// Persisted publishes are sent in order once connected, including after a restart.
+    /// If not provided, publishes made while disconnected are only queued in memory.
+    #[builder(default = "None", setter(into, strip_option))]
+    pub offline_queue_dir: Option<String>,
+    /// Maximum number of publishes persisted in the offline publish queue.
+    /// Publishing fails once the limit is reached, until queued publishes have been delivered.
+    #[builder(default = "10_000")]
+    pub offline_queue_max: usize,
+    /// Duration the TLS files set in the connection settings must go unchanged before rotated
+    /// TLS material is reloaded, so that files updated one at a time are reloaded together.
+    /// Reloaded TLS material is used from the next connection attempt, and does not interrupt
+    /// the current connection.
+    #[builder(default = "Duration::from_secs(10)")]
+    pub tls_reload_debounce: Duration,
+    /// Indicates if the [`Session`] should recover when the MQTT session is lost (e.g. due to
+    /// session expiry while disconnected) by restoring all active subscriptions, instead of ending.
+    #[builder(default = "false")]
+    pub recover_lost_session: bool,
+    /// Indicates if incoming publishes are acknowledged in the order they were received.
+    /// If false, a publish is acknowledged as soon as all of its [`AckToken`]s have been acked,
+    /// so that a receiver holding an [`AckToken`] does not delay the acknowledgement of other
+    /// publishes. Only disable if the MQTT broker does not require in-order acknowledgement.
+    #[builder(default = "true")]
+    pub ordered_acks: bool,
+    /// If provided, the [`Session`] periodically logs a warning with the topic and PKID of each
+    /// incoming publish whose [`AckToken`]s have been held for at least this long.
+    /// If not provided, held [`AckToken`]s are not logged.
+    #[builder(default = "None", setter(strip_option))]
+    pub held_ack_warning_threshold: Option<Duration>,
+    /// [`Meter`] used to record metrics for the [`Session`] and its managed clients.
+    /// If not provided, no metrics are recorded.
+    #[builder(default = "None", setter(strip_option))]
+    pub meter: Option<Arc<dyn Meter>>,
+    /// Options for capturing every MQTT packet sent and received by the [`Session`] to a file,
+    /// for debugging. See the [`capture`](crate::capture) module for the format of the capture.
+    /// If not provided, packets are not captured.
+    #[cfg(feature = "capture")]
+    #[builder(default = "None", setter(strip_option))]
+    pub capture: Option<CaptureOptions>,
+}
+
+#[cfg(feature = "config-file")]
+impl SessionOptionsBuilder {
+    /// Initialize the [`SessionOptionsBuilder`] from the `session_options` table of a TOML, YAML
+    /// or JSON config file. Values set on the returned builder take precedence over those in the
+    /// file.
+    ///
+    /// The format of the file is determined by its extension (`.toml`, `.yaml`, `.yml` or
+    /// `.json`). Keys are named after the fields of [`SessionOptions`], except for
+    /// `connection_settings`, `reconnect_policy`, `auth_provider`, `meter` and `capture`, which must
+    /// be set on the builder. Use [`MqttConnectionSettingsBuilder::from_config_file`](crate::MqttConnectionSettingsBuilder::from_config_file)
+    /// to load the connection settings from the same file.
+    ///
+    /// # Errors
+    /// Returns a [`ConfigFileError`] if the file cannot be read, is malformed or contains an
+    /// unknown key or invalid value.
+    pub fn from_config_file(path: impl AsRef<Path>) -> Result<Self, ConfigFileError> {
+        ConfigFile::read(path.as_ref())?.session_options_builder()
+    }
 }
 
 impl Session {
@@ -71,6 +157,23 @@ impl Session {
     pub fn new(options: SessionOptions) -> Result<Self, SessionConfigError> {
         let client_id = options.connection_settings.client_id.clone();
         let sat_file = options.connection_settings.sat_file.clone();
+        let tls_files = TlsFiles::from_connection_settings(&options.connection_settings);
+        // NOTE: Endpoints are not used when connecting over a Unix domain socket
+        let endpoints = if options.connection_settings.unix_socket_path.is_none() {
+            let mut endpoints = vec![(
+                options.connection_settings.hostname.clone(),
+                options.connection_settings.tcp_port,
+            )];
+            endpoints.extend(options.connection_settings.failover_endpoints.clone());
+            Some(endpoints)
+        } else {
+            None
+        };
+        let offline_queue = options
+            .offline_queue_dir
+            .map(|dir| OfflinePublishQueue::open(dir, options.offline_queue_max))
+            .transpose()
+            .map_err(SessionConfigErrorRepr::OfflineQueue)?;
 
         // Add AIO metric to user properties when using AIO MQTT broker features
         // TODO: consider user properties from being supported on SessionOptions or ConnectionSettings
@@ -86,13 +189,43 @@ impl Session {
             true,
             user_properties,
         )?;
-        Ok(Session(session::Session::new_from_injection(
+        let mut session = session::Session::new_from_injection(
             client,
             event_loop,
             options.reconnect_policy,
             client_id,
             sat_file,
-        )))
+        );
+        if let Some(auth_provider) = options.auth_provider {
+            session.set_auth_provider(auth_provider);
+        }
+        if let Some(tls_files) = tls_files {
+            session.set_tls_files(tls_files, options.tls_reload_debounce);
+        }
+        if let Some(endpoints) = endpoints {
+            session.set_broker_endpoints(endpoints);
+        }
+        if let Some(offline_queue) = offline_queue {
+            session.set_offline_queue(offline_queue);
+        }
+        if options.recover_lost_session {
+            session.set_recover_lost_session();
+        }
+        if !options.ordered_acks {
+            session.set_unordered_acks();
+        }
+        if let Some(threshold) = options.held_ack_warning_threshold {
+            session.set_held_ack_warning_threshold(threshold);
+        }
+        if let Some(meter) = options.meter {
+            session.set_meter(meter);
+        }
+        #[cfg(feature = "capture")]
+        if let Some(capture) = options.capture {
+            let capture = CaptureWriter::open(capture).map_err(SessionConfigErrorRepr::Capture)?;
+            session.set_capture(capture);
+        }
+        Ok(Session(session))
     }
 
     /// Return a new instance of [`SessionExitHandle`] that can be used to end this [`Session`]
@@ -121,6 +254,15 @@ impl Session {
     }
 }
 
+impl SessionManagedClient {
+    /// Return the number of publishes in the offline publish queue waiting to be sent,
+    /// or `None` if the offline publish queue is not enabled.
+    #[must_use]
+    pub fn offline_queue_depth(&self) -> Option<usize> {
+        self.0.offline_queue_depth()
+    }
+}
+
 impl ManagedClient for SessionManagedClient {
     type PubReceiver = SessionPubReceiver;
 
@@ -137,6 +279,39 @@ impl ManagedClient for SessionManagedClient {
         ))
     }
 
+    fn create_bounded_filtered_pub_receiver(
+        &self,
+        topic_filter: &str,
+        capacity: NonZeroUsize,
+        overflow_policy: OverflowPolicy,
+    ) -> Result<SessionPubReceiver, TopicParseError> {
+        Ok(SessionPubReceiver(
+            self.0
+                .create_bounded_filtered_pub_receiver(topic_filter, capacity, overflow_policy)?,
+        ))
+    }
+
+    fn create_content_filtered_pub_receiver(
+        &self,
+        topic_filter: &str,
+        publish_filter: PublishFilter,
+    ) -> Result<SessionPubReceiver, TopicParseError> {
+        Ok(SessionPubReceiver(
+            self.0
+                .create_content_filtered_pub_receiver(topic_filter, publish_filter)?,
+        ))
+    }
+
+    fn create_identified_pub_receiver(
+        &self,
+        subscription_identifier: usize,
+    ) -> Result<SessionPubReceiver, SubscribeError> {
+        Ok(SessionPubReceiver(
+            self.0
+                .create_identified_pub_receiver(subscription_identifier)?,
+        ))
+    }
+
     fn create_unfiltered_pub_receiver(&self) -> SessionPubReceiver {
         SessionPubReceiver(self.0.create_unfiltered_pub_receiver())
     }
@@ -186,6 +361,14 @@ impl MqttPubSub for SessionManagedClient {
             .await
     }
 
+    async fn subscribe_with_options(
+        &self,
+        topic: impl Into<String> + Send,
+        options: SubscribeOptions,
+    ) -> Result<CompletionToken, SubscribeError> {
+        self.0.subscribe_with_options(topic, options).await
+    }
+
     async fn unsubscribe(
         &self,
         topic: impl Into<String> + Send,
@@ -202,6 +385,14 @@ impl MqttPubSub for SessionManagedClient {
     }
 }
 
+impl SessionPubReceiver {
+    /// Return the number of incoming messages dropped because this receiver was full
+    #[must_use]
+    pub fn dropped_count(&self) -> u64 {
+        self.0.dropped_count()
+    }
+}
+
 #[async_trait]
 impl PubReceiver for SessionPubReceiver {
     async fn recv(&mut self) -> Option<Publish> {
@@ -289,4 +480,18 @@ impl SessionConnectionMonitor {
     pub async fn disconnected(&self) {
         self.0.disconnected().await;
     }
+
+    /// Return a new [`SessionEventStream`] that receives all subsequent
+    /// [`SessionEvent`](crate::session::SessionEvent)s from the [`Session`].
+    #[must_use]
+    pub fn events(&self) -> SessionEventStream {
+        self.0.events()
+    }
+
+    /// Returns the MQTT broker endpoint (hostname, TCP port) the [`Session`] is currently
+    /// connected to, or `None` if not connected or connected over a Unix domain socket.
+    #[must_use]
+    pub fn connected_endpoint(&self) -> Option<(String, u16)> {
+        self.0.connected_endpoint()
+    }
 }
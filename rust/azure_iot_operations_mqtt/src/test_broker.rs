@@ -0,0 +1,833 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Minimal in-process MQTT v5 broker stand-in for integration tests.
+//!
+//! The [`TestBroker`] listens on an ephemeral localhost port (or a Unix domain socket) and
+//! implements enough of the MQTT v5 broker behavior to exercise a
+//! [`Session`](crate::session::Session) end-to-end without any external services:
+//! * QoS 0 and QoS 1 delivery (QoS 2 is not supported)
+//! * Retained messages
+//! * Shared subscriptions (`$share/<group>/<filter>`), distributed round-robin
+//! * Session expiry and the session present flag, including redelivery of unacknowledged
+//!   QoS 1 messages on reconnect
+//! * Forwarding of publish properties, including user properties
+//! * Will messages, published immediately when a connection is lost without a normal disconnect
+//!   (the will delay interval is not supported)
+//!
+//! It is NOT a conformant MQTT broker, and should never be used outside of tests.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::Ipv4Addr;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use rumqttc::v5::mqttbytes::v5::{
+    ConnAck, ConnectReturnCode, Disconnect, DisconnectReasonCode, LastWill, Packet, PingResp,
+    PubAck, RetainForwardRule, SubAck, SubscribeReasonCode, UnsubAck, UnsubAckReason,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::Notify;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use tokio_util::sync::CancellationToken;
+
+use crate::MqttConnectionSettingsBuilder;
+use crate::control_packet::{Publish, PublishProperties, QoS};
+use crate::topic::{TopicFilter, TopicName};
+
+/// Prefix indicating a shared subscription topic filter
+const SHARED_SUBSCRIPTION_PREFIX: &str = "$share/";
+
+/// In-process MQTT v5 broker for use in tests.
+///
+/// The broker runs until the [`TestBroker`] is dropped.
+pub struct TestBroker {
+    /// Port the broker is listening on
+    port: u16,
+    /// Path of the Unix domain socket the broker is listening on, if not listening on a port
+    #[cfg(unix)]
+    unix_socket_path: Option<PathBuf>,
+    /// Shared broker state
+    state: Arc<Mutex<BrokerState>>,
+    /// Cancellation token for the listener and all connections
+    cancel_token: CancellationToken,
+}
+
+impl TestBroker {
+    /// Hostname the broker can be reached on
+    pub const HOSTNAME: &'static str = "127.0.0.1";
+
+    /// Start a new [`TestBroker`] listening on an ephemeral port on localhost.
+    ///
+    /// # Errors
+    /// Returns a [`std::io::Error`] if the listener cannot be bound.
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let port = listener.local_addr()?.port();
+        let state = Arc::new(Mutex::new(BrokerState::default()));
+        let cancel_token = CancellationToken::new();
+
+        tokio::spawn(run_listener(
+            Listener::Tcp(listener),
+            state.clone(),
+            cancel_token.clone(),
+        ));
+        log::debug!("Test broker listening on {}:{port}", Self::HOSTNAME);
+
+        Ok(Self {
+            port,
+            #[cfg(unix)]
+            unix_socket_path: None,
+            state,
+            cancel_token,
+        })
+    }
+
+    /// Start a new [`TestBroker`] listening on a Unix domain socket at the provided path.
+    ///
+    /// # Errors
+    /// Returns a [`std::io::Error`] if the listener cannot be bound.
+    #[cfg(unix)]
+    pub fn start_unix(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let unix_socket_path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&unix_socket_path)?;
+        let state = Arc::new(Mutex::new(BrokerState::default()));
+        let cancel_token = CancellationToken::new();
+
+        tokio::spawn(run_listener(
+            Listener::Unix(listener),
+            state.clone(),
+            cancel_token.clone(),
+        ));
+        log::debug!("Test broker listening on {}", unix_socket_path.display());
+
+        Ok(Self {
+            port: 0,
+            unix_socket_path: Some(unix_socket_path),
+            state,
+            cancel_token,
+        })
+    }
+
+    /// Return the port the broker is listening on
+    #[must_use]
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Return a [`MqttConnectionSettingsBuilder`] pre-configured to connect to this broker
+    /// with the provided client ID.
+    #[must_use]
+    pub fn connection_settings_builder(&self, client_id: &str) -> MqttConnectionSettingsBuilder {
+        let builder = MqttConnectionSettingsBuilder::default()
+            .client_id(client_id)
+            .use_tls(false);
+        #[cfg(unix)]
+        if let Some(unix_socket_path) = &self.unix_socket_path {
+            return builder.unix_socket_path(unix_socket_path.to_string_lossy().to_string());
+        }
+        builder.hostname(Self::HOSTNAME).tcp_port(self.port)
+    }
+
+    /// Return true if a client with the provided client ID is currently connected
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn is_client_connected(&self, client_id: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .sessions
+            .get(client_id)
+            .is_some_and(|s| s.connection.is_some())
+    }
+
+    /// Abruptly drop the network connection of the client with the provided client ID, as if
+    /// there had been a network failure. The MQTT session state is retained.
+    ///
+    /// Returns true if the client was connected.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn disconnect_client(&self, client_id: &str) -> bool {
+        self.state.lock().unwrap().drop_connection(client_id)
+    }
+
+    /// Drop the network connection of the client with the provided client ID (if connected)
+    /// and discard its MQTT session state, as if the session had expired.
+    ///
+    /// Returns true if there was a session to discard.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn discard_session(&self, client_id: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.drop_connection(client_id);
+        state.sessions.remove(client_id).is_some()
+    }
+
+    /// Return true if the client with the provided client ID holds a subscription for the
+    /// provided topic filter (including any `$share` prefix)
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn has_subscription(&self, client_id: &str, topic_filter: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .sessions
+            .get(client_id)
+            .is_some_and(|s| s.subscriptions.iter().any(|sub| sub.path == topic_filter))
+    }
+
+    /// Wait until the client with the provided client ID holds a subscription for the provided
+    /// topic filter (including any `$share` prefix).
+    ///
+    /// Useful for components that subscribe internally, where the SUBACK cannot be awaited.
+    #[allow(clippy::missing_panics_doc)]
+    pub async fn wait_for_subscription(&self, client_id: &str, topic_filter: &str) {
+        let subscribed = self.state.lock().unwrap().subscribed.clone();
+        loop {
+            // NOTE: The notification is created before checking, so that a subscription made in
+            // between is not missed.
+            let notified = subscribed.notified();
+            if self.has_subscription(client_id, topic_filter) {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for TestBroker {
+    fn drop(&mut self) {
+        self.cancel_token.cancel();
+    }
+}
+
+/// A network connection to a client
+struct ClientConnection {
+    /// Unique identifier of the connection
+    id: u64,
+    /// Sender for outgoing packets
+    tx: UnboundedSender<Packet>,
+    /// Token used to drop the connection
+    cancel_token: CancellationToken,
+    /// Will message to publish if the connection is lost without a normal disconnect
+    will: Option<Publish>,
+}
+
+/// A subscription held by a client session
+struct Subscription {
+    /// Full topic filter as provided in the SUBSCRIBE, including any `$share` prefix
+    path: String,
+    /// Topic filter used for matching (i.e. without any `$share` prefix)
+    filter: TopicFilter,
+    /// Share group name, if this is a shared subscription
+    share_group: Option<String>,
+    /// Maximum QoS granted for the subscription
+    qos: QoS,
+    /// Subscription identifier
+    id: Option<usize>,
+    /// Indicates publishes from the same client should not be delivered
+    no_local: bool,
+}
+
+/// MQTT session state of a client
+#[derive(Default)]
+struct ClientSession {
+    /// Subscriptions held by the session
+    subscriptions: Vec<Subscription>,
+    /// The current network connection, if connected
+    connection: Option<ClientConnection>,
+    /// QoS 1 publishes sent (or queued) to the client, not yet acknowledged, in order
+    inflight: VecDeque<Publish>,
+    /// Last packet identifier used for an outgoing publish
+    last_pkid: u16,
+    /// Session expiry interval in seconds
+    session_expiry_interval: u32,
+    /// Time the client disconnected, if not connected
+    disconnected_at: Option<Instant>,
+}
+
+impl ClientSession {
+    /// Return true if the session has expired
+    fn is_expired(&self) -> bool {
+        match self.disconnected_at {
+            Some(disconnected_at) if self.session_expiry_interval != u32::MAX => {
+                disconnected_at.elapsed()
+                    >= Duration::from_secs(u64::from(self.session_expiry_interval))
+            }
+            _ => false,
+        }
+    }
+
+    /// Drop the current connection, if there is one, returning it.
+    fn take_connection(&mut self) -> Option<ClientConnection> {
+        let connection = self.connection.take()?;
+        connection.cancel_token.cancel();
+        self.disconnected_at = Some(Instant::now());
+        Some(connection)
+    }
+
+    /// Send a packet to the client if it is connected
+    fn send(&self, packet: Packet) {
+        if let Some(connection) = &self.connection {
+            // NOTE: A send failure means the connection is in the process of closing, in which
+            // case any QoS 1 publish remains in the inflight queue for redelivery.
+            let _ = connection.tx.send(packet);
+        }
+    }
+
+    /// Return the next available packet identifier
+    fn next_pkid(&mut self) -> u16 {
+        loop {
+            self.last_pkid = self.last_pkid.wrapping_add(1);
+            if self.last_pkid != 0 && !self.inflight.iter().any(|p| p.pkid == self.last_pkid) {
+                return self.last_pkid;
+            }
+        }
+    }
+
+    /// Deliver a publish to the client, queueing it for redelivery if QoS 1
+    fn deliver(&mut self, publish: &Publish, max_qos: QoS, subscription_ids: Vec<usize>) {
+        let mut outgoing = publish.clone();
+        outgoing.dup = false;
+        outgoing.qos = min_qos(publish.qos, max_qos);
+        if let Some(properties) = outgoing.properties.as_mut() {
+            properties.topic_alias = None;
+            properties.subscription_identifiers = subscription_ids;
+        } else if !subscription_ids.is_empty() {
+            outgoing.properties = Some(PublishProperties {
+                subscription_identifiers: subscription_ids,
+                ..Default::default()
+            });
+        }
+
+        if outgoing.qos == QoS::AtMostOnce {
+            outgoing.pkid = 0;
+        } else {
+            outgoing.pkid = self.next_pkid();
+            self.inflight.push_back(outgoing.clone());
+        }
+        self.send(Packet::Publish(outgoing));
+    }
+}
+
+/// State of the broker shared between all connections
+#[derive(Default)]
+struct BrokerState {
+    /// MQTT sessions by client ID
+    sessions: HashMap<String, ClientSession>,
+    /// Retained messages by topic name
+    retained: HashMap<String, Publish>,
+    /// Round-robin cursors for shared subscriptions by (share group, topic filter)
+    share_cursors: HashMap<(String, String), usize>,
+    /// Identifier to assign to the next connection
+    next_connection_id: u64,
+    /// Notified whenever a SUBSCRIBE has been processed
+    subscribed: Arc<Notify>,
+}
+
+impl BrokerState {
+    /// Register a new connection for a client, returning whether a session was present.
+    ///
+    /// The CONNACK and any redelivered publishes are sent to the client.
+    fn connect(
+        &mut self,
+        client_id: &str,
+        clean_start: bool,
+        session_expiry_interval: u32,
+        connection: ClientConnection,
+    ) -> bool {
+        // Take over any existing connection for the same client ID
+        if let Some(session) = self.sessions.get_mut(client_id) {
+            session.send(Packet::Disconnect(Disconnect::new(
+                DisconnectReasonCode::SessionTakenOver,
+            )));
+            self.drop_connection(client_id);
+        }
+
+        // Determine if an existing session can be resumed
+        let session_present = !clean_start
+            && self
+                .sessions
+                .get(client_id)
+                .is_some_and(|s| !s.is_expired());
+        if !session_present {
+            self.sessions
+                .insert(client_id.to_string(), ClientSession::default());
+        }
+
+        let session = self
+            .sessions
+            .get_mut(client_id)
+            .expect("session was just inserted if not present");
+        session.session_expiry_interval = session_expiry_interval;
+        session.disconnected_at = None;
+        session.connection = Some(connection);
+        session.send(Packet::ConnAck(ConnAck {
+            session_present,
+            code: ConnectReturnCode::Success,
+            properties: None,
+        }));
+        // Redeliver any unacknowledged QoS 1 publishes
+        for publish in &session.inflight {
+            let mut publish = publish.clone();
+            publish.dup = true;
+            session.send(Packet::Publish(publish));
+        }
+        session_present
+    }
+
+    /// Handle the loss of a connection, graceful or otherwise
+    fn connection_lost(&mut self, client_id: &str, connection_id: u64) {
+        let Some(session) = self.sessions.get_mut(client_id) else {
+            return;
+        };
+        // Only handle if the session is still associated with this connection
+        if session
+            .connection
+            .as_ref()
+            .is_none_or(|c| c.id != connection_id)
+        {
+            return;
+        }
+        let session_expiry_interval = session.session_expiry_interval;
+        self.drop_connection(client_id);
+        if session_expiry_interval == 0 {
+            self.sessions.remove(client_id);
+        }
+    }
+
+    /// Drop the connection of a client, if connected, publishing its will message if it has one.
+    /// Returns true if there was a connection to drop.
+    fn drop_connection(&mut self, client_id: &str) -> bool {
+        let Some(connection) = self
+            .sessions
+            .get_mut(client_id)
+            .and_then(ClientSession::take_connection)
+        else {
+            return false;
+        };
+        if let Some(will) = connection.will {
+            self.route(client_id, &will);
+        }
+        true
+    }
+
+    /// Route an incoming publish to all matching subscriptions
+    fn route(&mut self, publisher: &str, publish: &Publish) {
+        let Ok(topic) = String::from_utf8(publish.topic.to_vec()) else {
+            log::warn!("Test broker received PUBLISH with non-UTF-8 topic");
+            return;
+        };
+        let Ok(topic_name) = TopicName::from_string(topic.clone()) else {
+            log::warn!("Test broker received PUBLISH with invalid topic: {topic}");
+            return;
+        };
+
+        // Update retained messages
+        if publish.retain {
+            if publish.payload.is_empty() {
+                self.retained.remove(&topic);
+            } else {
+                let mut retained = publish.clone();
+                retained.pkid = 0;
+                self.retained.insert(topic, retained);
+            }
+        }
+
+        // Find the matching subscriptions.
+        // Non-shared subscriptions for a client are combined into a single delivery with the
+        // highest QoS, while each share group receives exactly one delivery.
+        let mut deliveries: Vec<(String, QoS, Vec<usize>)> = vec![];
+        let mut share_groups: HashMap<(String, String), Vec<(String, QoS, Option<usize>)>> =
+            HashMap::new();
+        for (client_id, session) in &self.sessions {
+            let mut matched: Option<(QoS, Vec<usize>)> = None;
+            for sub in &session.subscriptions {
+                if !sub.filter.matches_topic_name(&topic_name)
+                    || (sub.no_local && client_id == publisher)
+                {
+                    continue;
+                }
+                if let Some(group) = &sub.share_group {
+                    share_groups
+                        .entry((group.clone(), sub.filter.as_str().to_string()))
+                        .or_default()
+                        .push((client_id.clone(), sub.qos, sub.id));
+                } else {
+                    let (qos, ids) = matched.get_or_insert((QoS::AtMostOnce, vec![]));
+                    if sub.qos > *qos {
+                        *qos = sub.qos;
+                    }
+                    ids.extend(sub.id);
+                }
+            }
+            if let Some((qos, ids)) = matched {
+                deliveries.push((client_id.clone(), qos, ids));
+            }
+        }
+        for (key, mut members) in share_groups {
+            // Prefer connected members, but fall back to queueing for disconnected ones
+            let connected: Vec<_> = members
+                .iter()
+                .filter(|(client_id, _, _)| {
+                    self.sessions
+                        .get(client_id)
+                        .is_some_and(|s| s.connection.is_some())
+                })
+                .cloned()
+                .collect();
+            if !connected.is_empty() {
+                members = connected;
+            }
+            members.sort_by(|a, b| a.0.cmp(&b.0));
+            let cursor = self.share_cursors.entry(key).or_default();
+            let (client_id, qos, id) = members[*cursor % members.len()].clone();
+            *cursor = cursor.wrapping_add(1);
+            deliveries.push((client_id, qos, id.into_iter().collect()));
+        }
+
+        // Forward the publish. The retain flag is not propagated to existing subscriptions.
+        let mut forwarded = publish.clone();
+        forwarded.retain = false;
+        for (client_id, qos, ids) in deliveries {
+            if let Some(session) = self.sessions.get_mut(&client_id) {
+                session.deliver(&forwarded, qos, ids);
+            }
+        }
+    }
+}
+
+/// Convert a will message into the publish to route when it is triggered
+fn will_publish(will: LastWill) -> Publish {
+    let properties = will.properties.map(|p| PublishProperties {
+        payload_format_indicator: p.payload_format_indicator,
+        message_expiry_interval: p.message_expiry_interval,
+        content_type: p.content_type,
+        response_topic: p.response_topic,
+        correlation_data: p.correlation_data,
+        user_properties: p.user_properties,
+        ..Default::default()
+    });
+    Publish {
+        topic: will.topic,
+        payload: will.message,
+        qos: will.qos,
+        retain: will.retain,
+        properties,
+        ..Default::default()
+    }
+}
+
+/// Return the lower of two [`QoS`] values
+fn min_qos(a: QoS, b: QoS) -> QoS {
+    if a < b { a } else { b }
+}
+
+/// Listener for incoming client connections
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// Accept incoming connections until cancelled
+async fn run_listener(
+    listener: Listener,
+    state: Arc<Mutex<BrokerState>>,
+    cancel_token: CancellationToken,
+) {
+    loop {
+        tokio::select! {
+            () = cancel_token.cancelled() => {
+                log::debug!("Test broker stopped");
+                break;
+            }
+            accepted = accept(&listener, &state, &cancel_token) => {
+                if let Err(e) = accepted {
+                    log::warn!("Test broker failed to accept connection: {e:?}");
+                }
+            }
+        }
+    }
+}
+
+/// Accept a single client connection, and spawn a task to handle it
+async fn accept(
+    listener: &Listener,
+    state: &Arc<Mutex<BrokerState>>,
+    cancel_token: &CancellationToken,
+) -> std::io::Result<()> {
+    match listener {
+        Listener::Tcp(listener) => {
+            let (stream, addr) = listener.accept().await?;
+            log::debug!("Test broker accepted connection from {addr}");
+            tokio::spawn(run_connection(
+                stream,
+                state.clone(),
+                cancel_token.child_token(),
+            ));
+        }
+        #[cfg(unix)]
+        Listener::Unix(listener) => {
+            let (stream, _) = listener.accept().await?;
+            log::debug!("Test broker accepted connection on Unix domain socket");
+            tokio::spawn(run_connection(
+                stream,
+                state.clone(),
+                cancel_token.child_token(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Handle a single client connection until it closes or is cancelled
+async fn run_connection<S>(
+    stream: S,
+    state: Arc<Mutex<BrokerState>>,
+    cancel_token: CancellationToken,
+) where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let (tx, mut rx) = unbounded_channel::<Packet>();
+
+    // Write outgoing packets until all senders are dropped
+    tokio::spawn(async move {
+        let mut buf = BytesMut::new();
+        while let Some(packet) = rx.recv().await {
+            buf.clear();
+            if let Err(e) = packet.write(&mut buf) {
+                log::warn!("Test broker failed to serialize packet: {e:?}");
+                break;
+            }
+            if writer.write_all(&buf).await.is_err() {
+                break;
+            }
+            if matches!(packet, Packet::Disconnect(_)) {
+                break;
+            }
+        }
+        let _ = writer.shutdown().await;
+    });
+
+    let connection_id = {
+        let mut state = state.lock().unwrap();
+        state.next_connection_id += 1;
+        state.next_connection_id
+    };
+    // Client ID of the connection, once known
+    let mut client_id: Option<String> = None;
+    let mut buf = BytesMut::with_capacity(4096);
+
+    loop {
+        // Read the next packet from the buffer, reading more from the socket as necessary
+        let packet = match Packet::read(&mut buf, None) {
+            Ok(packet) => packet,
+            Err(rumqttc::v5::mqttbytes::Error::InsufficientBytes(_)) => {
+                let read = tokio::select! {
+                    () = cancel_token.cancelled() => break,
+                    read = reader.read_buf(&mut buf) => read,
+                };
+                match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => continue,
+                }
+            }
+            Err(e) => {
+                log::warn!("Test broker received malformed packet: {e:?}");
+                break;
+            }
+        };
+
+        let mut state = state.lock().unwrap();
+        match (packet, &client_id) {
+            (Packet::Connect(connect, will, _), None) => {
+                if connect.client_id.is_empty() {
+                    let _ = tx.send(Packet::ConnAck(ConnAck {
+                        session_present: false,
+                        code: ConnectReturnCode::ClientIdentifierNotValid,
+                        properties: None,
+                    }));
+                    break;
+                }
+                let session_expiry_interval = connect
+                    .properties
+                    .as_ref()
+                    .and_then(|p| p.session_expiry_interval)
+                    .unwrap_or(0);
+                state.connect(
+                    &connect.client_id,
+                    connect.clean_start,
+                    session_expiry_interval,
+                    ClientConnection {
+                        id: connection_id,
+                        tx: tx.clone(),
+                        cancel_token: cancel_token.clone(),
+                        will: will.map(will_publish),
+                    },
+                );
+                client_id = Some(connect.client_id);
+            }
+            (_, None) | (Packet::Connect(..), Some(_)) => {
+                log::warn!("Test broker received unexpected packet. Closing connection.");
+                let _ = tx.send(Packet::Disconnect(Disconnect::new(
+                    DisconnectReasonCode::ProtocolError,
+                )));
+                break;
+            }
+            (Packet::Publish(publish), Some(client_id)) => match publish.qos {
+                QoS::AtMostOnce => state.route(client_id, &publish),
+                QoS::AtLeastOnce => {
+                    state.route(client_id, &publish);
+                    let _ = tx.send(Packet::PubAck(PubAck::new(publish.pkid, None)));
+                }
+                QoS::ExactlyOnce => {
+                    let _ = tx.send(Packet::Disconnect(Disconnect::new(
+                        DisconnectReasonCode::QoSNotSupported,
+                    )));
+                    break;
+                }
+            },
+            (Packet::PubAck(puback), Some(client_id)) => {
+                if let Some(session) = state.sessions.get_mut(client_id) {
+                    session.inflight.retain(|p| p.pkid != puback.pkid);
+                }
+            }
+            (Packet::Subscribe(subscribe), Some(client_id)) => {
+                let id = subscribe.properties.as_ref().and_then(|p| p.id);
+                let mut return_codes = vec![];
+                let mut retained_deliveries = vec![];
+                let Some(session) = state.sessions.get_mut(client_id) else {
+                    break;
+                };
+                for filter in subscribe.filters {
+                    let (share_group, topic_filter) = match filter
+                        .path
+                        .strip_prefix(SHARED_SUBSCRIPTION_PREFIX)
+                    {
+                        Some(rest) => match rest.split_once('/') {
+                            Some((group, topic_filter)) => (Some(group.to_string()), topic_filter),
+                            None => (None, ""),
+                        },
+                        None => (None, filter.path.as_str()),
+                    };
+                    let Ok(topic_filter) = TopicFilter::from_str(topic_filter) else {
+                        return_codes.push(SubscribeReasonCode::TopicFilterInvalid);
+                        continue;
+                    };
+                    let qos = min_qos(filter.qos, QoS::AtLeastOnce);
+                    let existed = session.subscriptions.iter().any(|s| s.path == filter.path);
+                    session.subscriptions.retain(|s| s.path != filter.path);
+                    let send_retained = share_group.is_none()
+                        && match filter.retain_forward_rule {
+                            RetainForwardRule::OnEverySubscribe => true,
+                            RetainForwardRule::OnNewSubscribe => !existed,
+                            RetainForwardRule::Never => false,
+                        };
+                    if send_retained {
+                        retained_deliveries.push((topic_filter.clone(), qos));
+                    }
+                    session.subscriptions.push(Subscription {
+                        path: filter.path,
+                        filter: topic_filter,
+                        share_group,
+                        qos,
+                        id,
+                        no_local: filter.nolocal,
+                    });
+                    return_codes.push(SubscribeReasonCode::Success(qos));
+                }
+                session.send(Packet::SubAck(SubAck {
+                    pkid: subscribe.pkid,
+                    return_codes,
+                    properties: None,
+                }));
+                state.subscribed.notify_waiters();
+
+                // Deliver any retained messages matching the new subscriptions
+                let retained: Vec<_> = state.retained.values().cloned().collect();
+                let Some(session) = state.sessions.get_mut(client_id) else {
+                    break;
+                };
+                for (topic_filter, qos) in retained_deliveries {
+                    for publish in &retained {
+                        let Ok(topic) = String::from_utf8(publish.topic.to_vec()) else {
+                            continue;
+                        };
+                        if TopicName::from_string(topic)
+                            .is_ok_and(|t| topic_filter.matches_topic_name(&t))
+                        {
+                            session.deliver(publish, qos, id.into_iter().collect());
+                        }
+                    }
+                }
+            }
+            (Packet::Unsubscribe(unsubscribe), Some(client_id)) => {
+                let Some(session) = state.sessions.get_mut(client_id) else {
+                    break;
+                };
+                let reasons = unsubscribe
+                    .filters
+                    .iter()
+                    .map(|path| {
+                        let before = session.subscriptions.len();
+                        session.subscriptions.retain(|s| &s.path != path);
+                        if session.subscriptions.len() < before {
+                            UnsubAckReason::Success
+                        } else {
+                            UnsubAckReason::NoSubscriptionExisted
+                        }
+                    })
+                    .collect();
+                session.send(Packet::UnsubAck(UnsubAck {
+                    pkid: unsubscribe.pkid,
+                    reasons,
+                    properties: None,
+                }));
+            }
+            (Packet::PingReq(_), Some(_)) => {
+                let _ = tx.send(Packet::PingResp(PingResp));
+            }
+            (Packet::Disconnect(disconnect), Some(client_id)) => {
+                // The will message is discarded on a normal disconnect
+                if disconnect.reason_code == DisconnectReasonCode::NormalDisconnection {
+                    if let Some(connection) = state
+                        .sessions
+                        .get_mut(client_id)
+                        .and_then(|s| s.connection.as_mut())
+                        .filter(|c| c.id == connection_id)
+                    {
+                        connection.will = None;
+                    }
+                }
+                if let Some(session_expiry_interval) = disconnect
+                    .properties
+                    .as_ref()
+                    .and_then(|p| p.session_expiry_interval)
+                {
+                    if let Some(session) = state.sessions.get_mut(client_id) {
+                        session.session_expiry_interval = session_expiry_interval;
+                    }
+                }
+                break;
+            }
+            (packet, Some(_)) => {
+                log::debug!("Test broker ignoring packet: {packet:?}");
+            }
+        }
+    }
+
+    if let Some(client_id) = client_id {
+        state
+            .lock()
+            .unwrap()
+            .connection_lost(&client_id, connection_id);
+        log::debug!("Test broker connection for {client_id} closed");
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Internal module for detecting rotation of the TLS files used by the MQTT client.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use notify::RecommendedWatcher;
+use notify_debouncer_full::{RecommendedCache, new_debouncer};
+
+use crate::MqttConnectionSettings;
+use crate::rumqttc_adapter::TlsMaterial;
+
+/// TLS material used by the MQTT client, including the file paths of any read from files.
+#[derive(Clone, Default)]
+pub struct TlsFiles {
+    /// TLS material, from files or held in memory
+    pub material: TlsMaterial,
+    /// Indicates the TLS material is used for secure WebSockets (wss) rather than TLS over TCP
+    pub use_websocket: bool,
+}
+
+impl TlsFiles {
+    /// Return the [`TlsFiles`] used by the provided [`MqttConnectionSettings`], if any.
+    ///
+    /// Returns `None` if none of the TLS material is read from files, as there is nothing to
+    /// watch for rotation.
+    pub fn from_connection_settings(connection_settings: &MqttConnectionSettings) -> Option<Self> {
+        if !connection_settings.use_tls {
+            return None;
+        }
+        let tls_files = Self {
+            material: TlsMaterial::from_connection_settings(connection_settings),
+            use_websocket: connection_settings.use_websocket,
+        };
+        if tls_files.paths().next().is_none() {
+            return None;
+        }
+        Some(tls_files)
+    }
+
+    /// Iterate over all file paths that are set
+    fn paths(&self) -> impl Iterator<Item = &String> {
+        [
+            &self.material.ca_file,
+            &self.material.cert_file,
+            &self.material.key_file,
+            &self.material.key_password_file,
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+/// Watcher for changes to the TLS files used by the MQTT client.
+pub struct TlsFileWatcher {
+    /// TLS files being watched
+    files: TlsFiles,
+    /// TLS files' directory watcher, held to keep the watcher alive
+    #[allow(dead_code)]
+    watcher: notify_debouncer_full::Debouncer<RecommendedWatcher, RecommendedCache>,
+    /// Indicates a change was detected since the last call to `take_changed`
+    changed: Arc<AtomicBool>,
+}
+
+impl TlsFileWatcher {
+    /// Create a new [`TlsFileWatcher`] watching the directories of the provided [`TlsFiles`].
+    /// A change is only reported once the files have not changed for the `debounce` duration,
+    /// so that files updated one at a time are reloaded together.
+    ///
+    /// Directories are watched rather than the files themselves, as Kubernetes rotates mounted
+    /// secrets by swapping a symlink rather than modifying the files in place.
+    ///
+    /// Returns a [`TlsFileWatcher`] instance. If an error occurs, a [`notify::Error`] is returned.
+    pub fn new(files: TlsFiles, debounce: Duration) -> Result<Self, notify::Error> {
+        let changed = Arc::new(AtomicBool::new(false));
+        let changed_clone = changed.clone();
+
+        let mut watcher = new_debouncer(
+            debounce,
+            None,
+            move |res: Result<Vec<notify_debouncer_full::DebouncedEvent>, Vec<notify::Error>>| {
+                match res {
+                    Ok(events) => {
+                        if events.iter().any(|e| {
+                            // Only notify on non-access events
+                            !matches!(e.event.kind, notify::EventKind::Access(_))
+                        }) {
+                            changed_clone.store(true, Ordering::SeqCst);
+                        }
+                    }
+                    Err(err) => {
+                        log::error!("Error reading TLS file directory: {err:?}");
+                    }
+                }
+            },
+        )?;
+
+        // Watch the parent directory of each file, once per directory
+        let directories: HashSet<PathBuf> = files
+            .paths()
+            .filter_map(|p| Path::new(p).parent().map(Path::to_path_buf))
+            .collect();
+        for directory in directories {
+            watcher.watch(&directory, notify::RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(Self {
+            files,
+            watcher,
+            changed,
+        })
+    }
+
+    /// Return the [`TlsFiles`] being watched
+    pub fn files(&self) -> &TlsFiles {
+        &self.files
+    }
+
+    /// Return true if any of the TLS files have changed since the last call, and reset the
+    /// change indicator.
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MqttConnectionSettingsBuilder, TlsData};
+
+    #[test]
+    fn no_tls_files() {
+        let connection_settings = MqttConnectionSettingsBuilder::default()
+            .client_id("test_client_id")
+            .hostname("test_host")
+            .build()
+            .unwrap();
+        assert!(TlsFiles::from_connection_settings(&connection_settings).is_none());
+    }
+
+    #[test]
+    fn tls_disabled() {
+        let connection_settings = MqttConnectionSettingsBuilder::default()
+            .client_id("test_client_id")
+            .hostname("test_host")
+            .use_tls(false)
+            .ca_file(Some("ca.pem".to_string()))
+            .build()
+            .unwrap();
+        assert!(TlsFiles::from_connection_settings(&connection_settings).is_none());
+    }
+
+    #[test]
+    fn in_memory_tls_material() {
+        let connection_settings = MqttConnectionSettingsBuilder::default()
+            .client_id("test_client_id")
+            .hostname("test_host")
+            .ca_data(TlsData::Pem(b"ca".to_vec()))
+            .build()
+            .unwrap();
+        assert!(TlsFiles::from_connection_settings(&connection_settings).is_none());
+    }
+
+    #[tokio::test]
+    async fn change_detected() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let ca_file = dir.path().join("ca.pem");
+        std::fs::write(&ca_file, "original").unwrap();
+
+        let watcher = TlsFileWatcher::new(
+            TlsFiles {
+                material: TlsMaterial {
+                    ca_file: Some(ca_file.to_str().unwrap().to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            Duration::from_millis(100),
+        )
+        .unwrap();
+        assert!(!watcher.take_changed());
+
+        std::fs::write(&ca_file, "rotated").unwrap();
+        // Wait for the debouncer to emit the change
+        let mut changed = false;
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            if watcher.take_changed() {
+                changed = true;
+                break;
+            }
+        }
+        assert!(changed);
+        // Change indicator is reset
+        assert!(!watcher.take_changed());
+    }
+}
@@ -11,6 +11,10 @@ use std::str::FromStr;
 
 use thiserror::Error;
 
+mod tree;
+
+pub use tree::TopicTree;
+
 // TODO: $ rules are not supported yet
 
 /// MQTT topic level separator
@@ -44,6 +48,14 @@ pub enum TopicParseError {
     /// A shared subscription topic filter must contain at least three levels
     #[error("shared subscription topic filter must contain at least three levels: {0}")]
     SharedSubscriptionTooShort(String),
+    /// The topic name or topic filter is outside of the namespace of a
+    /// [`NamespacedManagedClient`](crate::session::NamespacedManagedClient)
+    #[error("topic must be within the namespace: {0}")]
+    OutsideNamespace(String),
+    /// Receivers filtering on the content of messages are not supported for the topic filter by
+    /// the [`ManagedClient`](crate::interface::ManagedClient)
+    #[error("content filtered receivers are not supported by this client: {0}")]
+    ContentFilterNotSupported(String),
 }
 
 /// Represents an MQTT topic name
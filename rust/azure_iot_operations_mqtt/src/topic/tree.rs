@@ -0,0 +1,346 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Index of MQTT topic filters for efficient matching against topic names.
+
+use std::collections::HashMap;
+
+use crate::topic::{
+    MULTI_LEVEL_WILDCARD, SINGLE_LEVEL_WILDCARD, TopicFilter, TopicName, is_shared_sub,
+};
+
+/// Map of [`TopicFilter`]s to values, indexed as a tree of topic levels so that the
+/// [`TopicFilter`]s matching a [`TopicName`] can be found in time proportional to the depth of
+/// the [`TopicName`], rather than the number of [`TopicFilter`]s.
+///
+/// Matching follows the same rules as [`TopicFilter::matches_topic_name`].
+#[derive(Debug)]
+pub struct TopicTree<V> {
+    root: Node<V>,
+    len: usize,
+}
+
+/// Node of a [`TopicTree`] for a single topic level
+#[derive(Debug)]
+struct Node<V> {
+    /// Child nodes for the next topic level, including wildcards
+    children: HashMap<String, Node<V>>,
+    /// Values for the topic filters ending at this node.
+    /// There can be more than one, as shared subscription topic filters end at the same node as
+    /// the topic filter they share.
+    entries: Vec<(TopicFilter, V)>,
+}
+
+impl<V> Default for Node<V> {
+    fn default() -> Self {
+        Self {
+            children: HashMap::new(),
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<V> Node<V> {
+    /// Returns true if there are no entries at or below this node
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.children.is_empty()
+    }
+
+    /// Collect the entries at or below this node that match the remaining topic name levels
+    fn collect_matches<'a>(
+        &'a self,
+        levels: &[String],
+        matches: &mut Vec<(&'a TopicFilter, &'a V)>,
+    ) {
+        let Some((level, rest)) = levels.split_first() else {
+            matches.extend(self.entries.iter().map(|(f, v)| (f, v)));
+            return;
+        };
+        // NOTE: Topic names cannot contain wildcards, so an exact match never finds a wildcard node
+        if let Some(child) = self.children.get(level) {
+            child.collect_matches(rest, matches);
+        }
+        if let Some(child) = self.children.get(SINGLE_LEVEL_WILDCARD) {
+            child.collect_matches(rest, matches);
+        }
+        // A multi-level wildcard matches any number of remaining levels (but at least one)
+        if let Some(child) = self.children.get(MULTI_LEVEL_WILDCARD) {
+            matches.extend(child.entries.iter().map(|(f, v)| (f, v)));
+        }
+    }
+
+    /// Remove the entry for the topic filter from this node or below, pruning empty nodes
+    fn remove(&mut self, levels: &[String], topic_filter: &TopicFilter) -> Option<V> {
+        match levels.split_first() {
+            None => {
+                let pos = self.entries.iter().position(|(f, _)| f == topic_filter)?;
+                Some(self.entries.remove(pos).1)
+            }
+            Some((level, rest)) => {
+                let child = self.children.get_mut(level)?;
+                let value = child.remove(rest, topic_filter);
+                if child.is_empty() {
+                    self.children.remove(level);
+                }
+                value
+            }
+        }
+    }
+
+    /// Retain only the entries at or below this node for which the predicate returns true,
+    /// pruning empty nodes. Returns the number of entries removed.
+    fn retain<F>(&mut self, f: &mut F) -> usize
+    where
+        F: FnMut(&TopicFilter, &mut V) -> bool,
+    {
+        let len = self.entries.len();
+        self.entries
+            .retain_mut(|(topic_filter, v)| f(topic_filter, v));
+        let mut removed = len - self.entries.len();
+        self.children.retain(|_, child| {
+            removed += child.retain(&mut *f);
+            !child.is_empty()
+        });
+        removed
+    }
+}
+
+impl<V> Default for TopicTree<V> {
+    fn default() -> Self {
+        Self {
+            root: Node::default(),
+            len: 0,
+        }
+    }
+}
+
+impl<V> TopicTree<V> {
+    /// Create a new, empty [`TopicTree`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the number of [`TopicFilter`]s in the [`TopicTree`]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the [`TopicTree`] contains no [`TopicFilter`]s
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns true if the [`TopicTree`] contains the [`TopicFilter`]
+    #[must_use]
+    pub fn contains_key(&self, topic_filter: &TopicFilter) -> bool {
+        self.get(topic_filter).is_some()
+    }
+
+    /// Return a reference to the value for the [`TopicFilter`], if present
+    #[must_use]
+    pub fn get(&self, topic_filter: &TopicFilter) -> Option<&V> {
+        let mut node = &self.root;
+        for level in match_levels(topic_filter) {
+            node = node.children.get(level)?;
+        }
+        node.entries
+            .iter()
+            .find(|(f, _)| f == topic_filter)
+            .map(|(_, v)| v)
+    }
+
+    /// Return a mutable reference to the value for the [`TopicFilter`], if present
+    pub fn get_mut(&mut self, topic_filter: &TopicFilter) -> Option<&mut V> {
+        let mut node = &mut self.root;
+        for level in match_levels(topic_filter) {
+            node = node.children.get_mut(level)?;
+        }
+        node.entries
+            .iter_mut()
+            .find(|(f, _)| f == topic_filter)
+            .map(|(_, v)| v)
+    }
+
+    /// Return a mutable reference to the value for the [`TopicFilter`], inserting the result of
+    /// `default` first if not present
+    pub fn get_or_insert_with<F>(&mut self, topic_filter: &TopicFilter, default: F) -> &mut V
+    where
+        F: FnOnce() -> V,
+    {
+        let mut node = &mut self.root;
+        for level in match_levels(topic_filter) {
+            node = node.children.entry(level.clone()).or_default();
+        }
+        let pos = match node.entries.iter().position(|(f, _)| f == topic_filter) {
+            Some(pos) => pos,
+            None => {
+                node.entries.push((topic_filter.clone(), default()));
+                self.len += 1;
+                node.entries.len() - 1
+            }
+        };
+        &mut node.entries[pos].1
+    }
+
+    /// Insert a value for the [`TopicFilter`], returning the previous value, if any
+    pub fn insert(&mut self, topic_filter: TopicFilter, value: V) -> Option<V> {
+        let mut node = &mut self.root;
+        for level in match_levels(&topic_filter) {
+            node = node.children.entry(level.clone()).or_default();
+        }
+        if let Some((_, v)) = node.entries.iter_mut().find(|(f, _)| *f == topic_filter) {
+            return Some(std::mem::replace(v, value));
+        }
+        node.entries.push((topic_filter, value));
+        self.len += 1;
+        None
+    }
+
+    /// Remove the [`TopicFilter`], returning its value, if present
+    pub fn remove(&mut self, topic_filter: &TopicFilter) -> Option<V> {
+        let value = self.root.remove(match_levels(topic_filter), topic_filter);
+        if value.is_some() {
+            self.len -= 1;
+        }
+        value
+    }
+
+    /// Retain only the [`TopicFilter`]s for which the predicate returns true
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&TopicFilter, &mut V) -> bool,
+    {
+        self.len -= self.root.retain(&mut f);
+    }
+
+    /// Return all [`TopicFilter`]s in the [`TopicTree`] that match the [`TopicName`], along with
+    /// their values
+    #[must_use]
+    pub fn matches(&self, topic_name: &TopicName) -> Vec<(&TopicFilter, &V)> {
+        let mut matches = vec![];
+        self.root.collect_matches(&topic_name.levels, &mut matches);
+        matches
+    }
+}
+
+/// Return the levels of a [`TopicFilter`] that are matched against topic names, i.e. without the
+/// share name of a shared subscription
+fn match_levels(topic_filter: &TopicFilter) -> &[String] {
+    if is_shared_sub(&topic_filter.topic_filter) {
+        &topic_filter.levels[2..]
+    } else {
+        &topic_filter.levels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use test_case::test_case;
+
+    use super::*;
+
+    const FILTERS: [&str; 12] = [
+        "sport/tennis/player1",
+        "sport/tennis/+",
+        "sport/+/player1",
+        "sport/#",
+        "sport/tennis/player1/#",
+        "+/+/+",
+        "#",
+        "finance/bonds/banker1",
+        "$share/group1/sport/tennis/player1",
+        "$share/group2/sport/#",
+        "/sport",
+        "sport/",
+    ];
+
+    fn create_tree() -> TopicTree<usize> {
+        let mut tree = TopicTree::new();
+        for (i, topic_filter) in FILTERS.iter().enumerate() {
+            tree.insert(TopicFilter::from_str(topic_filter).unwrap(), i);
+        }
+        tree
+    }
+
+    #[test_case("sport/tennis/player1"; "Exact")]
+    #[test_case("sport/tennis/player2"; "Single-level wildcard")]
+    #[test_case("sport/tennis/player1/ranking"; "Multi-level wildcard")]
+    #[test_case("sport"; "Single level")]
+    #[test_case("sport/"; "Zero-length level at end")]
+    #[test_case("/sport"; "Zero-length level at start")]
+    #[test_case("finance/bonds/banker1"; "Other subtree")]
+    #[test_case("finance"; "Only multi-level wildcard")]
+    fn matches_same_as_topic_filter(topic_name: &str) {
+        let tree = create_tree();
+        let topic_name = TopicName::from_str(topic_name).unwrap();
+
+        let mut expected: Vec<&str> = FILTERS
+            .iter()
+            .copied()
+            .filter(|f| {
+                TopicFilter::from_str(f)
+                    .unwrap()
+                    .matches_topic_name(&topic_name)
+            })
+            .collect();
+        let mut actual: Vec<&str> = tree
+            .matches(&topic_name)
+            .into_iter()
+            .map(|(f, v)| {
+                assert_eq!(FILTERS[*v], f.as_str());
+                f.as_str()
+            })
+            .collect();
+        expected.sort_unstable();
+        actual.sort_unstable();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut tree = create_tree();
+        assert_eq!(tree.len(), FILTERS.len());
+
+        // Shared subscriptions are distinct from the topic filter they share
+        let topic_filter = TopicFilter::from_str("sport/tennis/player1").unwrap();
+        let shared_topic_filter =
+            TopicFilter::from_str("$share/group1/sport/tennis/player1").unwrap();
+        assert_eq!(tree.get(&topic_filter), Some(&0));
+        assert_eq!(tree.get(&shared_topic_filter), Some(&8));
+
+        // Inserting an existing topic filter replaces the value
+        assert_eq!(tree.insert(topic_filter.clone(), 100), Some(0));
+        assert_eq!(tree.len(), FILTERS.len());
+        *tree.get_mut(&topic_filter).unwrap() += 1;
+        assert_eq!(tree.get(&topic_filter), Some(&101));
+        assert_eq!(*tree.get_or_insert_with(&topic_filter, || 0), 101);
+
+        // Removing a topic filter leaves the shared subscription
+        assert_eq!(tree.remove(&topic_filter), Some(101));
+        assert_eq!(tree.remove(&topic_filter), None);
+        assert!(!tree.contains_key(&topic_filter));
+        assert!(tree.contains_key(&shared_topic_filter));
+        assert_eq!(tree.len(), FILTERS.len() - 1);
+
+        // A topic filter that is not present can be inserted with a default
+        assert_eq!(*tree.get_or_insert_with(&topic_filter, || 7), 7);
+        assert_eq!(tree.len(), FILTERS.len());
+    }
+
+    #[test]
+    fn retain_prunes_nodes() {
+        let mut tree = create_tree();
+        tree.retain(|f, _| !f.as_str().starts_with("sport") && !f.as_str().starts_with("$share"));
+        assert_eq!(tree.len(), 4);
+        assert!(!tree.root.children.contains_key("sport"));
+
+        tree.retain(|_, _| false);
+        assert!(tree.is_empty());
+        assert!(tree.root.is_empty());
+    }
+}
@@ -0,0 +1,818 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::time::Duration;
+
+use test_case::test_case;
+
+use azure_iot_operations_mqtt::control_packet::{PublishProperties, QoS, SubscribeOptionsBuilder};
+use azure_iot_operations_mqtt::interface::{ManagedClient, MqttPubSub, PubReceiver};
+use azure_iot_operations_mqtt::session::{Session, SessionEvent, SessionOptionsBuilder};
+use azure_iot_operations_mqtt::test_broker::TestBroker;
+
+fn setup_test(broker: &TestBroker, client_id: &str) -> Session {
+    let _ = env_logger::Builder::new()
+        .filter_level(log::LevelFilter::max())
+        .format_timestamp(None)
+        .filter_module("rumqttc", log::LevelFilter::Warn)
+        .filter_module("azure_iot_operations", log::LevelFilter::Warn)
+        .try_init();
+
+    let connection_settings = broker
+        .connection_settings_builder(client_id)
+        .keep_alive(Duration::from_secs(5))
+        .build()
+        .unwrap();
+    let session_options = SessionOptionsBuilder::default()
+        .connection_settings(connection_settings)
+        .build()
+        .unwrap();
+    Session::new(session_options).unwrap()
+}
+
+#[test_case(QoS::AtMostOnce; "QoS 0")]
+#[test_case(QoS::AtLeastOnce; "QoS 1")]
+#[tokio::test]
+async fn test_simple_recv(qos: QoS) {
+    let broker = TestBroker::start().await.unwrap();
+    let session = setup_test(&broker, "test_broker_simple_recv");
+    let exit_handle = session.create_exit_handle();
+    let managed_client = session.create_managed_client();
+
+    let topic = "mqtt/test/simple_recv";
+    let payload = "simple_recv_test_payload";
+
+    let test = async move {
+        let mut receiver = managed_client.create_filtered_pub_receiver(topic).unwrap();
+        managed_client
+            .subscribe(topic, qos)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        managed_client
+            .publish(topic, qos, false, payload)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let publish = receiver.recv().await.unwrap();
+        assert_eq!(publish.payload, payload.as_bytes());
+        assert_eq!(publish.qos, qos);
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_retained_message() {
+    let broker = TestBroker::start().await.unwrap();
+    let session = setup_test(&broker, "test_broker_retained_message");
+    let exit_handle = session.create_exit_handle();
+    let managed_client = session.create_managed_client();
+
+    let topic = "mqtt/test/retained";
+    let payload = "retained_test_payload";
+
+    let test = async move {
+        // Publish a retained message before subscribing
+        managed_client
+            .publish(topic, QoS::AtLeastOnce, true, payload)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let mut receiver = managed_client.create_filtered_pub_receiver(topic).unwrap();
+        managed_client
+            .subscribe(topic, QoS::AtLeastOnce)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let publish = receiver.recv().await.unwrap();
+        assert_eq!(publish.payload, payload.as_bytes());
+        assert!(publish.retain);
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_subscription_identifier_routing() {
+    let broker = TestBroker::start().await.unwrap();
+    let session = setup_test(&broker, "test_broker_subscription_identifier_routing");
+    let exit_handle = session.create_exit_handle();
+    let managed_client = session.create_managed_client();
+
+    let test = async move {
+        // Two subscriptions with overlapping topic filters, each with its own receiver
+        let mut receiver1 = managed_client.create_identified_pub_receiver(1).unwrap();
+        let mut receiver2 = managed_client.create_identified_pub_receiver(2).unwrap();
+        for (topic_filter, subscription_identifier) in
+            [("mqtt/test/ids/#", 1), ("mqtt/test/ids/+/sensor", 2)]
+        {
+            let options = SubscribeOptionsBuilder::default()
+                .subscription_identifier(subscription_identifier)
+                .build()
+                .unwrap();
+            managed_client
+                .subscribe_with_options(topic_filter, options)
+                .await
+                .unwrap()
+                .await
+                .unwrap();
+        }
+
+        // Publish matching only the first subscription is only received by its receiver
+        managed_client
+            .publish(
+                "mqtt/test/ids/a/other",
+                QoS::AtLeastOnce,
+                false,
+                "publish 1",
+            )
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        // Publish matching both subscriptions is received by both receivers
+        managed_client
+            .publish(
+                "mqtt/test/ids/a/sensor",
+                QoS::AtLeastOnce,
+                false,
+                "publish 2",
+            )
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            receiver1.recv().await.unwrap().payload,
+            "publish 1".as_bytes()
+        );
+        assert_eq!(
+            receiver1.recv().await.unwrap().payload,
+            "publish 2".as_bytes()
+        );
+        assert_eq!(
+            receiver2.recv().await.unwrap().payload,
+            "publish 2".as_bytes()
+        );
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_subscribe_no_local() {
+    let broker = TestBroker::start().await.unwrap();
+    let session = setup_test(&broker, "test_broker_subscribe_no_local");
+    let exit_handle = session.create_exit_handle();
+    let managed_client = session.create_managed_client();
+
+    let topic = "mqtt/test/no_local";
+
+    let test = async move {
+        let mut receiver = managed_client.create_filtered_pub_receiver(topic).unwrap();
+        let options = SubscribeOptionsBuilder::default()
+            .no_local(true)
+            .build()
+            .unwrap();
+        managed_client
+            .subscribe_with_options(topic, options)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        managed_client
+            .publish(topic, QoS::AtLeastOnce, false, "own publish")
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        // The publish made by this client is not delivered back to it
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), receiver.recv())
+                .await
+                .is_err()
+        );
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_shared_subscription() {
+    let broker = TestBroker::start().await.unwrap();
+    let session1 = setup_test(&broker, "test_broker_shared_subscription_1");
+    let session2 = setup_test(&broker, "test_broker_shared_subscription_2");
+    let exit_handle1 = session1.create_exit_handle();
+    let exit_handle2 = session2.create_exit_handle();
+    let managed_client1 = session1.create_managed_client();
+    let managed_client2 = session2.create_managed_client();
+
+    let topic = "mqtt/test/shared";
+    let shared_topic_filter = "$share/group1/mqtt/test/shared";
+
+    let test = async move {
+        let mut receiver1 = managed_client1
+            .create_filtered_pub_receiver(shared_topic_filter)
+            .unwrap();
+        let mut receiver2 = managed_client2
+            .create_filtered_pub_receiver(shared_topic_filter)
+            .unwrap();
+        for managed_client in [&managed_client1, &managed_client2] {
+            managed_client
+                .subscribe(shared_topic_filter, QoS::AtLeastOnce)
+                .await
+                .unwrap()
+                .await
+                .unwrap();
+        }
+
+        for i in 0..4 {
+            managed_client1
+                .publish(topic, QoS::AtLeastOnce, false, format!("payload{i}"))
+                .await
+                .unwrap()
+                .await
+                .unwrap();
+        }
+
+        // Each publish is delivered to exactly one member of the share group, alternating
+        for i in [0, 2] {
+            assert_eq!(
+                receiver1.recv().await.unwrap().payload,
+                format!("payload{i}").as_bytes()
+            );
+        }
+        for i in [1, 3] {
+            assert_eq!(
+                receiver2.recv().await.unwrap().payload,
+                format!("payload{i}").as_bytes()
+            );
+        }
+        for receiver in [&mut receiver1, &mut receiver2] {
+            assert!(
+                tokio::time::timeout(Duration::from_secs(1), receiver.recv())
+                    .await
+                    .is_err()
+            );
+        }
+
+        exit_handle1.try_exit().await.map_err(|e| e.to_string())?;
+        exit_handle2.try_exit().await.map_err(|e| e.to_string())
+    };
+
+    assert!(
+        tokio::try_join!(
+            test,
+            async move { session1.run().await.map_err(|e| e.to_string()) },
+            async move { session2.run().await.map_err(|e| e.to_string()) }
+        )
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_user_properties() {
+    let broker = TestBroker::start().await.unwrap();
+    let session = setup_test(&broker, "test_broker_user_properties");
+    let exit_handle = session.create_exit_handle();
+    let managed_client = session.create_managed_client();
+
+    let topic = "mqtt/test/user_properties";
+    let user_properties = vec![
+        ("key1".to_string(), "value1".to_string()),
+        ("key2".to_string(), "value2".to_string()),
+        // Duplicate keys are allowed, and their order is preserved
+        ("key1".to_string(), "value3".to_string()),
+    ];
+
+    let test = async move {
+        let mut receiver = managed_client.create_filtered_pub_receiver(topic).unwrap();
+        managed_client
+            .subscribe(topic, QoS::AtLeastOnce)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let properties = PublishProperties {
+            user_properties: user_properties.clone(),
+            content_type: Some("text/plain".to_string()),
+            ..Default::default()
+        };
+        managed_client
+            .publish_with_properties(topic, QoS::AtLeastOnce, false, "payload", properties)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        let properties = receiver.recv().await.unwrap().properties.unwrap();
+        assert_eq!(properties.user_properties, user_properties);
+        assert_eq!(properties.content_type, Some("text/plain".to_string()));
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_session_resumed_after_connection_drop() {
+    let client_id = "test_broker_session_resumed";
+    let broker = TestBroker::start().await.unwrap();
+    let session = setup_test(&broker, client_id);
+    let exit_handle = session.create_exit_handle();
+    let monitor = session.create_connection_monitor();
+    let managed_client = session.create_managed_client();
+
+    let topic = "mqtt/test/session_resumed";
+    let payload = "session_resumed_test_payload";
+
+    let test = async move {
+        let mut receiver = managed_client.create_filtered_pub_receiver(topic).unwrap();
+        managed_client
+            .subscribe(topic, QoS::AtLeastOnce)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        // Drop the connection, and wait for the session to reconnect
+        assert!(broker.disconnect_client(client_id));
+        monitor.disconnected().await;
+        monitor.connected().await;
+        assert!(broker.is_client_connected(client_id));
+
+        // Subscription is still present on the resumed session
+        managed_client
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let publish = receiver.recv().await.unwrap();
+        assert_eq!(publish.payload, payload.as_bytes());
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_session_lost() {
+    let client_id = "test_broker_session_lost";
+    let broker = TestBroker::start().await.unwrap();
+    let session = setup_test(&broker, client_id);
+    let monitor = session.create_connection_monitor();
+
+    let test = async move {
+        monitor.connected().await;
+        // Discarding the session on the broker causes the session to be lost on reconnect
+        assert!(broker.discard_session(client_id));
+        // Keep the broker alive until the session ends
+        std::future::pending::<()>().await;
+    };
+
+    tokio::select! {
+        () = test => unreachable!(),
+        result = session.run() => assert!(result.is_err()),
+    }
+}
+
+#[test_case(Duration::ZERO, true; "Reconnect before expiry")]
+#[test_case(Duration::from_secs(2), false; "Reconnect after expiry")]
+#[tokio::test]
+async fn test_session_expiry(disconnected: Duration, session_present: bool) {
+    let client_id = "test_broker_session_expiry";
+    let broker = TestBroker::start().await.unwrap();
+    let create_session = || {
+        let connection_settings = broker
+            .connection_settings_builder(client_id)
+            .session_expiry(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        let session_options = SessionOptionsBuilder::default()
+            .connection_settings(connection_settings)
+            .build()
+            .unwrap();
+        Session::new(session_options).unwrap()
+    };
+
+    // Connect with a session that expires 1 second after disconnecting
+    let session = create_session();
+    let exit_handle = session.create_exit_handle();
+    let monitor = session.create_connection_monitor();
+    let test = async move {
+        monitor.connected().await;
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+
+    // NOTE: The session expiry is tracked by the broker in real time
+    tokio::time::sleep(disconnected).await;
+
+    // Reconnect with the same client ID. The session is only present if it has not expired.
+    let session = create_session();
+    let exit_handle = session.create_exit_handle();
+    let mut events = session.create_connection_monitor().events();
+    let test = async move {
+        loop {
+            match events.recv().await.unwrap() {
+                SessionEvent::Connected {
+                    session_present: present,
+                    ..
+                } => {
+                    assert_eq!(present, session_present);
+                    break;
+                }
+                SessionEvent::Exited { .. } => panic!("Session exited before connecting"),
+                _ => {}
+            }
+        }
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_offline_queue_replayed_on_connect() {
+    let broker = TestBroker::start().await.unwrap();
+    let queue_dir = tempfile::TempDir::new().unwrap();
+    let connection_settings = broker
+        .connection_settings_builder("test_broker_offline_queue")
+        .build()
+        .unwrap();
+    let session_options = SessionOptionsBuilder::default()
+        .connection_settings(connection_settings)
+        .offline_queue_dir(queue_dir.path().to_str().unwrap())
+        .build()
+        .unwrap();
+    let session = Session::new(session_options).unwrap();
+    let exit_handle = session.create_exit_handle();
+    let managed_client = session.create_managed_client();
+
+    let topic = "mqtt/test/offline_queue";
+    let mut receiver = managed_client.create_filtered_pub_receiver(topic).unwrap();
+    let sub_ct = managed_client
+        .subscribe(topic, QoS::AtLeastOnce)
+        .await
+        .unwrap();
+
+    // Publish before the Session has connected
+    let mut pub_cts = vec![];
+    for i in 0..3 {
+        pub_cts.push(
+            managed_client
+                .publish(topic, QoS::AtLeastOnce, false, format!("payload{i}"))
+                .await
+                .unwrap(),
+        );
+    }
+    assert_eq!(managed_client.offline_queue_depth(), Some(3));
+
+    let test = async move {
+        sub_ct.await.unwrap();
+        for ct in pub_cts {
+            ct.await.unwrap();
+        }
+        // Queued publishes are delivered in order
+        for i in 0..3 {
+            let publish = receiver.recv().await.unwrap();
+            assert_eq!(publish.payload, format!("payload{i}").as_bytes());
+        }
+        assert_eq!(managed_client.offline_queue_depth(), Some(0));
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_session_events() {
+    let client_id = "test_broker_session_events";
+    let broker = TestBroker::start().await.unwrap();
+    let session = setup_test(&broker, client_id);
+    let exit_handle = session.create_exit_handle();
+    let monitor = session.create_connection_monitor();
+    let mut events = monitor.events();
+
+    let test = async move {
+        assert!(matches!(
+            events.recv().await,
+            Some(SessionEvent::ConnectAttempt {
+                reconnect_attempt: 0
+            })
+        ));
+        assert!(matches!(
+            events.recv().await,
+            Some(SessionEvent::Connected {
+                session_present: false,
+                ..
+            })
+        ));
+
+        // Drop the connection, and wait for the session to reconnect
+        assert!(broker.disconnect_client(client_id));
+        assert!(matches!(
+            events.recv().await,
+            Some(SessionEvent::ConnectionLost { .. })
+        ));
+        assert!(matches!(
+            events.recv().await,
+            Some(SessionEvent::ReconnectScheduled {
+                prev_attempts: 0,
+                ..
+            })
+        ));
+        assert!(matches!(
+            events.recv().await,
+            Some(SessionEvent::ConnectAttempt {
+                reconnect_attempt: 1
+            })
+        ));
+        assert!(matches!(
+            events.recv().await,
+            Some(SessionEvent::Connected {
+                session_present: true,
+                ..
+            })
+        ));
+
+        exit_handle.try_exit().await.map_err(|e| e.to_string())?;
+        // The final event is the exit
+        let mut last_event = None;
+        while let Some(event) = events.recv().await {
+            last_event = Some(event);
+        }
+        assert!(matches!(
+            last_event,
+            Some(SessionEvent::Exited { error: None })
+        ));
+        Ok(())
+    };
+
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_session_lost_recovered() {
+    let client_id = "test_broker_session_recovered";
+    let broker = TestBroker::start().await.unwrap();
+    let connection_settings = broker
+        .connection_settings_builder(client_id)
+        .build()
+        .unwrap();
+    let session_options = SessionOptionsBuilder::default()
+        .connection_settings(connection_settings)
+        .recover_lost_session(true)
+        .build()
+        .unwrap();
+    let session = Session::new(session_options).unwrap();
+    let exit_handle = session.create_exit_handle();
+    let monitor = session.create_connection_monitor();
+    let mut events = monitor.events();
+    let managed_client = session.create_managed_client();
+
+    let topic = "mqtt/test/session_recovered";
+    let payload = "session_recovered_test_payload";
+
+    let test = async move {
+        let mut receiver = managed_client.create_filtered_pub_receiver(topic).unwrap();
+        managed_client
+            .subscribe(topic, QoS::AtLeastOnce)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        // Discarding the session on the broker causes the session to be lost on reconnect
+        assert!(broker.discard_session(client_id));
+        loop {
+            match events.recv().await.unwrap() {
+                SessionEvent::SessionRecovered { resubscribed } => {
+                    assert_eq!(resubscribed, 1);
+                    break;
+                }
+                SessionEvent::Exited { .. } => panic!("Session exited instead of recovering"),
+                _ => {}
+            }
+        }
+        // The subscription was restored on the new session before the event was sent
+        managed_client
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let publish = receiver.recv().await.unwrap();
+        assert_eq!(publish.payload, payload.as_bytes());
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_unix_socket_reconnect() {
+    let client_id = "test_broker_unix_socket";
+    let socket_dir = tempfile::TempDir::new().unwrap();
+    let broker = TestBroker::start_unix(socket_dir.path().join("broker.sock")).unwrap();
+    let session = setup_test(&broker, client_id);
+    let exit_handle = session.create_exit_handle();
+    let monitor = session.create_connection_monitor();
+    let managed_client = session.create_managed_client();
+
+    let topic = "mqtt/test/unix_socket";
+    let payload = "unix_socket_test_payload";
+
+    let test = async move {
+        let mut receiver = managed_client.create_filtered_pub_receiver(topic).unwrap();
+        managed_client
+            .subscribe(topic, QoS::AtLeastOnce)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        // Drop the connection, and wait for the session to reconnect over the socket
+        assert!(broker.disconnect_client(client_id));
+        monitor.disconnected().await;
+        monitor.connected().await;
+
+        managed_client
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let publish = receiver.recv().await.unwrap();
+        assert_eq!(publish.payload, payload.as_bytes());
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_will_published_on_connection_loss() {
+    let client_id = "test_broker_will";
+    let will_topic = "mqtt/test/will";
+    let broker = TestBroker::start().await.unwrap();
+    let connection_settings = broker
+        .connection_settings_builder(client_id)
+        .will_topic(will_topic.to_string())
+        .will_payload("offline")
+        .will_qos(QoS::AtLeastOnce)
+        .build()
+        .unwrap();
+    let session_options = SessionOptionsBuilder::default()
+        .connection_settings(connection_settings)
+        .build()
+        .unwrap();
+    let session = Session::new(session_options).unwrap();
+    let exit_handle = session.create_exit_handle();
+    let monitor = session.create_connection_monitor();
+    let managed_client = session.create_managed_client();
+
+    let test = async move {
+        // Subscribe to our own will topic, so the will is received on the resumed session
+        let mut receiver = managed_client
+            .create_filtered_pub_receiver(will_topic)
+            .unwrap();
+        managed_client
+            .subscribe(will_topic, QoS::AtLeastOnce)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        // Drop the connection without a normal disconnect
+        assert!(broker.disconnect_client(client_id));
+        monitor.disconnected().await;
+        monitor.connected().await;
+
+        let publish = receiver.recv().await.unwrap();
+        assert_eq!(publish.payload, "offline".as_bytes());
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_failover_to_next_endpoint() {
+    let client_id = "test_broker_failover";
+    let broker = TestBroker::start().await.unwrap();
+    // Find a port with no broker listening on it
+    let unused_port = std::net::TcpListener::bind((TestBroker::HOSTNAME, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let connection_settings = broker
+        .connection_settings_builder(client_id)
+        .tcp_port(unused_port)
+        .failover_endpoints(vec![(TestBroker::HOSTNAME.to_string(), broker.port())])
+        .build()
+        .unwrap();
+    let session_options = SessionOptionsBuilder::default()
+        .connection_settings(connection_settings)
+        .build()
+        .unwrap();
+    let session = Session::new(session_options).unwrap();
+    let exit_handle = session.create_exit_handle();
+    let monitor = session.create_connection_monitor();
+
+    let test = async move {
+        // The first endpoint cannot be connected to, so the session fails over to the broker
+        monitor.connected().await;
+        assert!(broker.is_client_connected(client_id));
+        assert_eq!(
+            monitor.connected_endpoint(),
+            Some((TestBroker::HOSTNAME.to_string(), broker.port()))
+        );
+        exit_handle.try_exit().await.map_err(|e| e.to_string())
+    };
+
+    assert!(
+        tokio::try_join!(test, async move {
+            session.run().await.map_err(|e| e.to_string())
+        })
+        .is_ok()
+    );
+}
@@ -1,10 +1,13 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
-use std::{collections::HashMap, fmt::Display, marker::PhantomData, str::FromStr, sync::Arc};
+use std::{
+    collections::HashMap, fmt::Display, marker::PhantomData, num::NonZeroUsize, str::FromStr,
+    sync::Arc,
+};
 
 use azure_iot_operations_mqtt::{
-    control_packet::QoS,
-    interface::{AckToken, ManagedClient, PubReceiver},
+    control_packet::{PubAckReasonCode, QoS},
+    interface::{AckToken, ManagedClient, OverflowPolicy, PubReceiver},
 };
 use chrono::{DateTime, Utc};
 use tokio_util::sync::CancellationToken;
@@ -236,6 +239,17 @@ pub struct Options {
     /// If true, telemetry messages are auto-acknowledged
     #[builder(default = "true")]
     auto_ack: bool,
+    /// If true, telemetry messages that cannot be processed (e.g. because the payload cannot be
+    /// deserialized) are acknowledged with a failure reason code instead of a success reason code
+    #[builder(default = "false")]
+    reject_invalid_messages: bool,
+    /// Maximum number of telemetry messages held before being received.
+    /// If not provided, there is no limit.
+    #[builder(default = "None")]
+    receiver_capacity: Option<usize>,
+    /// Policy for handling incoming telemetry messages when `receiver_capacity` is reached
+    #[builder(default = "OverflowPolicy::Block")]
+    overflow_policy: OverflowPolicy,
     /// Service group ID
     #[allow(unused)]
     #[builder(default = "None")]
@@ -285,6 +299,8 @@ where
     receiver_cancellation_token: CancellationToken,
     // User autoack setting
     auto_ack: bool,
+    // User setting for acking unprocessable messages with a failure reason code
+    reject_invalid_messages: bool,
 }
 
 /// Describes state of receiver
@@ -342,7 +358,30 @@ where
         // Get the telemetry topic
         let telemetry_topic = topic_pattern.as_subscribe_topic();
 
-        let mqtt_receiver = match client.create_filtered_pub_receiver(&telemetry_topic) {
+        let receiver_capacity = receiver_options
+            .receiver_capacity
+            .map(|capacity| {
+                NonZeroUsize::new(capacity).ok_or_else(|| {
+                    AIOProtocolError::new_configuration_invalid_error(
+                        None,
+                        "receiver_options.receiver_capacity",
+                        Value::Integer(0),
+                        Some("receiver_capacity must be greater than 0".to_string()),
+                        None,
+                    )
+                })
+            })
+            .transpose()?;
+
+        let mqtt_receiver = match receiver_capacity {
+            Some(capacity) => client.create_bounded_filtered_pub_receiver(
+                &telemetry_topic,
+                capacity,
+                receiver_options.overflow_policy,
+            ),
+            None => client.create_filtered_pub_receiver(&telemetry_topic),
+        };
+        let mqtt_receiver = match mqtt_receiver {
             Ok(receiver) => receiver,
             Err(e) => {
                 return Err(AIOProtocolError::new_configuration_invalid_error(
@@ -365,6 +404,7 @@ where
             receiver_state: State::New,
             receiver_cancellation_token: CancellationToken::new(),
             auto_ack: receiver_options.auto_ack,
+            reject_invalid_messages: receiver_options.reject_invalid_messages,
         })
     }
 
@@ -460,6 +500,11 @@ where
     /// - Returns [`AIOProtocolError`] on error.
     ///
     /// A received message can be acknowledged via the [`AckToken`] by calling [`AckToken::ack`] or dropping the [`AckToken`].
+    /// A received message can be rejected via the [`AckToken`] by calling [`AckToken::ack_with_reason`] with a failure reason code.
+    ///
+    /// If a message cannot be processed, it is acknowledged (to prevent redelivery) rather than returned.
+    /// If [`reject_invalid_messages`](OptionsBuilder::reject_invalid_messages) is set, the
+    /// acknowledgement has a failure reason code describing why the message could not be processed.
     ///
     /// Will also subscribe to the telemetry topic if not already subscribed.
     ///
@@ -479,17 +524,11 @@ where
         loop {
             match self.mqtt_receiver.recv_manual_ack().await {
                 Some((m, mut ack_token)) => {
-                    // Drop the ack token if the user does not desire it
-                    // TODO: change API around this receive to simplify
-                    if self.auto_ack {
-                        // Replace the token with None (if Some)
-                        ack_token.take();
-                    }
-
                     // Process the received message
                     log::info!("[pkid: {}] Received message", m.pkid);
 
-                    'process_message: {
+                    // Reason code and reason string for rejecting the message if it cannot be processed
+                    let (reason_code, reason_string) = 'process_message: {
                         // Clone properties
 
                         let properties = m.properties.clone();
@@ -533,7 +572,10 @@ where
                                         "[pkid: {}] Unparsable protocol version value provided: {protocol_version}.",
                                         m.pkid
                                     );
-                                    break 'process_message;
+                                    break 'process_message (
+                                        PubAckReasonCode::ImplementationSpecificError,
+                                        "Unparsable protocol version",
+                                    );
                                 }
                             }
                             // Check that the version (or the default version if one isn't provided) is supported
@@ -542,7 +584,10 @@ where
                                     "[pkid: {}] Unsupported Protocol Version '{message_protocol_version}'. Only major protocol versions '{SUPPORTED_PROTOCOL_VERSIONS:?}' are supported.",
                                     m.pkid
                                 );
-                                break 'process_message;
+                                break 'process_message (
+                                    PubAckReasonCode::ImplementationSpecificError,
+                                    "Unsupported protocol version",
+                                );
                             }
 
                             for (key, value) in properties.user_properties {
@@ -556,7 +601,10 @@ where
                                                         "[pkid: {}] Failure updating application HLC against {value}: {e}",
                                                         m.pkid
                                                     );
-                                                    break 'process_message;
+                                                    break 'process_message (
+                                                        PubAckReasonCode::ImplementationSpecificError,
+                                                        "Timestamp could not be applied",
+                                                    );
                                                 }
                                                 timestamp = Some(ts);
                                             }
@@ -565,7 +613,10 @@ where
                                                     "[pkid: {}] Invalid timestamp {value}: {e}",
                                                     m.pkid
                                                 );
-                                                break 'process_message;
+                                                break 'process_message (
+                                                    PubAckReasonCode::ImplementationSpecificError,
+                                                    "Invalid timestamp",
+                                                );
                                             }
                                         }
                                     }
@@ -597,7 +648,10 @@ where
                                     "[pkid: {}] Topic deserialization error: {e:?}",
                                     m.pkid
                                 );
-                                break 'process_message;
+                                break 'process_message (
+                                    PubAckReasonCode::TopicNameInvalid,
+                                    "Invalid topic",
+                                );
                             }
                         };
 
@@ -615,7 +669,10 @@ where
                                     "[pkid: {}] Payload deserialization error: {e:?}",
                                     m.pkid
                                 );
-                                break 'process_message;
+                                break 'process_message (
+                                    PubAckReasonCode::PayloadFormatInvalid,
+                                    "Payload could not be deserialized",
+                                );
                             }
                         };
 
@@ -629,18 +686,40 @@ where
                             topic_tokens,
                         };
 
+                        // Drop the ack token if the user does not desire it
+                        // NOTE: This is only done once the message has been processed, so that a
+                        // message that cannot be processed can still be rejected.
+                        // TODO: change API around this receive to simplify
+                        if self.auto_ack {
+                            // Replace the token with None (if Some)
+                            ack_token.take();
+                        }
+
                         return Some(Ok((telemetry_message, ack_token)));
-                    }
+                    };
 
                     // Occurs on an error processing the message, ack to prevent redelivery
                     if let Some(ack_token) = ack_token {
+                        let reject = self.reject_invalid_messages;
                         tokio::spawn({
                             let receiver_cancellation_token_clone =
                                 self.receiver_cancellation_token.clone();
                             async move {
+                                let ack_f = async move {
+                                    if reject {
+                                        ack_token
+                                            .ack_with_reason(
+                                                reason_code,
+                                                Some(reason_string.to_string()),
+                                            )
+                                            .await
+                                    } else {
+                                        ack_token.ack().await
+                                    }
+                                };
                                 tokio::select! {
                                     () = receiver_cancellation_token_clone.cancelled() => { /* Received loop cancelled */ },
-                                    ack_res = ack_token.ack() => {
+                                    ack_res = ack_f => {
                                         match ack_res {
                                             Ok(_) => { /* Success */ }
                                             Err(e) => {
@@ -755,6 +834,8 @@ mod tests {
             .topic_pattern("test/{telemetryName}/receiver")
             .topic_namespace("test_namespace")
             .topic_token_map(create_topic_tokens())
+            .auto_ack(false)
+            .reject_invalid_messages(true)
             .build()
             .unwrap();
 
@@ -798,6 +879,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_new_zero_receiver_capacity() {
+        let session = get_session();
+        let receiver_options = OptionsBuilder::default()
+            .topic_pattern("test/receiver")
+            .receiver_capacity(0usize)
+            .build()
+            .unwrap();
+
+        let result: Result<Receiver<MockPayload, _>, _> = Receiver::new(
+            ApplicationContextBuilder::default().build().unwrap(),
+            session.create_managed_client(),
+            receiver_options,
+        );
+        match result {
+            Ok(_) => panic!("Expected error"),
+            Err(e) => {
+                assert_eq!(e.kind, AIOProtocolErrorKind::ConfigurationInvalid);
+                assert_eq!(
+                    e.property_name,
+                    Some("receiver_options.receiver_capacity".to_string())
+                );
+                assert_eq!(e.property_value, Some(Value::Integer(0)));
+            }
+        }
+    }
+
     #[tokio::test]
     async fn test_shutdown_without_subscribe() {
         let session = get_session();
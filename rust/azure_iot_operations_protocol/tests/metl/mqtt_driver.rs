@@ -3,7 +3,8 @@
 
 use async_trait::async_trait;
 use azure_iot_operations_mqtt::control_packet::{
-    AuthProperties, Publish, PublishProperties, QoS, SubscribeProperties, UnsubscribeProperties,
+    AuthProperties, Publish, PublishProperties, QoS, SubscribeOptions, SubscribeProperties,
+    UnsubscribeProperties,
 };
 use azure_iot_operations_mqtt::error::{
     AckError, DisconnectError, PublishError, ReauthError, SubscribeError, UnsubscribeError,
@@ -166,6 +167,19 @@ impl MqttPubSub for MqttDriver {
         Ok(self.subscribe_with_optional_properties(topic, qos, Some(properties)))
     }
 
+    #[allow(clippy::unused_async)]
+    async fn subscribe_with_options(
+        &self,
+        topic: impl Into<String> + Send,
+        options: SubscribeOptions,
+    ) -> Result<CompletionToken, SubscribeError> {
+        Ok(self.subscribe_with_optional_properties(
+            topic,
+            options.qos(),
+            Some(options.properties()),
+        ))
+    }
+
     #[allow(clippy::unused_async)]
     async fn unsubscribe(
         &self,
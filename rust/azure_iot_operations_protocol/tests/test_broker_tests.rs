@@ -0,0 +1,306 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::time::Duration;
+
+use env_logger::Builder;
+
+use azure_iot_operations_mqtt::{
+    control_packet::QoS,
+    session::{Session, SessionOptionsBuilder},
+    test_broker::TestBroker,
+};
+use azure_iot_operations_protocol::{
+    application::{ApplicationContext, ApplicationContextBuilder},
+    rpc_command, telemetry,
+};
+
+// These tests run end-to-end against the in-process test broker, so unlike the network tests they
+// do not require an external broker.
+// - command invoke/response with custom user data
+// - command requests distributed between executors in a service group (shared subscription)
+// - telemetry send/receive with custom user data and manual ack
+
+/// Create a session connected to the test broker, and an application context for testing
+fn setup_test(broker: &TestBroker, client_id: &str) -> (Session, ApplicationContext) {
+    let _ = Builder::new()
+        .filter_level(log::LevelFilter::max())
+        .format_timestamp(None)
+        .filter_module("rumqttc", log::LevelFilter::Warn)
+        .filter_module("azure_iot_operations", log::LevelFilter::Warn)
+        .try_init();
+
+    let connection_settings = broker
+        .connection_settings_builder(client_id)
+        .keep_alive(Duration::from_secs(5))
+        .clean_start(true)
+        .build()
+        .unwrap();
+    let session_options = SessionOptionsBuilder::default()
+        .connection_settings(connection_settings)
+        .build()
+        .unwrap();
+    let session = Session::new(session_options).unwrap();
+    let application_context = ApplicationContextBuilder::default().build().unwrap();
+    (session, application_context)
+}
+
+/// Tests command invoke/response scenario, with custom user data on the request and response
+#[tokio::test]
+async fn command_invoke_response_test_broker() {
+    let client_id = "command_invoke_response_test_broker-rust";
+    let request_topic = "protocol/tests/test_broker/command";
+    let broker = TestBroker::start().await.unwrap();
+    let (session, application_context) = setup_test(&broker, client_id);
+    let exit_handle = session.create_exit_handle();
+
+    let invoker_options = rpc_command::invoker::OptionsBuilder::default()
+        .request_topic_pattern(request_topic)
+        .response_topic_prefix("response".to_string())
+        .command_name("test_command")
+        .build()
+        .unwrap();
+    let invoker: rpc_command::Invoker<Vec<u8>, Vec<u8>, _> = rpc_command::Invoker::new(
+        application_context.clone(),
+        session.create_managed_client(),
+        invoker_options,
+    )
+    .unwrap();
+    let executor_options = rpc_command::executor::OptionsBuilder::default()
+        .request_topic_pattern(request_topic)
+        .command_name("test_command")
+        .build()
+        .unwrap();
+    let mut executor: rpc_command::Executor<Vec<u8>, Vec<u8>, _> = rpc_command::Executor::new(
+        application_context,
+        session.create_managed_client(),
+        executor_options,
+    )
+    .unwrap();
+
+    let test_task = tokio::task::spawn(async move {
+        let executor_task = tokio::task::spawn(async move {
+            let request = executor.recv().await.unwrap().unwrap();
+            assert_eq!(request.payload, b"request".to_vec());
+            assert_eq!(
+                request.custom_user_data,
+                vec![("requestKey".to_string(), "requestValue".to_string())]
+            );
+            assert_eq!(request.invoker_id, Some(client_id.to_string()));
+            let response = rpc_command::executor::ResponseBuilder::default()
+                .payload(b"response".to_vec())
+                .unwrap()
+                .custom_user_data(vec![(
+                    "responseKey".to_string(),
+                    "responseValue".to_string(),
+                )])
+                .build()
+                .unwrap();
+            assert!(request.complete(response).await.is_ok());
+            assert!(executor.shutdown().await.is_ok());
+        });
+        // The executor subscribes when first receiving, so wait for it before invoking
+        broker.wait_for_subscription(client_id, request_topic).await;
+
+        let request = rpc_command::invoker::RequestBuilder::default()
+            .payload(b"request".to_vec())
+            .unwrap()
+            .custom_user_data(vec![("requestKey".to_string(), "requestValue".to_string())])
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        let response = invoker.invoke(request).await.unwrap();
+        assert_eq!(response.payload, b"response".to_vec());
+        assert_eq!(
+            response.custom_user_data,
+            vec![("responseKey".to_string(), "responseValue".to_string())]
+        );
+        assert!(response.timestamp.is_some());
+
+        assert!(executor_task.await.is_ok());
+        assert!(invoker.shutdown().await.is_ok());
+        exit_handle.try_exit().await.unwrap();
+    });
+
+    assert!(
+        tokio::try_join!(
+            async move { test_task.await.map_err(|e| { e.to_string() }) },
+            async move { session.run().await.map_err(|e| { e.to_string() }) }
+        )
+        .is_ok()
+    );
+}
+
+/// Tests that command requests are distributed between executors in the same service group,
+/// which subscribe with a shared subscription
+#[tokio::test]
+async fn command_service_group_test_broker() {
+    let invoker_id = "command_service_group_test_broker-rust";
+    let executor_ids = [
+        "command_service_group_test_broker-rust-executor1",
+        "command_service_group_test_broker-rust-executor2",
+    ];
+    let request_topic = "protocol/tests/test_broker/service_group";
+    let shared_request_topic = format!("$share/group1/{request_topic}");
+    let broker = TestBroker::start().await.unwrap();
+
+    let (invoker_session, application_context) = setup_test(&broker, invoker_id);
+    let invoker_options = rpc_command::invoker::OptionsBuilder::default()
+        .request_topic_pattern(request_topic)
+        .response_topic_prefix("response".to_string())
+        .command_name("test_command")
+        .build()
+        .unwrap();
+    let invoker: rpc_command::Invoker<Vec<u8>, Vec<u8>, _> = rpc_command::Invoker::new(
+        application_context,
+        invoker_session.create_managed_client(),
+        invoker_options,
+    )
+    .unwrap();
+
+    let mut sessions = vec![invoker_session];
+    let mut executor_tasks = vec![];
+    for executor_id in executor_ids {
+        let (session, application_context) = setup_test(&broker, executor_id);
+        let executor_options = rpc_command::executor::OptionsBuilder::default()
+            .request_topic_pattern(request_topic)
+            .command_name("test_command")
+            .service_group_id("group1")
+            .build()
+            .unwrap();
+        let mut executor: rpc_command::Executor<Vec<u8>, Vec<u8>, _> = rpc_command::Executor::new(
+            application_context,
+            session.create_managed_client(),
+            executor_options,
+        )
+        .unwrap();
+        // Each executor responds to a single request with its own ID
+        executor_tasks.push(tokio::task::spawn(async move {
+            let request = executor.recv().await.unwrap().unwrap();
+            let response = rpc_command::executor::ResponseBuilder::default()
+                .payload(executor_id.as_bytes().to_vec())
+                .unwrap()
+                .build()
+                .unwrap();
+            assert!(request.complete(response).await.is_ok());
+        }));
+        sessions.push(session);
+    }
+    let exit_handles: Vec<_> = sessions.iter().map(Session::create_exit_handle).collect();
+
+    let test_task = tokio::task::spawn(async move {
+        for executor_id in executor_ids {
+            broker
+                .wait_for_subscription(executor_id, &shared_request_topic)
+                .await;
+        }
+
+        // Each request is handled by exactly one executor, so both executors respond once
+        let mut responders = vec![];
+        for _ in 0..2 {
+            let request = rpc_command::invoker::RequestBuilder::default()
+                .payload(b"request".to_vec())
+                .unwrap()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap();
+            let response = invoker.invoke(request).await.unwrap();
+            responders.push(String::from_utf8(response.payload).unwrap());
+        }
+        responders.sort();
+        assert_eq!(responders, executor_ids);
+
+        for executor_task in executor_tasks {
+            assert!(executor_task.await.is_ok());
+        }
+        for exit_handle in exit_handles {
+            exit_handle.try_exit().await.unwrap();
+        }
+    });
+
+    let session_runs = futures::future::try_join_all(
+        sessions
+            .into_iter()
+            .map(|session| async move { session.run().await.map_err(|e| e.to_string()) }),
+    );
+    assert!(
+        tokio::try_join!(
+            async move { test_task.await.map_err(|e| { e.to_string() }) },
+            session_runs
+        )
+        .is_ok()
+    );
+}
+
+/// Tests telemetry send/receive scenario, with custom user data and manual ack
+#[tokio::test]
+async fn telemetry_send_receive_test_broker() {
+    let client_id = "telemetry_send_receive_test_broker-rust";
+    let topic = "protocol/tests/test_broker/telemetry";
+    let broker = TestBroker::start().await.unwrap();
+    let (session, application_context) = setup_test(&broker, client_id);
+    let exit_handle = session.create_exit_handle();
+
+    let sender_options = telemetry::sender::OptionsBuilder::default()
+        .topic_pattern(topic)
+        .build()
+        .unwrap();
+    let sender: telemetry::Sender<Vec<u8>, _> = telemetry::Sender::new(
+        application_context.clone(),
+        session.create_managed_client(),
+        sender_options,
+    )
+    .unwrap();
+    let receiver_options = telemetry::receiver::OptionsBuilder::default()
+        .topic_pattern(topic)
+        .auto_ack(false)
+        .build()
+        .unwrap();
+    let mut receiver: telemetry::Receiver<Vec<u8>, _> = telemetry::Receiver::new(
+        application_context,
+        session.create_managed_client(),
+        receiver_options,
+    )
+    .unwrap();
+
+    let test_task = tokio::task::spawn(async move {
+        let receiver_task = tokio::task::spawn(async move {
+            let (message, ack_token) = receiver.recv().await.unwrap().unwrap();
+            assert_eq!(message.payload, b"telemetry".to_vec());
+            assert_eq!(
+                message.custom_user_data,
+                vec![("telemetryKey".to_string(), "telemetryValue".to_string())]
+            );
+            assert_eq!(message.sender_id, Some(client_id.to_string()));
+            assert!(message.timestamp.is_some());
+            // Manual ack is required for QoS 1
+            assert!(ack_token.unwrap().ack().await.unwrap().await.is_ok());
+            assert!(receiver.shutdown().await.is_ok());
+        });
+        // The receiver subscribes when first receiving, so wait for it before sending
+        broker.wait_for_subscription(client_id, topic).await;
+
+        let message = telemetry::sender::MessageBuilder::default()
+            .payload(b"telemetry".to_vec())
+            .unwrap()
+            .qos(QoS::AtLeastOnce)
+            .custom_user_data(vec![(
+                "telemetryKey".to_string(),
+                "telemetryValue".to_string(),
+            )])
+            .build()
+            .unwrap();
+        assert!(sender.send(message).await.is_ok());
+
+        assert!(receiver_task.await.is_ok());
+        exit_handle.try_exit().await.unwrap();
+    });
+
+    assert!(
+        tokio::try_join!(
+            async move { test_task.await.map_err(|e| { e.to_string() }) },
+            async move { session.run().await.map_err(|e| { e.to_string() }) }
+        )
+        .is_ok()
+    );
+}
@@ -6,6 +6,7 @@
 # - Files MUST NOT have lines with trailing whitespace
 # - Rust sources MUST include the Microsoft copyright header
 # - Rust sources MUST be formatted with `rustfmt`
+# - The MQTT crate MUST build without default features
 # - Rust crate manifest files MUST contain:
 #   ```toml
 #   [lints]
@@ -72,3 +73,10 @@ cargo fmt --verbose --all --check
 # NOTE: Check for unused dependencies.
 cargo machete
 
+# NOTE: Require the MQTT crate to build with only the features that cannot be disabled, so that
+# code gated on optional features (e.g. `config-file`) is not relied on elsewhere.
+cargo check \
+    --manifest-path="${REPOSITORY_ROOT}/rust/azure_iot_operations_mqtt/Cargo.toml" \
+    --no-default-features \
+    --features use-native-tls
+